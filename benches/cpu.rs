@@ -0,0 +1,321 @@
+//! Speed of the CPU core, running instruction mixes and test binaries through `evaluating_next`.
+//! Every benchmark reports millions of instructions per second and the clock speed in MHz that
+//! the emulated CPU would need to keep up, which is 2 MHz for a stock 8080.
+//!
+//!     cargo bench --bench cpu
+//!     cargo bench --bench cpu -- exm
+//!
+//! Arguments filter the benchmarks by name.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use emu_8080::blocks::BlockCache;
+use emu_8080::emulator::{DummyIOHandler, State8080};
+
+const RUNS: usize = 5;
+
+// Clock of a stock 8080
+const STOCK_MHZ: f64 = 2.0;
+
+// Instructions of 8080EXM run, the whole suite takes minutes
+const EXM_SLICE: u64 = 20_000_000;
+
+// Evaluates a copy of `state` until it jumps to 0x0000 or `limit` instructions have run, returning
+// the instructions and cycles run and the time they took
+fn running(state: &State8080, limit: u64) -> (u64, u64, Duration) {
+    let mut state = state.clone();
+    let mut instructions = 0;
+    let mut cycles = 0;
+    let start = Instant::now();
+    while instructions < limit {
+        state = state.evaluating_next::<DummyIOHandler>(None);
+        instructions += 1;
+        cycles += state.last_cycles() as u64;
+        if state.pc == 0 {
+            break;
+        }
+    }
+    let elapsed = start.elapsed();
+    assert!(!state.halted, "benchmark halted at {:#06x}", state.pc);
+    black_box(&state);
+
+    (instructions, cycles, elapsed)
+}
+
+// Same as `running` with the block cache, which starts out empty
+fn running_blocks(state: &State8080, limit: u64, lazy_flags: bool) -> (u64, u64, Duration) {
+    let mut state = state.clone();
+    let mut cache = BlockCache::new().setting_lazy_flags(lazy_flags);
+    let mut instructions = 0;
+    let mut cycles = 0;
+    let start = Instant::now();
+    while instructions < limit {
+        state = state.evaluating_block::<DummyIOHandler>(&mut cache, None);
+        instructions += cache.last_instructions() as u64;
+        cycles += cache.last_cycles() as u64;
+        if state.pc == 0 {
+            break;
+        }
+    }
+    let elapsed = start.elapsed();
+    assert!(!state.halted, "benchmark halted at {:#06x}", state.pc);
+    black_box(&state);
+
+    (instructions, cycles, elapsed)
+}
+
+// Best of a few runs, each of them running the benchmark `repeat` times
+fn measuring(benchmark: &Benchmark) {
+    let state = (benchmark.state)();
+    let mut best = Duration::MAX;
+    let mut instructions = 0;
+    let mut cycles = 0;
+    for _ in 0..RUNS {
+        let mut elapsed = Duration::ZERO;
+        instructions = 0;
+        cycles = 0;
+        for _ in 0..benchmark.repeat {
+            let run = match benchmark.engine {
+                Engine::Instructions => running(&state, benchmark.limit),
+                Engine::Blocks => running_blocks(&state, benchmark.limit, false),
+                Engine::LazyFlags => running_blocks(&state, benchmark.limit, true),
+            };
+            instructions += run.0;
+            cycles += run.1;
+            elapsed += run.2;
+        }
+        best = best.min(elapsed);
+    }
+
+    let seconds = best.as_secs_f64();
+    let mips = instructions as f64 / seconds / 1_000_000.0;
+    let mhz = cycles as f64 / seconds / 1_000_000.0;
+    println!(
+        "{:<16} {:>8.1} MIPS {:>8.1} MHz ({:.0}x a {} MHz 8080)",
+        benchmark.name,
+        mips,
+        mhz,
+        mhz / STOCK_MHZ,
+        STOCK_MHZ
+    );
+}
+
+fn loop_state(program: &[u8]) -> State8080 {
+    let mut state = State8080::new().loading_buffer_into_memory_at(program.to_vec(), 0x100);
+    state.pc = 0x100;
+
+    state
+}
+
+// A CP/M test binary, returning to 0x0000 when it is done
+fn suite_state(path: &str) -> State8080 {
+    let mut state = State8080::new()
+        .loading_file_into_memory_at(path, 0x0100)
+        .setting_memory_at(0xC9, 0x0005);
+    state.pc = 0x100;
+
+    state
+}
+
+// Moves, arithmetic, stack operations and branches
+const MIX: [u8; 19] = [
+    0x21, 0x00, 0x20, // LXI H,0x2000
+    0x7e, // MOV A,M
+    0x80, // ADD B
+    0x77, // MOV M,A
+    0x2c, // INR L
+    0x04, // INR B
+    0xa9, // XRA C
+    0x17, // RAL
+    0x4f, // MOV C,A
+    0xc5, // PUSH B
+    0xc1, // POP B
+    0xc2, 0x03, 0x01, // JNZ 0x0103
+    0xc3, 0x03, 0x01, // JMP 0x0103
+];
+
+// Arithmetic and logic only, every instruction writing flags
+const ALU: [u8; 21] = [
+    0x06, 0x35, // MVI B,0x35
+    0x80, // ADD B
+    0x89, // ADC C
+    0x92, // SUB D
+    0x9b, // SBB E
+    0xa4, // ANA H
+    0xad, // XRA L
+    0xb0, // ORA B
+    0xb9, // CMP C
+    0xc6, 0x17, // ADI 0x17
+    0xd6, 0x05, // SUI 0x05
+    0x27, // DAA
+    0x0c, // INR C
+    0x15, // DCR D
+    0x07, // RLC
+    0xc3, 0x02, 0x01, // JMP 0x0102
+];
+
+// Loads, stores and stack operations
+const MEMORY: [u8; 36] = [
+    0x31, 0x00, 0x40, // LXI SP,0x4000
+    0x21, 0x00, 0x20, // LXI H,0x2000
+    0x11, 0x00, 0x30, // LXI D,0x3000
+    0x77, // MOV M,A
+    0x1a, // LDAX D
+    0x12, // STAX D
+    0x32, 0x00, 0x21, // STA 0x2100
+    0x3a, 0x01, 0x21, // LDA 0x2101
+    0x22, 0x00, 0x22, // SHLD 0x2200
+    0x2a, 0x00, 0x22, // LHLD 0x2200
+    0xe5, // PUSH H
+    0xe3, // XTHL
+    0xe1, // POP H
+    0x23, // INX H
+    0x13, // INX D
+    0x26, 0x20, // MVI H,0x20
+    0x16, 0x30, // MVI D,0x30
+    0xc3, 0x09, 0x01, // JMP 0x0109
+];
+
+// Calls, returns and conditional jumps
+const BRANCHES: [u8; 26] = [
+    0x31, 0x00, 0x40, // LXI SP,0x4000
+    0x04, // INR B
+    0xcd, 0x10, 0x01, // CALL 0x0110
+    0xca, 0x03, 0x01, // JZ 0x0103
+    0xd2, 0x03, 0x01, // JNC 0x0103
+    0xc3, 0x03, 0x01, // JMP 0x0103
+    0x0d, // DCR C
+    0xcc, 0x18, 0x01, // CZ 0x0118
+    0xc0, // RNZ
+    0xc9, // RET
+    0x00, 0x00, // NOP; NOP
+    0xb7, // ORA A
+    0xc9, // RET
+];
+
+enum Engine {
+    // `evaluating_next`
+    Instructions,
+    // `evaluating_block`
+    Blocks,
+    // `evaluating_block` with lazy flags
+    LazyFlags,
+}
+
+struct Benchmark {
+    name: &'static str,
+    state: fn() -> State8080,
+    // Instructions run at most, and how many times the benchmark is run per measurement
+    limit: u64,
+    repeat: u32,
+    engine: Engine,
+}
+
+const BENCHMARKS: [Benchmark; 13] = [
+    Benchmark {
+        name: "mix",
+        state: || loop_state(&MIX),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::Instructions,
+    },
+    Benchmark {
+        name: "alu",
+        state: || loop_state(&ALU),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::Instructions,
+    },
+    Benchmark {
+        name: "memory",
+        state: || loop_state(&MEMORY),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::Instructions,
+    },
+    Benchmark {
+        name: "branches",
+        state: || loop_state(&BRANCHES),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::Instructions,
+    },
+    Benchmark {
+        name: "8080pre",
+        state: || suite_state("./resources/cpu_tests/8080PRE.COM"),
+        limit: u64::MAX,
+        repeat: 1000,
+        engine: Engine::Instructions,
+    },
+    Benchmark {
+        name: "tst8080",
+        state: || suite_state("./resources/cpu_tests/TST8080.COM"),
+        limit: u64::MAX,
+        repeat: 1000,
+        engine: Engine::Instructions,
+    },
+    Benchmark {
+        name: "8080exm",
+        state: || suite_state("./resources/cpu_tests/8080EXM.COM"),
+        limit: EXM_SLICE,
+        repeat: 1,
+        engine: Engine::Instructions,
+    },
+    Benchmark {
+        name: "alu blocks",
+        state: || loop_state(&ALU),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::Blocks,
+    },
+    Benchmark {
+        name: "mix blocks",
+        state: || loop_state(&MIX),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::Blocks,
+    },
+    Benchmark {
+        name: "8080exm blocks",
+        state: || suite_state("./resources/cpu_tests/8080EXM.COM"),
+        limit: EXM_SLICE,
+        repeat: 1,
+        engine: Engine::Blocks,
+    },
+    Benchmark {
+        name: "alu lazy",
+        state: || loop_state(&ALU),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::LazyFlags,
+    },
+    Benchmark {
+        name: "mix lazy",
+        state: || loop_state(&MIX),
+        limit: 5_000_000,
+        repeat: 1,
+        engine: Engine::LazyFlags,
+    },
+    Benchmark {
+        name: "8080exm lazy",
+        state: || suite_state("./resources/cpu_tests/8080EXM.COM"),
+        limit: EXM_SLICE,
+        repeat: 1,
+        engine: Engine::LazyFlags,
+    },
+];
+
+fn main() {
+    let filters: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+
+    for benchmark in BENCHMARKS.iter() {
+        let name = benchmark.name;
+        if filters.is_empty() || filters.iter().any(|filter| name.contains(filter.as_str())) {
+            measuring(benchmark);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Decoding throughput, on its own and in the disassembler. The `cpu` benchmark measures
+//! evaluating instructions.
+//!
+//!     cargo bench --bench decode
+
+use std::convert::TryFrom;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use emu_8080::disassembler::{self, Instruction};
+use emu_8080::emulator::CpuVariant;
+
+const RUNS: usize = 5;
+
+// Best of a few runs of `f`, which returns how many items it processed
+fn measuring<F: FnMut() -> u64>(name: &str, unit: &str, mut f: F) {
+    f();
+    let mut best = Duration::MAX;
+    let mut items = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        items = f();
+        best = best.min(start.elapsed());
+    }
+
+    let rate = items as f64 / best.as_secs_f64() / 1_000_000.0;
+    println!("{:<16} {:>8.1} M {}/s", name, rate, unit);
+}
+
+// Every opcode decoded, with its size and cycles
+fn decoding() -> u64 {
+    let mut total = 0u64;
+    for _ in 0..10_000 {
+        for op_code in 0..=255u8 {
+            if let Ok(instruction) = Instruction::try_from(black_box(op_code)) {
+                total += instruction.size() as u64 + instruction.cycles() as u64;
+            }
+        }
+    }
+    black_box(total);
+
+    10_000 * 256
+}
+
+fn disassembling(memory: &[u8]) -> u64 {
+    let mut count = 0;
+    let mut address = 0usize;
+    while address < memory.len() {
+        let (text, size) =
+            disassembler::disassembling(memory, address as u16, CpuVariant::Intel8080);
+        black_box(text);
+        address += size as usize;
+        count += 1;
+    }
+
+    count
+}
+
+fn main() {
+    // Pseudo-random bytes, so every opcode shows up
+    let mut seed = 1u32;
+    let memory: Vec<u8> = (0..0x10000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+
+    measuring("decode", "opcodes", decoding);
+    measuring("disassemble", "instructions", || disassembling(&memory));
+}
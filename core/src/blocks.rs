@@ -0,0 +1,273 @@
+//! Cached basic-block execution, an optional faster way of running the 8080 and 8085 cores.
+//!
+//! `State8080::evaluating_block` decodes the straight-line run of instructions at PC once, up to
+//! and including the first jump, call, return, RST, HLT, EI, DI, IN, OUT or SIM, and caches it
+//! in a `BlockCache`. Later visits run the cached instructions back to back without fetching and
+//! decoding them again. An instruction writing to memory that cached code was decoded from drops
+//! every block on that 256-byte page and ends the block it is part of, so self-modifying code
+//! runs as it does with `evaluating_next`.
+//!
+//! Interrupts and HLT are only checked between blocks. The instructions that can make an
+//! interrupt pending end blocks, so results are the same as calling `evaluating_next` once per
+//! instruction. Interrupts raised from outside the CPU, through `setting_interrupt_pin` or
+//! `acknowledging_interrupt`, are seen when the block is done.
+//!
+//! With `BlockCache::setting_lazy_flags`, the flags of arithmetic and logical instructions are
+//! only worked out when an instruction reads them, such as a conditional jump or PUSH PSW, and
+//! at the end of the block. Flags that a later instruction of the block sets again are never
+//! worked out at all. `cc` is the same as without lazy flags whenever a block is done, and IO
+//! handlers see it up to date too.
+//!
+//! Memory changed other than by instructions, such as a new program loaded into `memory`, has to
+//! be reported with `BlockCache::invalidate` or `BlockCache::clear`.
+//!
+//! ```
+//! use emu_8080_core::blocks::BlockCache;
+//! use emu_8080_core::emulator::{DummyIOHandler, State8080};
+//!
+//! // MVI A,0x42; INR A; HLT
+//! let mut state = State8080::new().loading_buffer_into_memory_at(vec![0x3e, 0x42, 0x3c, 0x76], 0);
+//! let mut cache = BlockCache::new();
+//! state = state.evaluating_block::<DummyIOHandler>(&mut cache, None);
+//!
+//! assert_eq!((state.a, state.halted), (0x43, true));
+//! assert_eq!((cache.last_instructions(), cache.last_cycles()), (3, 7 + 5 + 7));
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::disassembler::{Decoded, Instruction, Instruction8085, DECODE, DECODE_8085};
+use crate::emulator::{CpuVariant, IOHandler, PendingFlags, State8080};
+
+// Longest block decoded, so a long run of straight-line code doesn't delay interrupts for long
+const MAX_BLOCK_LEN: usize = 64;
+
+const PAGE_COUNT: usize = 0x100;
+
+/// An instruction of a cached block.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CachedInstruction {
+    Intel8080(Decoded<Instruction>),
+    Intel8085(Decoded<Instruction8085>),
+}
+
+// Jumps, calls, returns and RST, then the instructions that can change what interrupts are
+// pending or let the outside world in
+fn ends_block(op_code: u8) -> bool {
+    matches!(op_code & 0xc7, 0xc0 | 0xc2 | 0xc4 | 0xc7)
+        || matches!(
+            op_code,
+            // JMP, RET, CALL, PCHL
+            0xc3 | 0xc9 | 0xcd | 0xe9
+            // HLT, EI, DI, IN, OUT
+            | 0x76 | 0xfb | 0xf3 | 0xdb | 0xd3
+            // 8085 SIM, RSTV, JNK and JK
+            | 0x30 | 0xcb | 0xdd | 0xfd
+        )
+}
+
+fn decoding(memory: &[u8], address: u16, variant: CpuVariant) -> Option<(CachedInstruction, u8)> {
+    let op_code = memory[address as usize] as usize;
+    if variant == CpuVariant::Intel8085 {
+        if let Some(decoded) = DECODE_8085[op_code] {
+            return Some((CachedInstruction::Intel8085(decoded), decoded.size));
+        }
+    }
+
+    DECODE[op_code].map(|decoded| (CachedInstruction::Intel8080(decoded), decoded.size))
+}
+
+#[derive(Clone)]
+struct CachedBlock {
+    instructions: Box<[CachedInstruction]>,
+    // Page of the block's last byte, the one after the first if it crosses a page boundary
+    last_page: usize,
+}
+
+/// Blocks decoded from memory, keyed by the address they start at.
+pub struct BlockCache {
+    variant: CpuVariant,
+    blocks: Vec<Option<CachedBlock>>,
+    // Start of every block with code on each page
+    pages: Vec<Vec<u16>>,
+    // Bytes that cached instructions were decoded from
+    code: Vec<bool>,
+    lazy_flags: bool,
+    last_instructions: u32,
+    last_cycles: u32,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache {
+            variant: CpuVariant::Intel8080,
+            blocks: vec![None; 0x10000],
+            pages: vec![Vec::new(); PAGE_COUNT],
+            code: vec![false; 0x10000],
+            lazy_flags: false,
+            last_instructions: 0,
+            last_cycles: 0,
+        }
+    }
+
+    /// Leaves the flags of arithmetic and logical instructions to be worked out when they are
+    /// read, see the module documentation. Off by default.
+    pub fn setting_lazy_flags(self, lazy_flags: bool) -> Self {
+        BlockCache { lazy_flags, ..self }
+    }
+
+    /// Instructions evaluated by the last `evaluating_block`.
+    pub fn last_instructions(&self) -> u32 {
+        self.last_instructions
+    }
+
+    /// Cycles taken by the last `evaluating_block`.
+    pub fn last_cycles(&self) -> u32 {
+        self.last_cycles
+    }
+
+    /// Number of blocks cached.
+    pub fn len(&self) -> usize {
+        self.blocks.iter().filter(|block| block.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every block with code on the page of `address`. Needed after changing memory other
+    /// than by evaluating instructions.
+    pub fn invalidate(&mut self, address: u16) {
+        let page = (address >> 8) as usize;
+        for start in core::mem::take(&mut self.pages[page]) {
+            if let Some(block) = self.blocks[start as usize].take() {
+                for other in (start as usize >> 8)..=block.last_page {
+                    if other != page {
+                        self.pages[other].retain(|&other_start| other_start != start);
+                    }
+                }
+            }
+        }
+        // Blocks crossing into the neighbouring pages leave their bytes marked there, which only
+        // costs an extra invalidation
+        let first = page << 8;
+        self.code[first..first + 0x100].fill(false);
+    }
+
+    /// Drops every block.
+    pub fn clear(&mut self) {
+        for page in 0..PAGE_COUNT {
+            self.invalidate((page << 8) as u16);
+        }
+    }
+
+    fn caching(&mut self, memory: &[u8], start: u16) {
+        let mut instructions = Vec::new();
+        let mut address = start as usize;
+        while instructions.len() < MAX_BLOCK_LEN && address < memory.len() {
+            let (instruction, size) = match decoding(memory, address as u16, self.variant) {
+                Some(decoded) => decoded,
+                // Left to `evaluating_next`
+                None => break,
+            };
+            if address + size as usize > memory.len() {
+                break;
+            }
+            instructions.push(instruction);
+            let op_code = memory[address];
+            address += size as usize;
+            if ends_block(op_code) {
+                break;
+            }
+        }
+        if instructions.is_empty() {
+            return;
+        }
+
+        let last_page = (address - 1) >> 8;
+        self.code[start as usize..address].fill(true);
+        for page in (start as usize >> 8)..=last_page {
+            self.pages[page].push(start);
+        }
+        self.blocks[start as usize] = Some(CachedBlock {
+            instructions: instructions.into_boxed_slice(),
+            last_page,
+        });
+    }
+}
+
+impl State8080 {
+    /// Evaluates the block of instructions at PC, decoding and caching it in `cache` first if it
+    /// isn't already. When an interrupt is pending, the CPU is halted or the opcode at PC is not
+    /// an instruction, this is a single `evaluating_next` instead. `cache` keeps how many
+    /// instructions were evaluated and the cycles they took.
+    pub fn evaluating_block<I: IOHandler>(
+        self,
+        cache: &mut BlockCache,
+        mut io_handler: Option<&mut I>,
+    ) -> Self {
+        if self.variant() != cache.variant {
+            cache.clear();
+            cache.variant = self.variant();
+        }
+        let start = self.pc as usize;
+        let interrupted = self.is_interrupted();
+        if !interrupted && cache.blocks[start].is_none() {
+            cache.caching(&self.memory, self.pc);
+        }
+        let block = match &cache.blocks[start] {
+            Some(block) if !interrupted => &block.instructions,
+            _ => {
+                let state = self.evaluating_next(io_handler);
+                cache.last_instructions = 1;
+                cache.last_cycles = state.last_cycles() as u32;
+
+                return state;
+            }
+        };
+
+        let mut state = self;
+        let mut pending: Option<PendingFlags> = None;
+        let mut instructions = 0;
+        let mut cycles = 0;
+        let mut code_written = false;
+        for &instruction in block.iter() {
+            let io_handler = io_handler.as_deref_mut();
+            state = if cache.lazy_flags {
+                state.evaluating_with_lazy_flags(instruction, &mut pending, io_handler)
+            } else {
+                state.evaluating_decoded(instruction, io_handler)
+            };
+            instructions += 1;
+            cycles += state.last_cycles() as u32;
+
+            code_written = state
+                .last_memory_writes()
+                .iter()
+                .any(|&address| cache.code[address as usize]);
+            if code_written {
+                break;
+            }
+        }
+
+        if code_written {
+            for &address in state.last_memory_writes() {
+                if cache.code[address as usize] {
+                    cache.invalidate(address);
+                }
+            }
+        }
+        cache.last_instructions = instructions;
+        cache.last_cycles = cycles;
+
+        state.materializing_flags(pending)
+    }
+}
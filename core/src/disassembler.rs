@@ -1,4 +1,13 @@
-macro_rules! enum_try_from_u8 {
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::convert::TryFrom;
+
+use crate::emulator::{ConditionCodes, CpuVariant};
+
+// S, Z, AC, P and CY
+const ALL_FLAGS: ConditionCodes = ConditionCodes::from_bits_truncate(0xd5);
+
+macro_rules! enum_from_u8 {
     ($(#[$meta:meta])* $vis:vis enum $name:ident {
         $($(#[$vmeta:meta])* $vname:ident $(= $val:expr)?,)*
     }) => {
@@ -7,21 +16,20 @@ macro_rules! enum_try_from_u8 {
             $($(#[$vmeta])* $vname $(= $val)?,)*
         }
 
-        impl std::convert::TryFrom<u8> for $name {
-            type Error = ();
-
-            fn try_from(v: u8) -> Result<Self, Self::Error> {
+        impl $name {
+            // Compares with every variant in turn, the decode tables are built with it
+            const fn from_u8(v: u8) -> Option<Self> {
                 match v {
-                    $(x if x == $name::$vname as u8 => Ok($name::$vname),)*
-                    _ => Err(()),
+                    $(x if x == $name::$vname as u8 => Some($name::$vname),)*
+                    _ => None,
                 }
             }
         }
     }
 }
 
-enum_try_from_u8! {
-    #[derive(Debug, Clone)]
+enum_from_u8! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     #[repr(u8)]
     pub enum Instruction {
         Nop = 0x00,
@@ -278,14 +286,14 @@ enum_try_from_u8! {
     }
 }
 
-impl std::fmt::Display for Instruction {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
 impl Instruction {
-    pub fn size(&self) -> u8 {
+    pub const fn size(&self) -> u8 {
         match self {
             Instruction::LxiB
             | Instruction::LxiD
@@ -337,7 +345,127 @@ impl Instruction {
         }
     }
 
-    pub fn cycles(&self) -> u8 {
+    /// Cycle count on the 8085, which differs from the 8080 for register moves, increments, stack
+    /// operations and calls. Conditional branches report the taken count like `cycles`.
+    pub const fn cycles_8085(&self) -> u8 {
+        match self {
+            Instruction::MovBB
+            | Instruction::MovBC
+            | Instruction::MovBD
+            | Instruction::MovBE
+            | Instruction::MovBH
+            | Instruction::MovBL
+            | Instruction::MovBA
+            | Instruction::MovCB
+            | Instruction::MovCC
+            | Instruction::MovCD
+            | Instruction::MovCE
+            | Instruction::MovCH
+            | Instruction::MovCL
+            | Instruction::MovCA
+            | Instruction::MovDB
+            | Instruction::MovDC
+            | Instruction::MovDD
+            | Instruction::MovDE
+            | Instruction::MovDH
+            | Instruction::MovDL
+            | Instruction::MovDA
+            | Instruction::MovEB
+            | Instruction::MovEC
+            | Instruction::MovED
+            | Instruction::MovEE
+            | Instruction::MovEH
+            | Instruction::MovEL
+            | Instruction::MovEA
+            | Instruction::MovHB
+            | Instruction::MovHC
+            | Instruction::MovHD
+            | Instruction::MovHE
+            | Instruction::MovHH
+            | Instruction::MovHL
+            | Instruction::MovHA
+            | Instruction::MovLB
+            | Instruction::MovLC
+            | Instruction::MovLD
+            | Instruction::MovLE
+            | Instruction::MovLH
+            | Instruction::MovLL
+            | Instruction::MovLA
+            | Instruction::MovAB
+            | Instruction::MovAC
+            | Instruction::MovAD
+            | Instruction::MovAE
+            | Instruction::MovAH
+            | Instruction::MovAL
+            | Instruction::MovAA => 4,
+
+            Instruction::InrB
+            | Instruction::InrC
+            | Instruction::InrD
+            | Instruction::InrE
+            | Instruction::InrH
+            | Instruction::InrL
+            | Instruction::InrA
+            | Instruction::DcrB
+            | Instruction::DcrC
+            | Instruction::DcrD
+            | Instruction::DcrE
+            | Instruction::DcrH
+            | Instruction::DcrL
+            | Instruction::DcrA => 4,
+
+            Instruction::InxB
+            | Instruction::InxD
+            | Instruction::InxH
+            | Instruction::InxSp
+            | Instruction::DcxB
+            | Instruction::DcxD
+            | Instruction::DcxH
+            | Instruction::DcxSp => 6,
+
+            Instruction::Cnz
+            | Instruction::Cz
+            | Instruction::Call
+            | Instruction::Cnc
+            | Instruction::Cc
+            | Instruction::Cpo
+            | Instruction::Cpe
+            | Instruction::Cp
+            | Instruction::Cm => 18,
+
+            Instruction::Rnz
+            | Instruction::Rz
+            | Instruction::Rnc
+            | Instruction::Rc
+            | Instruction::Rpo
+            | Instruction::Rpe
+            | Instruction::Rp
+            | Instruction::Rm => 12,
+
+            Instruction::Rst0
+            | Instruction::Rst1
+            | Instruction::Rst2
+            | Instruction::Rst3
+            | Instruction::Rst4
+            | Instruction::Rst5
+            | Instruction::Rst6
+            | Instruction::Rst7 => 12,
+
+            Instruction::PushB | Instruction::PushD | Instruction::PushH | Instruction::PushPsw => {
+                12
+            }
+
+            Instruction::Pchl | Instruction::Sphl => 6,
+
+            Instruction::Xthl => 16,
+
+            Instruction::Hlt => 5,
+
+            _ => self.cycles(),
+        }
+    }
+
+    pub const fn cycles(&self) -> u8 {
         match self {
             Instruction::MovBB
             | Instruction::MovBC
@@ -602,4 +730,201 @@ impl Instruction {
             | Instruction::Nop7 => 4,
         }
     }
+
+    /// Flags the instruction writes on the 8080. On the 8085 arithmetic and logical operations
+    /// also write V and K, and INX and DCX write K.
+    pub const fn flags(&self) -> ConditionCodes {
+        let op_code = *self as u8;
+        match op_code {
+            // POP PSW loads every bit
+            0xf1 => ConditionCodes::all(),
+            // ALU operations with a register, M or an immediate, and DAA
+            0x80..=0xbf | 0x27 => ALL_FLAGS,
+            _ if op_code & 0xc7 == 0xc6 => ALL_FLAGS,
+            // INR and DCR
+            _ if op_code & 0xc6 == 0x04 => ALL_FLAGS.difference(ConditionCodes::CY),
+            // DAD
+            _ if op_code & 0xcf == 0x09 => ConditionCodes::CY,
+            // Rotates, STC and CMC
+            0x07 | 0x0f | 0x17 | 0x1f | 0x37 | 0x3f => ConditionCodes::CY,
+            _ => ConditionCodes::empty(),
+        }
+    }
+}
+
+enum_from_u8! {
+    /// Opcodes the 8085 assigns to slots that are undefined or alternate NOPs on the 8080. Only
+    /// RIM and SIM are documented by Intel.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(u8)]
+    pub enum Instruction8085 {
+        Dsub = 0x08,
+        Arhl = 0x10,
+        Rdel = 0x18,
+        Rim = 0x20,
+        Ldhi = 0x28,
+        Sim = 0x30,
+        Ldsi = 0x38,
+        Rstv = 0xcb,
+        Shlx = 0xd9,
+        Jnk = 0xdd,
+        Lhlx = 0xed,
+        Jk = 0xfd,
+    }
+}
+
+impl core::fmt::Display for Instruction8085 {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Instruction8085 {
+    pub const fn size(&self) -> u8 {
+        match self {
+            Instruction8085::Jnk | Instruction8085::Jk => 3,
+
+            Instruction8085::Ldhi | Instruction8085::Ldsi => 2,
+
+            _ => 1,
+        }
+    }
+
+    pub const fn cycles(&self) -> u8 {
+        match self {
+            Instruction8085::Rim | Instruction8085::Sim => 4,
+
+            Instruction8085::Arhl => 7,
+
+            Instruction8085::Rstv => 12,
+
+            Instruction8085::Dsub
+            | Instruction8085::Rdel
+            | Instruction8085::Ldhi
+            | Instruction8085::Ldsi
+            | Instruction8085::Shlx
+            | Instruction8085::Lhlx
+            | Instruction8085::Jnk
+            | Instruction8085::Jk => 10,
+        }
+    }
+
+    /// Flags the instruction writes, DSUB also sets V and K.
+    pub const fn flags(&self) -> ConditionCodes {
+        match self {
+            Instruction8085::Dsub => ALL_FLAGS.union(ConditionCodes::V).union(ConditionCodes::K),
+            Instruction8085::Arhl | Instruction8085::Rdel => ConditionCodes::CY,
+            _ => ConditionCodes::empty(),
+        }
+    }
+}
+
+/// What the executor and the disassembler need to know about an opcode, looked up in `DECODE`
+/// or `DECODE_8085` rather than worked out on every step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded<I> {
+    pub instruction: I,
+    pub size: u8,
+    /// Conditional branches count as taken.
+    pub cycles: u8,
+    pub cycles_8085: u8,
+    /// Flags the instruction writes, see `Instruction::flags`.
+    pub flags: ConditionCodes,
+}
+
+/// The 8080 instruction of every opcode, `None` for the undefined ones.
+pub static DECODE: [Option<Decoded<Instruction>>; 256] = {
+    let mut table = [None; 256];
+    let mut op_code = 0;
+    while op_code < 256 {
+        if let Some(instruction) = Instruction::from_u8(op_code as u8) {
+            table[op_code] = Some(Decoded {
+                instruction,
+                size: instruction.size(),
+                cycles: instruction.cycles(),
+                cycles_8085: instruction.cycles_8085(),
+                flags: instruction.flags(),
+            });
+        }
+        op_code += 1;
+    }
+
+    table
+};
+
+/// The opcodes the 8085 decodes differently from the 8080, `None` for the others.
+pub static DECODE_8085: [Option<Decoded<Instruction8085>>; 256] = {
+    let mut table = [None; 256];
+    let mut op_code = 0;
+    while op_code < 256 {
+        if let Some(instruction) = Instruction8085::from_u8(op_code as u8) {
+            table[op_code] = Some(Decoded {
+                instruction,
+                size: instruction.size(),
+                cycles: instruction.cycles(),
+                cycles_8085: instruction.cycles(),
+                flags: instruction.flags(),
+            });
+        }
+        op_code += 1;
+    }
+
+    table
+};
+
+impl TryFrom<u8> for Instruction {
+    type Error = ();
+
+    fn try_from(op_code: u8) -> Result<Self, Self::Error> {
+        DECODE[op_code as usize]
+            .map(|decoded| decoded.instruction)
+            .ok_or(())
+    }
+}
+
+impl TryFrom<u8> for Instruction8085 {
+    type Error = ();
+
+    fn try_from(op_code: u8) -> Result<Self, Self::Error> {
+        DECODE_8085[op_code as usize]
+            .map(|decoded| decoded.instruction)
+            .ok_or(())
+    }
+}
+
+/// Formats the bytes following an opcode: `#$12` for an immediate byte, `$1234` for an address or
+/// 16-bit immediate.
+pub fn formatting_operand(bytes: &[u8]) -> Option<String> {
+    match bytes {
+        [] => None,
+        [byte] => Some(format!("#${:02x}", byte)),
+        [low, high, ..] => Some(format!("${:02x}{:02x}", high, low)),
+    }
+}
+
+/// Disassembles the instruction at `address`, returning the mnemonic followed by its operand and
+/// the size of the instruction. Operand bytes past the end of `memory` are left out.
+pub fn disassembling(memory: &[u8], address: u16, variant: CpuVariant) -> (String, u8) {
+    let start = address as usize;
+    let op_code = memory.get(start).copied().unwrap_or(0);
+
+    let decoded_8085 = match variant {
+        CpuVariant::Intel8080 => None,
+        CpuVariant::Intel8085 => DECODE_8085[op_code as usize],
+    };
+    let (mnemonic, size) = match (decoded_8085, DECODE[op_code as usize]) {
+        (Some(decoded), _) => (decoded.instruction.to_string(), decoded.size),
+        (None, Some(decoded)) => (decoded.instruction.to_string(), decoded.size),
+        // Undefined on the 8080, shown as a data byte
+        (None, None) => return (format!("Db    #${:02x}", op_code), 1),
+    };
+
+    let end = (start + size as usize).min(memory.len());
+    let operand = memory.get(start + 1..end).and_then(formatting_operand);
+    let text = match operand {
+        Some(operand) => format!("{}    {}", mnemonic, operand),
+        None => mnemonic,
+    };
+
+    (text, size)
 }
@@ -1,15 +1,41 @@
-use std::convert::TryFrom;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::path::Path;
 
 use bitflags::bitflags;
 
-use crate::disassembler::Instruction;
+use crate::blocks::CachedInstruction;
+use crate::disassembler::{Decoded, Instruction, Instruction8085, DECODE, DECODE_8085};
 
 pub trait IOHandler {
     fn inp(&mut self, state: State8080, port: u8) -> State8080;
     fn out(&mut self, state: State8080, port: u8) -> State8080;
 }
 
+/// A device driving the INTR line that supplies the instruction executed during the interrupt
+/// acknowledge cycles, like the 8259.
+pub trait InterruptController {
+    fn interrupt_requested(&self) -> bool;
+    /// Runs the acknowledge cycles, returning the instruction put on the data bus. Only CALL and
+    /// RST are taken, anything else is executed as a NOP.
+    fn acknowledging(&mut self) -> [u8; 3];
+}
+
+/// Devices a machine also needs to reach, to clock them or wire their other pins, can be shared.
+impl<H: IOHandler> IOHandler for Rc<RefCell<H>> {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        self.borrow_mut().inp(state, port)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        self.borrow_mut().out(state, port)
+    }
+}
+
 pub struct DummyIOHandler;
 
 impl IOHandler for DummyIOHandler {
@@ -23,14 +49,21 @@ impl IOHandler for DummyIOHandler {
 }
 
 bitflags! {
+    /// Flags of the 8080. Bits 1, 3 and 5 are fixed on the 8080, the 8085 keeps V and K in bits 1
+    /// and 5, and the Z80 uses bit 1 as N and bits 3 and 5 as the undocumented X and Y.
     #[repr(C)]
     pub struct ConditionCodes: u8 {
         const S = 0b10000000;
         const Z = 0b01000000;
+        const K = 0b00100000;
+        const Y = 0b00100000;
         const AC = 0b00010000;
+        const X = 0b00001000;
         const P = 0b00000100;
         const CY = 0b00000001;
         const PAD = 0b00000010;
+        const V = 0b00000010;
+        const N = 0b00000010;
     }
 }
 
@@ -40,6 +73,56 @@ impl Default for ConditionCodes {
     }
 }
 
+/// The CPU being emulated. The 8085 runs 8080 code unchanged, but reuses the alternate NOP slots
+/// and undefined opcodes, has interrupt pins and a serial line, and takes a different number of
+/// cycles for some instructions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CpuVariant {
+    #[default]
+    Intel8080,
+    Intel8085,
+}
+
+/// Interrupt inputs of the 8085. TRAP and RST 7.5 are edge triggered and latched, RST 5.5 and
+/// RST 6.5 are level triggered and must be held until the interrupt is serviced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptPin {
+    Rst55,
+    Rst65,
+    Rst75,
+    Trap,
+}
+
+impl InterruptPin {
+    fn vector(self) -> u16 {
+        match self {
+            InterruptPin::Rst55 => 0x2c,
+            InterruptPin::Rst65 => 0x34,
+            InterruptPin::Rst75 => 0x3c,
+            InterruptPin::Trap => 0x24,
+        }
+    }
+}
+
+/// State of the 8085 interrupt and serial pins.
+#[derive(Default, Clone, Copy)]
+struct Pins8085 {
+    // M5.5, M6.5 and M7.5 in the same bits as SIM and RIM
+    masks: u8,
+    rst55: bool,
+    rst65: bool,
+    rst75: bool,
+    rst75_pending: bool,
+    trap: bool,
+    trap_pending: bool,
+    // RIM reports the interrupt enable from before a TRAP once after servicing it
+    ie_before_trap: Option<bool>,
+    // Set by EI, RST 5.5, 6.5 and 7.5 wait until the instruction after it has run
+    after_ei: bool,
+    sid: bool,
+    sod: bool,
+}
+
 pub struct BytePair {
     pub low: u8,
     pub high: u8,
@@ -59,6 +142,145 @@ impl From<BytePair> for u16 {
         ((pair.high as u16) << 8) | pair.low as u16
     }
 }
+
+/// Addresses written by the last evaluated instruction. No 8080 instruction writes more than two
+/// bytes, the extra room is for interrupts pushing the return address.
+#[derive(Default, Clone, Copy)]
+struct MemoryWrites {
+    addresses: [u16; 4],
+    len: u8,
+}
+
+impl MemoryWrites {
+    fn recording(self, address: u16) -> Self {
+        let mut writes = self;
+        if let Some(slot) = writes.addresses.get_mut(writes.len as usize) {
+            *slot = address;
+            writes.len += 1;
+        }
+
+        writes
+    }
+
+    fn as_slice(&self) -> &[u16] {
+        &self.addresses[..self.len as usize]
+    }
+}
+
+/// ALU operations whose flags can be worked out later, see `PendingFlags`.
+#[derive(Clone, Copy)]
+enum AluOperation {
+    Add,
+    Subtract,
+    And,
+    Xor,
+    Or,
+    Increment,
+    Decrement,
+}
+
+/// An ALU operation whose flags are only worked out when something needs them. Until then `cc`
+/// keeps the flags from before it.
+#[derive(Clone, Copy)]
+pub(crate) struct PendingFlags {
+    operation: AluOperation,
+    lhs: u8,
+    rhs: u8,
+}
+
+impl PendingFlags {
+    // Runs the operation again on a state without memory, so the flags come out exactly as if
+    // they had not been left for later
+    #[inline(never)]
+    fn applying(self, cc: ConditionCodes, variant: CpuVariant) -> ConditionCodes {
+        let state = State8080 {
+            a: self.lhs,
+            cc,
+            variant,
+            ..State8080::default()
+        };
+        let state = match self.operation {
+            AluOperation::Add => state.adding(self.rhs, false),
+            AluOperation::Subtract => state.subtracting(self.rhs, false),
+            AluOperation::And => state.ana(self.rhs),
+            AluOperation::Xor => state.xra(self.rhs),
+            AluOperation::Or => state.ora(self.rhs),
+            AluOperation::Increment => state.incrementing(self.lhs).0,
+            AluOperation::Decrement => state.decrementing(self.lhs).0,
+        };
+
+        state.cc
+    }
+}
+
+// ADD, SUB, ANA, XRA, ORA and CMP on a register, M or an immediate, INR and DCR, whose flags
+// can be left pending. ADC, SBB, ACI and SBI read CY.
+const fn defers_flags(op_code: u8) -> bool {
+    match op_code {
+        0x88..=0x8f | 0x98..=0x9f => false,
+        0x80..=0xbf | 0xc6 | 0xd6 | 0xe6 | 0xee | 0xf6 | 0xfe => true,
+        0x00..=0x3f => op_code & 0x06 == 0x04,
+        _ => false,
+    }
+}
+
+// Instructions that neither read nor write flags, so they can run while flags are pending
+const fn leaves_flags_alone(op_code: u8, variant: CpuVariant) -> bool {
+    match op_code {
+        // HLT
+        0x76 => false,
+        // MOV
+        0x40..=0x7f => true,
+        // NOP and LXI
+        0x00 | 0x01 | 0x11 | 0x21 | 0x31 => true,
+        // INX and DCX, which write K on the 8085
+        0x03 | 0x13 | 0x23 | 0x33 | 0x0b | 0x1b | 0x2b | 0x3b => {
+            matches!(variant, CpuVariant::Intel8080)
+        }
+        // LDA, STA, LHLD, SHLD, LDAX, STAX and MVI
+        0x00..=0x3f => matches!(op_code & 0x07, 0x02 | 0x06),
+        // PUSH and POP other than PSW, JMP, CALL, RET, PCHL, XCHG, XTHL, SPHL, EI and DI
+        0xc1 | 0xd1 | 0xe1 | 0xc5 | 0xd5 | 0xe5 => true,
+        0xc3 | 0xcd | 0xc9 | 0xe9 | 0xeb | 0xe3 | 0xf9 | 0xfb | 0xf3 => true,
+        // RST
+        _ => op_code & 0xc7 == 0xc7,
+    }
+}
+
+const SAVE_STATE_MAGIC: &[u8; 4] = b"8080";
+const SAVE_STATE_VERSION: u8 = 1;
+// Everything before the memory
+const SAVE_STATE_HEADER_LEN: usize = 27;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveStateError {
+    /// Not a save state of this emulator.
+    NotASaveState,
+    UnsupportedVersion(u8),
+    /// The save state is shorter than it says.
+    Truncated,
+    /// The memory saved is not the 64 KiB the CPU addresses.
+    MemorySize(usize),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            SaveStateError::Truncated => write!(f, "truncated save state"),
+            SaveStateError::MemorySize(len) => {
+                write!(f, "save state has {} bytes of memory instead of 65536", len)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SaveStateError {}
+
 #[derive(Default, Clone)]
 pub struct State8080 {
     pub a: u8,
@@ -72,8 +294,13 @@ pub struct State8080 {
     pub pc: u16,
     pub cc: ConditionCodes,
     pub interrupt_enabled: bool,
+    /// Set by HLT, the CPU then idles until it takes an interrupt.
+    pub halted: bool,
     pub memory: Vec<u8>,
     last_cycles: u8,
+    last_writes: MemoryWrites,
+    variant: CpuVariant,
+    pins: Pins8085,
 }
 
 impl State8080 {
@@ -84,10 +311,193 @@ impl State8080 {
         }
     }
 
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    /// Switches the emulated CPU. Bit 1 of the flags is V on the 8085 and always set on the 8080,
+    /// and the 8085 comes out of reset with every interrupt masked.
+    pub fn setting_variant(self, variant: CpuVariant) -> Self {
+        let mut cc = self.cc;
+        let pins = match variant {
+            CpuVariant::Intel8080 => {
+                cc.remove(ConditionCodes::K);
+                cc.insert(ConditionCodes::PAD);
+
+                Pins8085::default()
+            }
+            CpuVariant::Intel8085 => {
+                cc.remove(ConditionCodes::V);
+
+                Pins8085 {
+                    masks: 0x07,
+                    ..Pins8085::default()
+                }
+            }
+        };
+
+        State8080 {
+            variant,
+            cc,
+            pins,
+            ..self
+        }
+    }
+
+    /// Drives an 8085 interrupt input. Does nothing on the 8080.
+    pub fn setting_interrupt_pin(self, pin: InterruptPin, level: bool) -> Self {
+        let mut pins = self.pins;
+        match pin {
+            InterruptPin::Rst55 => pins.rst55 = level,
+            InterruptPin::Rst65 => pins.rst65 = level,
+            InterruptPin::Rst75 => {
+                pins.rst75_pending |= level && !pins.rst75;
+                pins.rst75 = level;
+            }
+            InterruptPin::Trap => {
+                pins.trap_pending |= level && !pins.trap;
+                pins.trap = level;
+            }
+        }
+
+        State8080 { pins, ..self }
+    }
+
+    /// Drives the 8085 serial input line, read by RIM.
+    pub fn setting_sid(self, level: bool) -> Self {
+        let pins = Pins8085 {
+            sid: level,
+            ..self.pins
+        };
+
+        State8080 { pins, ..self }
+    }
+
+    /// Level of the 8085 serial output line, as last set by SIM.
+    pub fn sod(&self) -> bool {
+        self.pins.sod
+    }
+
     pub fn last_cycles(&self) -> u8 {
         self.last_cycles
     }
 
+    /// Addresses written to memory by the last evaluated instruction, in write order.
+    pub fn last_memory_writes(&self) -> &[u16] {
+        self.last_writes.as_slice()
+    }
+
+    /// Serializes everything but the memory writes of the last instruction, which the next
+    /// instruction clears anyway. The layout is little endian: "8080", the version, the variant,
+    /// A, B, C, D, E, H, L, the flags, SP, PC, the interrupt enable, halt and EI delay flags, the
+    /// cycles of the last instruction, the 8085 pins, then the length of memory and the memory
+    /// itself.
+    pub fn save_state(&self) -> Vec<u8> {
+        let pins = &self.pins;
+        let pin_levels = [
+            pins.rst55,
+            pins.rst65,
+            pins.rst75,
+            pins.rst75_pending,
+            pins.trap,
+            pins.trap_pending,
+            pins.sid,
+            pins.sod,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |byte, (bit, &level)| byte | (level as u8) << bit);
+        let ie_before_trap = match pins.ie_before_trap {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        };
+
+        let mut bytes = Vec::with_capacity(SAVE_STATE_HEADER_LEN + self.memory.len());
+        bytes.extend_from_slice(SAVE_STATE_MAGIC);
+        bytes.push(SAVE_STATE_VERSION);
+        bytes.push(self.variant as u8);
+        bytes.extend_from_slice(&[self.a, self.b, self.c, self.d, self.e, self.h, self.l]);
+        bytes.push(self.cc.bits());
+        bytes.extend_from_slice(&self.sp.to_le_bytes());
+        bytes.extend_from_slice(&self.pc.to_le_bytes());
+        bytes.push(
+            self.interrupt_enabled as u8 | (self.halted as u8) << 1 | (pins.after_ei as u8) << 2,
+        );
+        bytes.push(self.last_cycles);
+        bytes.extend_from_slice(&[pins.masks, pin_levels, ie_before_trap]);
+        bytes.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.memory);
+
+        bytes
+    }
+
+    pub fn from_save_state(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        if bytes.len() < 5 || &bytes[..4] != SAVE_STATE_MAGIC {
+            return Err(SaveStateError::NotASaveState);
+        }
+        if bytes[4] != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(bytes[4]));
+        }
+        if bytes.len() < SAVE_STATE_HEADER_LEN {
+            return Err(SaveStateError::Truncated);
+        }
+
+        let word = |index: usize| u16::from_le_bytes([bytes[index], bytes[index + 1]]);
+        let memory_len = u32::from_le_bytes([bytes[23], bytes[24], bytes[25], bytes[26]]) as usize;
+        let memory = match bytes.get(SAVE_STATE_HEADER_LEN..) {
+            Some(memory) if memory.len() == memory_len => memory.to_vec(),
+            _ => return Err(SaveStateError::Truncated),
+        };
+        if memory_len != 0x10000 {
+            return Err(SaveStateError::MemorySize(memory_len));
+        }
+
+        let variant = match bytes[5] {
+            0 => CpuVariant::Intel8080,
+            1 => CpuVariant::Intel8085,
+            _ => return Err(SaveStateError::NotASaveState),
+        };
+        let level = |bit: u8| bytes[21] & (1 << bit) != 0;
+        let pins = Pins8085 {
+            masks: bytes[20],
+            rst55: level(0),
+            rst65: level(1),
+            rst75: level(2),
+            rst75_pending: level(3),
+            trap: level(4),
+            trap_pending: level(5),
+            ie_before_trap: match bytes[22] {
+                0 => None,
+                1 => Some(false),
+                _ => Some(true),
+            },
+            sid: level(6),
+            sod: level(7),
+            after_ei: bytes[18] & 0x04 != 0,
+        };
+
+        Ok(State8080 {
+            a: bytes[6],
+            b: bytes[7],
+            c: bytes[8],
+            d: bytes[9],
+            e: bytes[10],
+            h: bytes[11],
+            l: bytes[12],
+            cc: ConditionCodes::from_bits_truncate(bytes[13]),
+            sp: word(14),
+            pc: word(16),
+            interrupt_enabled: bytes[18] & 0x01 != 0,
+            halted: bytes[18] & 0x02 != 0,
+            last_cycles: bytes[19],
+            memory,
+            last_writes: MemoryWrites::default(),
+            variant,
+            pins,
+        })
+    }
+
     pub fn loading_buffer_into_memory_at(self, buffer: Vec<u8>, index: u16) -> Self {
         let range_start = index as usize;
         let range_end = range_start + buffer.len();
@@ -100,6 +510,7 @@ impl State8080 {
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn loading_file_into_memory_at<P: AsRef<Path>>(self, path: P, index: u16) -> Self {
         let buf = std::fs::read(path).expect("Failed to read file");
 
@@ -116,6 +527,16 @@ impl State8080 {
         }
     }
 
+    // Same as `setting_memory_at`, but records the write for `last_memory_writes`
+    fn writing_memory_at(self, byte: u8, index: u16) -> Self {
+        let last_writes = self.last_writes.recording(index);
+
+        State8080 {
+            last_writes,
+            ..self.setting_memory_at(byte, index)
+        }
+    }
+
     pub fn bc(&self) -> BytePair {
         BytePair {
             high: self.b,
@@ -157,6 +578,14 @@ impl State8080 {
         self.cc.contains(ConditionCodes::AC)
     }
 
+    pub fn v(&self) -> bool {
+        self.variant == CpuVariant::Intel8085 && self.cc.contains(ConditionCodes::V)
+    }
+
+    pub fn k(&self) -> bool {
+        self.cc.contains(ConditionCodes::K)
+    }
+
     fn memory_at_sp(&self) -> BytePair {
         let low_index = self.sp;
         let high_index = self.sp.wrapping_add(1);
@@ -170,8 +599,8 @@ impl State8080 {
         let low_index = self.sp;
         let high_index = self.sp.wrapping_add(1);
 
-        self.setting_memory_at(pair.low, low_index)
-            .setting_memory_at(pair.high, high_index)
+        self.writing_memory_at(pair.low, low_index)
+            .writing_memory_at(pair.high, high_index)
     }
 
     fn reading_next_byte(self) -> (Self, u8) {
@@ -201,10 +630,17 @@ impl State8080 {
     }
 
     fn setting_raw_cc(self, bits: u8) -> Self {
-        State8080 {
-            cc: ConditionCodes::from_bits_truncate(bits | ConditionCodes::PAD.bits()),
-            ..self
-        }
+        let cc = match self.variant {
+            CpuVariant::Intel8080 => ConditionCodes::from_bits_truncate(
+                (bits & !(ConditionCodes::K | ConditionCodes::X).bits())
+                    | ConditionCodes::PAD.bits(),
+            ),
+            CpuVariant::Intel8085 => {
+                ConditionCodes::from_bits_truncate(bits & !ConditionCodes::X.bits())
+            }
+        };
+
+        State8080 { cc, ..self }
     }
 
     fn setting_all_flags(self, value: u16, ac_check: u8) -> Self {
@@ -241,6 +677,28 @@ impl State8080 {
         self.setting_zsp_flags(value).setting_ac_flag(ac_check)
     }
 
+    // The 8085 also keeps V for signed overflow and K, which is S xor V after arithmetic and
+    // logical operations. Neither exists on the 8080.
+    fn setting_overflow_flags(self, overflow: bool) -> Self {
+        match self.variant {
+            CpuVariant::Intel8080 => self,
+            CpuVariant::Intel8085 => {
+                let k = self.s() != overflow;
+
+                self.setting_flag(ConditionCodes::V, overflow)
+                    .setting_flag(ConditionCodes::K, k)
+            }
+        }
+    }
+
+    // K doubles as the carry out of INX and the borrow out of DCX on the 8085
+    fn setting_pair_carry_flag(self, carry: bool) -> Self {
+        match self.variant {
+            CpuVariant::Intel8080 => self,
+            CpuVariant::Intel8085 => self.setting_flag(ConditionCodes::K, carry),
+        }
+    }
+
     fn clearing_ac(self) -> Self {
         self.setting_flag(ConditionCodes::AC, false)
     }
@@ -250,41 +708,32 @@ impl State8080 {
     }
 
     fn pushing(self, high: u8, low: u8) -> Self {
-        let mut state = self;
-        state.memory[state.sp.wrapping_sub(1) as usize] = high;
-        state.memory[state.sp.wrapping_sub(2) as usize] = low;
-        state.sp = state.sp.wrapping_sub(2);
+        let high_index = self.sp.wrapping_sub(1);
+        let low_index = self.sp.wrapping_sub(2);
 
-        state
+        self.writing_memory_at(high, high_index)
+            .writing_memory_at(low, low_index)
+            .setting_sp(low_index)
     }
 
+    #[cfg(feature = "std")]
     fn log_instruction(&self, instruction: Instruction) {
+        self.log_raw_instruction(instruction as u8, &instruction, instruction.size());
+    }
+
+    #[cfg(feature = "std")]
+    fn log_raw_instruction(&self, op_code: u8, mnemonic: &dyn fmt::Display, size: u8) {
         // pc is incremented after reading it, we should rewind back here for logging
         let instruction_pc = self.pc - 1;
         let mut output_line = format!(
             "{:04x}    {:#04x}    {}",
-            instruction_pc,
-            instruction.clone() as u8,
-            instruction.to_string()
+            instruction_pc, op_code, mnemonic
         );
 
-        let mut next_bytes = Vec::new();
-        for i in 1..instruction.size() {
-            let byte = self.memory[instruction_pc as usize + i as usize];
-            next_bytes.push(byte);
-        }
-
-        let mut next_bytes_iter = next_bytes.iter();
-        if let Some(next) = next_bytes_iter.next() {
-            let mut addr_str = format!("{:02x}", next);
-
-            if let Some(next) = next_bytes_iter.next() {
-                addr_str = format!("${:02x}{}", next, addr_str);
-            } else {
-                addr_str = format!("#${}", addr_str);
-            }
-
-            output_line = format!("{}    {}", output_line, addr_str);
+        let start = instruction_pc as usize + 1;
+        let operand = &self.memory[start..start + size as usize - 1];
+        if let Some(operand) = crate::disassembler::formatting_operand(operand) {
+            output_line = format!("{}    {}", output_line, operand);
         }
         println!("{}", output_line);
     }
@@ -296,6 +745,7 @@ impl State8080 {
         state = state.pushing(pc_pair.high, pc_pair.low);
         state.pc = 8 * int_num;
         state.interrupt_enabled = false;
+        state.halted = false;
 
         state
     }
@@ -352,6 +802,37 @@ impl State8080 {
         Self { l, ..self }
     }
 
+    // B, C, D, E, H, L, M or A, in the order opcodes encode them
+    fn register(&self, code: u8) -> u8 {
+        match code & 0x07 {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => self.h,
+            5 => self.l,
+            6 => self.memory[u16::from(self.hl()) as usize],
+            _ => self.a,
+        }
+    }
+
+    fn setting_register(self, code: u8, value: u8) -> Self {
+        match code & 0x07 {
+            0 => self.setting_b(value),
+            1 => self.setting_c(value),
+            2 => self.setting_d(value),
+            3 => self.setting_e(value),
+            4 => self.setting_h(value),
+            5 => self.setting_l(value),
+            6 => {
+                let address = self.hl().into();
+
+                self.writing_memory_at(value, address)
+            }
+            _ => self.setting_a(value),
+        }
+    }
+
     fn setting_sp(self, sp: u16) -> Self {
         Self { sp, ..self }
     }
@@ -386,8 +867,8 @@ impl State8080 {
             new_state
                 .setting_pc(addr)
                 .setting_sp(low_mem_addr)
-                .setting_memory_at(return_pair.high, high_mem_addr)
-                .setting_memory_at(return_pair.low, low_mem_addr)
+                .writing_memory_at(return_pair.high, high_mem_addr)
+                .writing_memory_at(return_pair.low, low_mem_addr)
         } else {
             new_state
         }
@@ -416,10 +897,15 @@ impl State8080 {
         let res_precise = self.a as u16 + rhs as u16 + cy as u16;
         let ac_check = (self.a & 0x0f) + (rhs & 0x0f) + cy as u8;
         let res = res_precise as u8;
+        let overflow = (self.a ^ res) & (rhs ^ res) & 0x80 != 0;
 
-        self.setting_a(res).setting_all_flags(res_precise, ac_check)
+        self.setting_a(res)
+            .setting_all_flags(res_precise, ac_check)
+            .setting_overflow_flags(overflow)
     }
 
+    // The 8080 subtracts by adding the one's complement of the operand with the carry inverted.
+    // CY ends up as a borrow, but AC is left as the plain carry out of bit 3 of that addition.
     fn subtracting(self, rhs: u8, cy: bool) -> Self {
         let new_state = self.adding(!rhs, !cy);
         let new_cy = new_state.cy();
@@ -427,14 +913,39 @@ impl State8080 {
         new_state.setting_flag(ConditionCodes::CY, !new_cy)
     }
 
+    // INR and DCR leave CY alone. AC is set when the low nibble carries, i.e. it was 0xf.
+    fn incrementing(self, value: u8) -> (Self, u8) {
+        let res = value.wrapping_add(1);
+        let ac_check = (value & 0x0f) + 1;
+        let new_state = self
+            .setting_zspac_flags(res, ac_check)
+            .setting_overflow_flags(value == 0x7f);
+
+        (new_state, res)
+    }
+
+    // DCR is an addition of 0xff, so AC is set unless the low nibble was 0 and had to borrow.
+    fn decrementing(self, value: u8) -> (Self, u8) {
+        let res = value.wrapping_sub(1);
+        let ac_check = (value & 0x0f) + 0x0f;
+        let new_state = self
+            .setting_zspac_flags(res, ac_check)
+            .setting_overflow_flags(value == 0x80);
+
+        (new_state, res)
+    }
+
+    // AC on the 8080 is the OR of bit 3 of both operands, the 8085 always sets it instead.
     fn ana(self, rhs: u8) -> Self {
         let lhs = self.a;
         let res = lhs & rhs;
+        let ac = self.variant == CpuVariant::Intel8085 || ((lhs | rhs) & 0x08) != 0;
 
         self.setting_a(res)
             .setting_zsp_flags(res)
-            .setting_flag(ConditionCodes::AC, ((lhs | rhs) & 0x08) != 0)
+            .setting_flag(ConditionCodes::AC, ac)
             .clearing_cy()
+            .setting_overflow_flags(false)
     }
 
     fn xra(self, rhs: u8) -> Self {
@@ -444,6 +955,7 @@ impl State8080 {
             .setting_zsp_flags(res)
             .clearing_cy()
             .clearing_ac()
+            .setting_overflow_flags(false)
     }
 
     fn ora(self, rhs: u8) -> Self {
@@ -453,6 +965,7 @@ impl State8080 {
             .setting_zsp_flags(res)
             .clearing_cy()
             .clearing_ac()
+            .setting_overflow_flags(false)
     }
 
     fn cmp(self, rhs: u8) -> Self {
@@ -469,11 +982,12 @@ impl State8080 {
 
     fn evaluating_instruction<I: IOHandler>(
         self,
-        instruction: Instruction,
+        decoded: Decoded<Instruction>,
         io_handler: Option<&mut I>,
     ) -> Self {
+        let instruction = decoded.instruction;
         #[cfg(feature = "logging")]
-        self.log_instruction(instruction.clone());
+        self.log_instruction(instruction);
 
         // let state;
         let new_state = match instruction {
@@ -794,43 +1308,43 @@ impl State8080 {
             Instruction::MovMB => {
                 let offset = self.hl().into();
                 let byte = self.b;
-                self.setting_memory_at(byte, offset)
+                self.writing_memory_at(byte, offset)
             }
             // 0x71
             Instruction::MovMC => {
                 let offset = self.hl().into();
                 let byte = self.c;
-                self.setting_memory_at(byte, offset)
+                self.writing_memory_at(byte, offset)
             }
             // 0x72
             Instruction::MovMD => {
                 let offset = self.hl().into();
                 let byte = self.d;
-                self.setting_memory_at(byte, offset)
+                self.writing_memory_at(byte, offset)
             }
             // 0x73
             Instruction::MovME => {
                 let offset = self.hl().into();
                 let byte = self.e;
-                self.setting_memory_at(byte, offset)
+                self.writing_memory_at(byte, offset)
             }
             // 0x74
             Instruction::MovMH => {
                 let offset = self.hl().into();
                 let byte = self.h;
-                self.setting_memory_at(byte, offset)
+                self.writing_memory_at(byte, offset)
             }
             // 0x75
             Instruction::MovML => {
                 let offset = self.hl().into();
                 let byte = self.l;
-                self.setting_memory_at(byte, offset)
+                self.writing_memory_at(byte, offset)
             }
             // 0x77
             Instruction::MovMA => {
                 let offset = self.hl().into();
                 let byte = self.a;
-                self.setting_memory_at(byte, offset)
+                self.writing_memory_at(byte, offset)
             }
 
             // 0x78
@@ -924,7 +1438,7 @@ impl State8080 {
                 let (new_state, byte) = self.reading_next_byte();
                 let offset: u16 = new_state.hl().into();
 
-                new_state.setting_memory_at(byte, offset)
+                new_state.writing_memory_at(byte, offset)
             }
             // 0x3E
             Instruction::MviA => {
@@ -963,14 +1477,14 @@ impl State8080 {
                 let offset: u16 = self.bc().into();
                 let val = self.a;
 
-                self.setting_memory_at(val, offset)
+                self.writing_memory_at(val, offset)
             }
             // 0x12
             Instruction::StaxD => {
                 let offset: u16 = self.de().into();
                 let val = self.a;
 
-                self.setting_memory_at(val, offset)
+                self.writing_memory_at(val, offset)
             }
 
             // 0x0A
@@ -993,7 +1507,7 @@ impl State8080 {
                 let (new_state, pair) = self.reading_next_pair();
                 let offset: u16 = pair.into();
                 let byte = new_state.a;
-                new_state.setting_memory_at(byte, offset)
+                new_state.writing_memory_at(byte, offset)
             }
             // 0x3A
             Instruction::Lda => {
@@ -1011,8 +1525,8 @@ impl State8080 {
                 let l = new_state.l;
                 let h = new_state.h;
 
-                new_state.setting_memory_at(l, offset)
-                    .setting_memory_at(h, offset.wrapping_add(1))
+                new_state.writing_memory_at(l, offset)
+                    .writing_memory_at(h, offset.wrapping_add(1))
             }
             // 0x2A
             Instruction::Lhld => {
@@ -1286,123 +1800,123 @@ impl State8080 {
 
             // 0x04
             Instruction::InrB => {
-                let res = self.b.wrapping_add(1);
-                let ac_check = (self.b & 0x0f) + 1;
+                let value = self.b;
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_b(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_b(res)
             }
             // 0x0C
             Instruction::InrC => {
-                let res = self.c.wrapping_add(1);
-                let ac_check = (self.c & 0x0f) + 1;
+                let value = self.c;
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_c(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_c(res)
             }
             // 0x14
             Instruction::InrD => {
-                let res = self.d.wrapping_add(1);
-                let ac_check = (self.d & 0x0f) + 1;
+                let value = self.d;
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_d(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_d(res)
             }
             // 0x1C
             Instruction::InrE => {
-                let res = self.e.wrapping_add(1);
-                let ac_check = (self.e & 0x0f) + 1;
+                let value = self.e;
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_e(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_e(res)
             }
             // 0x24
             Instruction::InrH => {
-                let res = self.h.wrapping_add(1);
-                let ac_check = (self.h & 0x0f) + 1;
+                let value = self.h;
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_h(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_h(res)
             }
             // 0x2C
             Instruction::InrL => {
-                let res = self.l.wrapping_add(1);
-                let ac_check = (self.l & 0x0f) + 1;
+                let value = self.l;
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_l(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_l(res)
             }
             // 0x34
             Instruction::InrM => {
                 let offset: u16 = self.hl().into();
-                let res = self.memory[offset as usize].wrapping_add(1);
-                let ac_check = (self.memory[offset as usize] & 0x0f) + 1;
+                let value = self.memory[offset as usize];
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_memory_at(res, offset).setting_zspac_flags(res, ac_check)
+                new_state.writing_memory_at(res, offset)
             }
             // 0x3C
             Instruction::InrA => {
-                let res = self.a.wrapping_add(1);
-                let ac_check = (self.a & 0x0f) + 1;
+                let value = self.a;
+                let (new_state, res) = self.incrementing(value);
 
-                self.setting_a(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_a(res)
             }
 
             // 0x05
             Instruction::DcrB => {
-                let res = self.b.wrapping_sub(1);
-                // +1 here is instead of !false as u8, by the idea of negating addition
-                let ac_check = (self.b & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.b;
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_b(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_b(res)
             }
             // 0x0D
             Instruction::DcrC => {
-                let res = self.c.wrapping_sub(1);
-                let ac_check = (self.c & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.c;
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_c(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_c(res)
             }
             // 0x15
             Instruction::DcrD => {
-                let res = self.d.wrapping_sub(1);
-                let ac_check = (self.d & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.d;
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_d(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_d(res)
             }
             // 0x1D
             Instruction::DcrE => {
-                let res = self.e.wrapping_sub(1);
-                let ac_check = (self.e & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.e;
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_e(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_e(res)
             }
             // 0x25
             Instruction::DcrH => {
-                let res = self.h.wrapping_sub(1);
-                let ac_check = (self.h & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.h;
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_h(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_h(res)
             }
             // 0x2D
             Instruction::DcrL => {
-                let res = self.l.wrapping_sub(1);
-                let ac_check = (self.l & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.l;
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_l(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_l(res)
             }
             // 0x35
             Instruction::DcrM => {
                 let offset: u16 = self.hl().into();
-                let res = self.memory[offset as usize].wrapping_sub(1);
-                let ac_check = (self.memory[offset as usize] & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.memory[offset as usize];
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_memory_at(res, offset).setting_zspac_flags(res, ac_check)
+                new_state.writing_memory_at(res, offset)
             }
             // 0x3D
             Instruction::DcrA => {
-                let res = self.a.wrapping_sub(1);
-                let ac_check = (self.a & 0x0f) + (!1 & 0x0f) + 1;
+                let value = self.a;
+                let (new_state, res) = self.decrementing(value);
 
-                self.setting_a(res).setting_zspac_flags(res, ac_check)
+                new_state.setting_a(res)
             }
 
             // 0x03
             Instruction::InxB => {
+                let wrapped = u16::from(self.bc()) == 0xffff;
                 let c = self.c.wrapping_add(1);
                 let mut b: Option<u8> = None;
                 if c == 0 {
@@ -1414,10 +1928,11 @@ impl State8080 {
                     low: c
                 };
 
-                self.setting_bc(pair)
+                self.setting_bc(pair).setting_pair_carry_flag(wrapped)
             }
             // 0x13
             Instruction::InxD => {
+                let wrapped = u16::from(self.de()) == 0xffff;
                 let e = self.e.wrapping_add(1);
                 let mut d: Option<u8> = None;
                 if e == 0 {
@@ -1429,10 +1944,11 @@ impl State8080 {
                     low: e
                 };
 
-                self.setting_de(pair)
+                self.setting_de(pair).setting_pair_carry_flag(wrapped)
             }
             // 0x23
             Instruction::InxH => {
+                let wrapped = u16::from(self.hl()) == 0xffff;
                 let l = self.l.wrapping_add(1);
                 let mut h: Option<u8> = None;
                 if l == 0 {
@@ -1444,17 +1960,19 @@ impl State8080 {
                     low: l
                 };
 
-                self.setting_hl(pair)
+                self.setting_hl(pair).setting_pair_carry_flag(wrapped)
             }
             // 0x33
             Instruction::InxSp => {
+                let wrapped = self.sp == 0xffff;
                 let sp = self.sp.wrapping_add(1);
 
-                self.setting_sp(sp)
+                self.setting_sp(sp).setting_pair_carry_flag(wrapped)
             }
 
             // 0x0B
             Instruction::DcxB => {
+                let wrapped = u16::from(self.bc()) == 0;
                 let c = self.c.wrapping_sub(1);
                 let mut b: Option<u8> = None;
                 if c == 0xff {
@@ -1466,10 +1984,11 @@ impl State8080 {
                     low: c
                 };
 
-                self.setting_bc(pair)
+                self.setting_bc(pair).setting_pair_carry_flag(wrapped)
             }
             // 0x1B
             Instruction::DcxD => {
+                let wrapped = u16::from(self.de()) == 0;
                 let e = self.e.wrapping_sub(1);
                 let mut d: Option<u8> = None;
                 if e == 0xff {
@@ -1481,10 +2000,11 @@ impl State8080 {
                     low: e
                 };
 
-                self.setting_de(pair)
+                self.setting_de(pair).setting_pair_carry_flag(wrapped)
             }
             // 0x2B
             Instruction::DcxH => {
+                let wrapped = u16::from(self.hl()) == 0;
                 let l = self.l.wrapping_sub(1);
                 let mut h: Option<u8> = None;
                 if l == 0xff {
@@ -1496,13 +2016,14 @@ impl State8080 {
                     low: l
                 };
 
-                self.setting_hl(pair)
+                self.setting_hl(pair).setting_pair_carry_flag(wrapped)
             }
             // 0x3B
             Instruction::DcxSp => {
+                let wrapped = self.sp == 0;
                 let sp = self.sp.wrapping_sub(1);
 
-                self.setting_sp(sp)
+                self.setting_sp(sp).setting_pair_carry_flag(wrapped)
             }
 
             // 0x09
@@ -1543,24 +2064,25 @@ impl State8080 {
 
             // 0x27
             Instruction::Daa => {
-                let mut correction = 0;
-                let mut cy = self.cc.contains(ConditionCodes::CY);
-                let ac = self.cc.contains(ConditionCodes::AC);
-
                 let lsb = self.a & 0x0f;
-                let msb = self.a & 0xf0;
-                if ac || lsb > 9 {
-                    correction += 6;
-                }
+                let msb = self.a >> 4;
+
+                // The low correction depends only on AC and the low nibble, the high one looks at
+                // the high nibble as it will be after the low correction carries into it.
+                let low_correction = if self.ac() || lsb > 9 { 0x06 } else { 0 };
+                let high_correction = if self.cy() || msb > 9 || (msb == 9 && lsb > 9) {
+                    0x60
+                } else {
+                    0
+                };
 
-                if cy || msb > 0x90 || (msb >= 0x90 && lsb > 9)  {
-                    correction += 0x60;
-                    cy = true;
-                }
+                let res = self.a.wrapping_add(low_correction | high_correction);
 
-                // Not entirely sure about how flags should be set here
-                self.adding(correction, false)
-                    .setting_flag(ConditionCodes::CY, cy)
+                // AC is the carry out of bit 3 of the low correction. CY is only ever set here,
+                // never cleared, and there is no subtraction adjust as on the Z80.
+                self.setting_a(res)
+                    .setting_zspac_flags(res, lsb + low_correction)
+                    .setting_flag(ConditionCodes::CY, high_correction != 0)
             }
 
 
@@ -1910,8 +2432,8 @@ impl State8080 {
 
                 new_state.setting_pc(addr)
                     .setting_sp(low_mem_addr)
-                    .setting_memory_at(return_pair.high, high_mem_addr)
-                    .setting_memory_at(return_pair.low, low_mem_addr)
+                    .writing_memory_at(return_pair.high, high_mem_addr)
+                    .writing_memory_at(return_pair.low, low_mem_addr)
             }
             // 0xC4
             Instruction::Cnz =>  {
@@ -2056,19 +2578,19 @@ impl State8080 {
             Instruction::PopB => {
                 let (new_state, popped) = self.popping();
 
-                new_state.setting_bc(popped.into())
+                new_state.setting_bc(popped)
             }
             // 0xD1
             Instruction::PopD => {
                 let (new_state, popped) = self.popping();
 
-                new_state.setting_de(popped.into())
+                new_state.setting_de(popped)
             }
             // 0xE1
             Instruction::PopH => {
                 let (new_state, popped) = self.popping();
 
-                new_state.setting_hl(popped.into())
+                new_state.setting_hl(popped)
             }
             // 0xF1
             Instruction::PopPsw => {
@@ -2119,39 +2641,443 @@ impl State8080 {
             // 0xFB
             Instruction::Ei => Self {
                 interrupt_enabled: true,
+                pins: Pins8085 {
+                    after_ei: self.variant == CpuVariant::Intel8085,
+                    ..self.pins
+                },
                 ..self
             },
 
             // 0x76
-            Instruction::Hlt => {
-                println!("HLT called!");
-                std::process::exit(0);
+            Instruction::Hlt => Self {
+                halted: true,
+                ..self
+            },
+        };
+
+        let last_cycles = match new_state.variant {
+            CpuVariant::Intel8080 => decoded.cycles,
+            CpuVariant::Intel8085 => decoded.cycles_8085,
+        };
+
+        Self {
+            last_cycles,
+            ..new_state
+        }
+    }
+
+    fn evaluating_8085_instruction(self, decoded: Decoded<Instruction8085>) -> Self {
+        let instruction = decoded.instruction;
+        #[cfg(feature = "logging")]
+        self.log_raw_instruction(instruction as u8, &instruction, decoded.size);
+
+        let new_state = match instruction {
+            // 0x08
+            Instruction8085::Dsub => {
+                // Done as two 8-bit subtractions, Z reflects the whole 16-bit result
+                let (a, h, l, b, c) = (self.a, self.h, self.l, self.b, self.c);
+                let low = self.setting_a(l).subtracting(c, false);
+                let res_l = low.a;
+                let borrow = low.cy();
+                let high = low.setting_a(h).subtracting(b, borrow);
+                let res_h = high.a;
+
+                high.setting_a(a)
+                    .setting_hl(BytePair {
+                        high: res_h,
+                        low: res_l,
+                    })
+                    .setting_z_flag(res_h | res_l)
+            }
+            // 0x10
+            Instruction8085::Arhl => {
+                let hl: u16 = self.hl().into();
+                let res = ((hl as i16) >> 1) as u16;
+
+                // Halving can't overflow
+                self.setting_hl(res.into())
+                    .setting_flag(ConditionCodes::CY, hl & 0x01 != 0)
+                    .setting_flag(ConditionCodes::V, false)
+            }
+            // 0x18
+            Instruction8085::Rdel => {
+                let de: u16 = self.de().into();
+                let res = (de << 1) | self.cy() as u16;
+
+                // V is set when the sign of DE changes, a signed overflow of doubling it
+                self.setting_de(res.into())
+                    .setting_flag(ConditionCodes::CY, de & 0x8000 != 0)
+                    .setting_flag(ConditionCodes::V, (de ^ res) & 0x8000 != 0)
+            }
+            // 0x20
+            Instruction8085::Rim => {
+                let pins = self.pins;
+                let ie = pins.ie_before_trap.unwrap_or(self.interrupt_enabled);
+                let a = (pins.sid as u8) << 7
+                    | (pins.rst75_pending as u8) << 6
+                    | (pins.rst65 as u8) << 5
+                    | (pins.rst55 as u8) << 4
+                    | (ie as u8) << 3
+                    | pins.masks;
+                let pins = Pins8085 {
+                    ie_before_trap: None,
+                    ..pins
+                };
+
+                State8080 {
+                    pins,
+                    ..self.setting_a(a)
+                }
+            }
+            // 0x28
+            Instruction8085::Ldhi => {
+                let (new_state, byte) = self.reading_next_byte();
+                let res = u16::from(new_state.hl()).wrapping_add(byte as u16);
+
+                new_state.setting_de(res.into())
+            }
+            // 0x30
+            Instruction8085::Sim => {
+                let a = self.a;
+                let mut pins = self.pins;
+                // MSE
+                if a & 0x08 != 0 {
+                    pins.masks = a & 0x07;
+                }
+                // R7.5
+                if a & 0x10 != 0 {
+                    pins.rst75_pending = false;
+                }
+                // SOE
+                if a & 0x40 != 0 {
+                    pins.sod = a & 0x80 != 0;
+                }
+
+                State8080 { pins, ..self }
+            }
+            // 0x38
+            Instruction8085::Ldsi => {
+                let (new_state, byte) = self.reading_next_byte();
+                let res = new_state.sp.wrapping_add(byte as u16);
+
+                new_state.setting_de(res.into())
+            }
+            // 0xCB
+            Instruction8085::Rstv => {
+                if self.v() {
+                    self.rst(8)
+                } else {
+                    self
+                }
+            }
+            // 0xD9
+            Instruction8085::Shlx => {
+                let de: u16 = self.de().into();
+                let (h, l) = (self.h, self.l);
+
+                self.writing_memory_at(l, de)
+                    .writing_memory_at(h, de.wrapping_add(1))
+            }
+            // 0xDD
+            Instruction8085::Jnk => {
+                let condition = !self.k();
+
+                self.jumping(condition)
+            }
+            // 0xED
+            Instruction8085::Lhlx => {
+                let de: u16 = self.de().into();
+                let pair = BytePair {
+                    low: self.memory[de as usize],
+                    high: self.memory[de.wrapping_add(1) as usize],
+                };
+
+                self.setting_hl(pair)
+            }
+            // 0xFD
+            Instruction8085::Jk => {
+                let condition = self.k();
+
+                self.jumping(condition)
             }
         };
 
         Self {
-            last_cycles: instruction.cycles(),
+            last_cycles: decoded.cycles,
             ..new_state
         }
     }
 
+    // TRAP is serviced even with interrupts disabled, the others by priority unless masked or
+    // enabled by the instruction before
+    fn pending_interrupt(&self) -> Option<InterruptPin> {
+        let pins = &self.pins;
+
+        if self.variant != CpuVariant::Intel8085 {
+            None
+        } else if pins.trap_pending {
+            Some(InterruptPin::Trap)
+        } else if !self.interrupt_enabled || pins.after_ei {
+            None
+        } else if pins.rst75_pending && pins.masks & 0x04 == 0 {
+            Some(InterruptPin::Rst75)
+        } else if pins.rst65 && pins.masks & 0x02 == 0 {
+            Some(InterruptPin::Rst65)
+        } else if pins.rst55 && pins.masks & 0x01 == 0 {
+            Some(InterruptPin::Rst55)
+        } else {
+            None
+        }
+    }
+
+    fn servicing_interrupt(self, pin: InterruptPin) -> Self {
+        let mut pins = self.pins;
+        match pin {
+            InterruptPin::Trap => {
+                pins.trap_pending = false;
+                pins.ie_before_trap = Some(self.interrupt_enabled);
+            }
+            InterruptPin::Rst75 => pins.rst75_pending = false,
+            _ => (),
+        }
+        pins.after_ei = false;
+        let pair = BytePair::from(self.pc);
+
+        State8080 {
+            pins,
+            interrupt_enabled: false,
+            halted: false,
+            last_cycles: 12,
+            ..self.pushing(pair.high, pair.low).setting_pc(pin.vector())
+        }
+    }
+
+    /// Takes an interrupt from `controller` if it requests one and interrupts are enabled, running
+    /// the CALL or RST it puts on the bus. Call it between instructions.
+    pub fn acknowledging_interrupt<C: InterruptController>(self, controller: &mut C) -> Self {
+        if !self.interrupt_enabled || !controller.interrupt_requested() {
+            return self;
+        }
+
+        let instruction = controller.acknowledging();
+        let extra_cycle = if self.variant == CpuVariant::Intel8085 { 1 } else { 0 };
+        let state = State8080 {
+            interrupt_enabled: false,
+            halted: false,
+            last_writes: MemoryWrites::default(),
+            ..self
+        };
+        let pair = BytePair::from(state.pc);
+
+        match instruction[0] {
+            0xcd => State8080 {
+                last_cycles: 17 + extra_cycle,
+                ..state.pushing(pair.high, pair.low).setting_pc(
+                    BytePair {
+                        high: instruction[2],
+                        low: instruction[1],
+                    }
+                    .into(),
+                )
+            },
+            op_code if op_code & 0xc7 == 0xc7 => State8080 {
+                last_cycles: 11 + extra_cycle,
+                ..state
+                    .pushing(pair.high, pair.low)
+                    .setting_pc((op_code & 0x38) as u16)
+            },
+            _ => State8080 {
+                last_cycles: 4,
+                ..state
+            },
+        }
+    }
+
+    // Whether `evaluating_next` would take an interrupt or idle in HLT rather than evaluate the
+    // instruction at PC, or evaluate the instruction after EI, which interrupts wait for
+    pub(crate) fn is_interrupted(&self) -> bool {
+        self.halted || self.pins.after_ei || self.pending_interrupt().is_some()
+    }
+
+    // Same as `evaluating_next` when it is not interrupted, for an instruction decoded beforehand
+    pub(crate) fn evaluating_decoded<I: IOHandler>(
+        self,
+        instruction: CachedInstruction,
+        io_handler: Option<&mut I>,
+    ) -> Self {
+        let state = State8080 {
+            last_writes: MemoryWrites::default(),
+            pc: self.pc.wrapping_add(1),
+            ..self
+        };
+
+        match instruction {
+            CachedInstruction::Intel8080(decoded) => {
+                state.evaluating_instruction(decoded, io_handler)
+            }
+            CachedInstruction::Intel8085(decoded) => state.evaluating_8085_instruction(decoded),
+        }
+    }
+
+    pub(crate) fn materializing_flags(self, pending: Option<PendingFlags>) -> Self {
+        match pending {
+            Some(pending) => State8080 {
+                cc: pending.applying(self.cc, self.variant),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    // Evaluates an instruction that `defers_flags`, without setting its flags
+    fn deferring_flags(
+        self,
+        decoded: Decoded<Instruction>,
+        pending: Option<PendingFlags>,
+    ) -> (Self, PendingFlags) {
+        let op_code = decoded.instruction as u8;
+        let state = State8080 {
+            last_writes: MemoryWrites::default(),
+            pc: self.pc.wrapping_add(1),
+            ..self
+        };
+
+        let (state, pending) = match op_code {
+            0x80..=0xbf | 0xc6 | 0xd6 | 0xe6 | 0xee | 0xf6 | 0xfe => {
+                let (state, rhs) = if op_code < 0xc0 {
+                    let rhs = state.register(op_code);
+                    (state, rhs)
+                } else {
+                    state.reading_next_byte()
+                };
+                let lhs = state.a;
+                let (operation, res) = match (op_code >> 3) & 0x07 {
+                    0 => (AluOperation::Add, lhs.wrapping_add(rhs)),
+                    2 => (AluOperation::Subtract, lhs.wrapping_sub(rhs)),
+                    4 => (AluOperation::And, lhs & rhs),
+                    5 => (AluOperation::Xor, lhs ^ rhs),
+                    6 => (AluOperation::Or, lhs | rhs),
+                    // CMP
+                    _ => (AluOperation::Subtract, lhs),
+                };
+                let pending = PendingFlags {
+                    operation,
+                    lhs,
+                    rhs,
+                };
+
+                // Every flag any of them sets, so whatever was pending can be dropped
+                (state.setting_a(res), pending)
+            }
+            // INR and DCR
+            _ => {
+                // They leave CY alone, so it has to be known first
+                let state = state.materializing_flags(pending);
+                let register = op_code >> 3;
+                let lhs = state.register(register);
+                let (operation, res) = if op_code & 0x01 == 0 {
+                    (AluOperation::Increment, lhs.wrapping_add(1))
+                } else {
+                    (AluOperation::Decrement, lhs.wrapping_sub(1))
+                };
+                let pending = PendingFlags {
+                    operation,
+                    lhs,
+                    rhs: 1,
+                };
+
+                (state.setting_register(register, res), pending)
+            }
+        };
+        let last_cycles = match state.variant {
+            CpuVariant::Intel8080 => decoded.cycles,
+            CpuVariant::Intel8085 => decoded.cycles_8085,
+        };
+
+        (
+            State8080 {
+                last_cycles,
+                ..state
+            },
+            pending,
+        )
+    }
+
+    // Same as `evaluating_decoded`, but the flags of the ALU operations are left in `pending`
+    // instead of `cc`, to be worked out when an instruction needs them
+    pub(crate) fn evaluating_with_lazy_flags<I: IOHandler>(
+        self,
+        instruction: CachedInstruction,
+        pending: &mut Option<PendingFlags>,
+        io_handler: Option<&mut I>,
+    ) -> Self {
+        if let CachedInstruction::Intel8080(decoded) = instruction {
+            let op_code = decoded.instruction as u8;
+            if defers_flags(op_code) {
+                let (state, deferred) = self.deferring_flags(decoded, pending.take());
+                *pending = Some(deferred);
+
+                return state;
+            }
+            if pending.is_none() || leaves_flags_alone(op_code, self.variant) {
+                return self.evaluating_decoded(instruction, io_handler);
+            }
+        }
+
+        let state = self.materializing_flags(pending.take());
+
+        state.evaluating_decoded(instruction, io_handler)
+    }
+
     pub fn evaluating_next<I: IOHandler>(self, io_handler: Option<&mut I>) -> Self {
-        let (mut state, op_code) = self.reading_next_byte();
+        let state = State8080 {
+            last_writes: MemoryWrites::default(),
+            ..self
+        };
+        if let Some(pin) = state.pending_interrupt() {
+            return state.servicing_interrupt(pin);
+        }
+        // A halted CPU idles without advancing until interrupted
+        if state.halted {
+            return State8080 {
+                last_cycles: 4,
+                ..state
+            };
+        }
 
-        match Instruction::try_from(op_code) {
-            Ok(instruction) => state = state.evaluating_instruction(instruction, io_handler),
-            Err(_) => println!("Not an instruction: {:#04x}", op_code),
+        let (mut state, op_code) = state.reading_next_byte();
+        state.pins.after_ei = false;
+
+        if state.variant == CpuVariant::Intel8085 {
+            if let Some(decoded) = DECODE_8085[op_code as usize] {
+                return state.evaluating_8085_instruction(decoded);
+            }
+        }
+
+        if let Some(decoded) = DECODE[op_code as usize] {
+            state = state.evaluating_instruction(decoded, io_handler);
+        } else {
+            // Runs as a NOP
+            #[cfg(feature = "std")]
+            println!("Not an instruction: {:#04x}", op_code);
         }
 
         state
     }
 
+    #[cfg(feature = "std")]
     pub fn log_current_instruction(self) {
         let (state, op_code) = self.reading_next_byte();
 
-        match Instruction::try_from(op_code) {
-            Ok(instruction) => state.log_instruction(instruction.clone()),
-            Err(_) => println!("Not an instruction: {:#04x}", op_code),
+        if state.variant == CpuVariant::Intel8085 {
+            if let Some(decoded) = DECODE_8085[op_code as usize] {
+                return state.log_raw_instruction(op_code, &decoded.instruction, decoded.size);
+            }
+        }
+
+        match DECODE[op_code as usize] {
+            Some(decoded) => state.log_instruction(decoded.instruction),
+            None => println!("Not an instruction: {:#04x}", op_code),
         }
     }
 }
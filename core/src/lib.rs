@@ -0,0 +1,11 @@
+//! The 8080, 8085 and Z80 cores, the block cache and the disassembler of emu-8080. They only need
+//! `alloc`. The `std` feature, on by default, adds loading files and printing undefined opcodes,
+//! and `logging` logs every instruction evaluated.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod blocks;
+pub mod disassembler;
+pub mod emulator;
+pub mod z80;
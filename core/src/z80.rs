@@ -0,0 +1,1440 @@
+use crate::emulator::{ConditionCodes, IOHandler, State8080};
+
+const S: u8 = 0x80;
+const Z: u8 = 0x40;
+const Y: u8 = 0x20;
+const H: u8 = 0x10;
+const X: u8 = 0x08;
+const PV: u8 = 0x04;
+const N: u8 = 0x02;
+const C: u8 = 0x01;
+
+// T-states of every unprefixed opcode. Conditional jumps, calls and returns use the count of the
+// branch not being taken, the extra states for taking it are added when executing.
+#[rustfmt::skip]
+const CYCLES: [u8; 256] = [
+    // 0x00
+    4, 10, 7, 6, 4, 4, 7, 4,
+    4, 11, 7, 6, 4, 4, 7, 4,
+    // 0x10
+    8, 10, 7, 6, 4, 4, 7, 4,
+    12, 11, 7, 6, 4, 4, 7, 4,
+    // 0x20
+    7, 10, 16, 6, 4, 4, 7, 4,
+    7, 11, 16, 6, 4, 4, 7, 4,
+    // 0x30
+    7, 10, 13, 6, 11, 11, 10, 4,
+    7, 11, 13, 6, 4, 4, 7, 4,
+    // 0x40
+    4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0x50
+    4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0x60
+    4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0x70
+    7, 7, 7, 7, 7, 7, 4, 7,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0x80
+    4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0x90
+    4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0xa0
+    4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0xb0
+    4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4,
+    // 0xc0
+    5, 10, 10, 10, 10, 11, 7, 11,
+    5, 10, 10, 4, 10, 17, 7, 11,
+    // 0xd0
+    5, 10, 10, 11, 10, 11, 7, 11,
+    5, 4, 10, 11, 10, 4, 7, 11,
+    // 0xe0
+    5, 10, 10, 19, 10, 11, 7, 11,
+    5, 4, 10, 4, 10, 4, 7, 11,
+    // 0xf0
+    5, 10, 10, 4, 10, 11, 7, 11,
+    5, 6, 10, 4, 10, 4, 7, 11,
+];
+
+/// Interrupt mode selected with IM 0, IM 1 and IM 2.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptMode {
+    #[default]
+    Mode0,
+    Mode1,
+    Mode2,
+}
+
+/// The Z80's second register set, swapped in by EX AF,AF' and EXX.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AlternateRegisters {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+}
+
+// Register used in place of HL by the current instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Index {
+    Hl,
+    Ix,
+    Iy,
+}
+
+/// Z80 core built around the 8080 one: `core` holds the main register set, the flags, the memory
+/// and IFF1 in `interrupt_enabled`, everything only the Z80 has is kept next to it.
+///
+/// The flags use every bit of `ConditionCodes` with Z80 meanings: AC is the half carry, P is
+/// parity or overflow, bit 1 is N and bits 3 and 5 are the undocumented X and Y copies.
+///
+/// I/O goes through the same `IOHandler` as the 8080. Only the low byte of the port address is
+/// passed on, and values are exchanged through the accumulator of the handed over state.
+#[derive(Default, Clone)]
+pub struct StateZ80 {
+    pub core: State8080,
+    pub alternate: AlternateRegisters,
+    pub ix: u16,
+    pub iy: u16,
+    pub i: u8,
+    pub r: u8,
+    pub iff2: bool,
+    pub interrupt_mode: InterruptMode,
+    pub halted: bool,
+    // EI takes effect after the instruction following it
+    interrupt_delay: bool,
+    last_cycles: u8,
+}
+
+impl From<State8080> for StateZ80 {
+    fn from(core: State8080) -> Self {
+        StateZ80 {
+            core,
+            ..Default::default()
+        }
+    }
+}
+
+impl StateZ80 {
+    pub fn new() -> Self {
+        State8080::new().into()
+    }
+
+    /// T-states taken by the last evaluated instruction or interrupt.
+    pub fn last_cycles(&self) -> u8 {
+        self.last_cycles
+    }
+
+    pub fn f(&self) -> u8 {
+        self.core.cc.bits()
+    }
+
+    pub fn setting_f(self, f: u8) -> Self {
+        let mut state = self;
+        state.core.cc = ConditionCodes::from_bits_truncate(f);
+
+        state
+    }
+
+    /// Accepts a maskable interrupt if IFF1 is set. `data` is the byte the interrupting device
+    /// puts on the bus: an RST opcode in mode 0 and the low byte of the vector table address in
+    /// mode 2. It is ignored in mode 1.
+    pub fn generating_interrupt(self, data: u8) -> Self {
+        if !self.core.interrupt_enabled || self.interrupt_delay {
+            return self;
+        }
+
+        let mut state = self.acknowledging();
+        state.core.interrupt_enabled = false;
+        state.iff2 = false;
+
+        let (address, cycles) = match state.interrupt_mode {
+            InterruptMode::Mode0 => ((data & 0x38) as u16, 13),
+            InterruptMode::Mode1 => (0x38, 13),
+            InterruptMode::Mode2 => {
+                let table = (state.i as u16) << 8 | (data & 0xfe) as u16;
+
+                (state.read_pair(table), 19)
+            }
+        };
+        let pc = state.core.pc;
+
+        StateZ80 {
+            last_cycles: cycles,
+            ..state.pushing(pc).setting_pc(address)
+        }
+    }
+
+    /// Non-maskable interrupt, saving IFF1 into IFF2 for RETN.
+    pub fn generating_nmi(self) -> Self {
+        let mut state = self.acknowledging();
+        state.iff2 = state.core.interrupt_enabled;
+        state.core.interrupt_enabled = false;
+        let pc = state.core.pc;
+
+        StateZ80 {
+            last_cycles: 11,
+            ..state.pushing(pc).setting_pc(0x66)
+        }
+    }
+
+    pub fn evaluating_next<I: IOHandler>(self, io_handler: Option<&mut I>) -> Self {
+        let state = StateZ80 {
+            interrupt_delay: false,
+            ..self
+        };
+
+        // A halted CPU keeps executing NOPs without advancing until interrupted
+        if state.halted {
+            return StateZ80 {
+                last_cycles: 4,
+                ..state.refreshing()
+            };
+        }
+
+        let (state, op_code) = state.fetching_op_code();
+
+        state.evaluating_op_code(op_code, Index::Hl, io_handler)
+    }
+
+    fn acknowledging(self) -> Self {
+        let state = if self.halted {
+            StateZ80 {
+                halted: false,
+                ..self
+            }
+        } else {
+            self
+        };
+
+        state.refreshing()
+    }
+
+    // R counts opcode fetches in its low 7 bits
+    fn refreshing(self) -> Self {
+        let r = (self.r & 0x80) | (self.r.wrapping_add(1) & 0x7f);
+
+        StateZ80 { r, ..self }
+    }
+
+    fn fetching_op_code(self) -> (Self, u8) {
+        let (state, op_code) = self.reading_next_byte();
+
+        (state.refreshing(), op_code)
+    }
+
+    fn reading_next_byte(self) -> (Self, u8) {
+        let mut state = self;
+        let byte = state.read(state.core.pc);
+        state.core.pc = state.core.pc.wrapping_add(1);
+
+        (state, byte)
+    }
+
+    fn reading_next_pair(self) -> (Self, u16) {
+        let (state, low) = self.reading_next_byte();
+        let (state, high) = state.reading_next_byte();
+
+        (state, (high as u16) << 8 | low as u16)
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        self.core.memory[address as usize]
+    }
+
+    fn read_pair(&self, address: u16) -> u16 {
+        (self.read(address.wrapping_add(1)) as u16) << 8 | self.read(address) as u16
+    }
+
+    fn writing(self, address: u16, value: u8) -> Self {
+        let mut state = self;
+        state.core.memory[address as usize] = value;
+
+        state
+    }
+
+    fn writing_pair(self, address: u16, value: u16) -> Self {
+        self.writing(address, value as u8)
+            .writing(address.wrapping_add(1), (value >> 8) as u8)
+    }
+
+    fn pushing(self, value: u16) -> Self {
+        let sp = self.core.sp.wrapping_sub(2);
+
+        self.setting_sp(sp).writing_pair(sp, value)
+    }
+
+    fn popping(self) -> (Self, u16) {
+        let value = self.read_pair(self.core.sp);
+        let sp = self.core.sp.wrapping_add(2);
+
+        (self.setting_sp(sp), value)
+    }
+
+    fn setting_pc(self, pc: u16) -> Self {
+        let mut state = self;
+        state.core.pc = pc;
+
+        state
+    }
+
+    fn setting_sp(self, sp: u16) -> Self {
+        let mut state = self;
+        state.core.sp = sp;
+
+        state
+    }
+
+    fn setting_a(self, a: u8) -> Self {
+        let mut state = self;
+        state.core.a = a;
+
+        state
+    }
+
+    fn setting_cycles(self, cycles: u8) -> Self {
+        StateZ80 {
+            last_cycles: cycles,
+            ..self
+        }
+    }
+
+    fn index_register(&self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.core.hl().into(),
+            Index::Ix => self.ix,
+            Index::Iy => self.iy,
+        }
+    }
+
+    fn setting_index_register(self, index: Index, value: u16) -> Self {
+        let mut state = self;
+        match index {
+            Index::Hl => {
+                state.core.h = (value >> 8) as u8;
+                state.core.l = value as u8;
+            }
+            Index::Ix => state.ix = value,
+            Index::Iy => state.iy = value,
+        }
+
+        state
+    }
+
+    // BC, DE, HL (or the index register) and SP, as encoded in bits 4 and 5 of the opcode
+    fn register_pair(&self, p: u8, index: Index) -> u16 {
+        match p {
+            0 => self.core.bc().into(),
+            1 => self.core.de().into(),
+            2 => self.index_register(index),
+            _ => self.core.sp,
+        }
+    }
+
+    fn setting_register_pair(self, p: u8, index: Index, value: u16) -> Self {
+        let mut state = self;
+        match p {
+            0 => {
+                state.core.b = (value >> 8) as u8;
+                state.core.c = value as u8;
+            }
+            1 => {
+                state.core.d = (value >> 8) as u8;
+                state.core.e = value as u8;
+            }
+            2 => return state.setting_index_register(index, value),
+            _ => state.core.sp = value,
+        }
+
+        state
+    }
+
+    // Same as `register_pair`, with AF in place of SP for PUSH and POP
+    fn stack_pair(&self, p: u8, index: Index) -> u16 {
+        match p {
+            3 => (self.core.a as u16) << 8 | self.f() as u16,
+            _ => self.register_pair(p, index),
+        }
+    }
+
+    fn setting_stack_pair(self, p: u8, index: Index, value: u16) -> Self {
+        match p {
+            3 => self.setting_a((value >> 8) as u8).setting_f(value as u8),
+            _ => self.setting_register_pair(p, index, value),
+        }
+    }
+
+    // B, C, D, E, H, L and A, with H and L standing for the halves of the index register.
+    // (HL) is handled by the callers through `memory_operand`.
+    fn register(&self, r: u8, index: Index) -> u8 {
+        match r {
+            0 => self.core.b,
+            1 => self.core.c,
+            2 => self.core.d,
+            3 => self.core.e,
+            4 => (self.index_register(index) >> 8) as u8,
+            5 => self.index_register(index) as u8,
+            _ => self.core.a,
+        }
+    }
+
+    fn setting_register(self, r: u8, index: Index, value: u8) -> Self {
+        let mut state = self;
+        match r {
+            0 => state.core.b = value,
+            1 => state.core.c = value,
+            2 => state.core.d = value,
+            3 => state.core.e = value,
+            4 => {
+                let pair = state.index_register(index) & 0x00ff | (value as u16) << 8;
+                return state.setting_index_register(index, pair);
+            }
+            5 => {
+                let pair = state.index_register(index) & 0xff00 | value as u16;
+                return state.setting_index_register(index, pair);
+            }
+            _ => state.core.a = value,
+        }
+
+        state
+    }
+
+    // Address of (HL), or of (IX+d) and (IY+d) after reading the displacement
+    fn memory_operand(self, index: Index) -> (Self, u16) {
+        match index {
+            Index::Hl => {
+                let address = self.core.hl().into();
+
+                (self, address)
+            }
+            _ => {
+                let (state, displacement) = self.reading_next_byte();
+                let address = state
+                    .index_register(index)
+                    .wrapping_add(displacement as i8 as u16);
+
+                (state, address)
+            }
+        }
+    }
+
+    // NZ, Z, NC, C, PO, PE, P and M
+    fn condition(&self, y: u8) -> bool {
+        let f = self.f();
+        let flag = [Z, C, PV, S][(y >> 1) as usize];
+
+        (f & flag != 0) == (y & 1 != 0)
+    }
+
+    fn alu(self, operation: u8, value: u8) -> Self {
+        let a = self.core.a;
+        let carry = self.f() & C != 0;
+        let (res, f) = match operation {
+            0 => add8(a, value, false),
+            1 => add8(a, value, carry),
+            2 => sub8(a, value, false),
+            3 => sub8(a, value, carry),
+            4 => (a & value, sz53p(a & value) | H),
+            5 => (a ^ value, sz53p(a ^ value)),
+            6 => (a | value, sz53p(a | value)),
+            _ => {
+                // CP takes X and Y from the operand instead of the discarded result
+                let (_, f) = sub8(a, value, false);
+
+                (a, (f & !(X | Y)) | (value & (X | Y)))
+            }
+        };
+
+        self.setting_a(res).setting_f(f)
+    }
+
+    fn incrementing(self, value: u8) -> (Self, u8) {
+        let res = value.wrapping_add(1);
+        let mut f = (self.f() & C) | sz53(res);
+        if value & 0x0f == 0x0f {
+            f |= H;
+        }
+        if value == 0x7f {
+            f |= PV;
+        }
+
+        (self.setting_f(f), res)
+    }
+
+    fn decrementing(self, value: u8) -> (Self, u8) {
+        let res = value.wrapping_sub(1);
+        let mut f = (self.f() & C) | sz53(res) | N;
+        if value & 0x0f == 0 {
+            f |= H;
+        }
+        if value == 0x80 {
+            f |= PV;
+        }
+
+        (self.setting_f(f), res)
+    }
+
+    // RLC, RRC, RL, RR, SLA, SRA, the undocumented SLL and SRL
+    fn rotating(self, operation: u8, value: u8) -> (Self, u8) {
+        let carry_in = self.f() & C;
+        let (res, carry) = match operation {
+            0 => (value.rotate_left(1), value >> 7),
+            1 => (value.rotate_right(1), value & 0x01),
+            2 => (value << 1 | carry_in, value >> 7),
+            3 => (value >> 1 | carry_in << 7, value & 0x01),
+            4 => (value << 1, value >> 7),
+            5 => (value >> 1 | (value & 0x80), value & 0x01),
+            6 => (value << 1 | 0x01, value >> 7),
+            _ => (value >> 1, value & 0x01),
+        };
+
+        (self.setting_f(sz53p(res) | carry), res)
+    }
+
+    // RLCA, RRCA, RLA and RRA leave S, Z and P/V alone
+    fn rotating_a(self, operation: u8) -> Self {
+        let f = self.f();
+        let a = self.core.a;
+        let (new_state, res) = self.rotating(operation, a);
+        let f = (f & (S | Z | PV)) | (res & (X | Y)) | (new_state.f() & C);
+
+        new_state.setting_a(res).setting_f(f)
+    }
+
+    fn adding_pair(self, index: Index, value: u16) -> Self {
+        let lhs = self.index_register(index);
+        let res = lhs.wrapping_add(value);
+        let mut f = (self.f() & (S | Z | PV)) | ((res >> 8) as u8 & (X | Y));
+        if (lhs ^ value ^ res) & 0x1000 != 0 {
+            f |= H;
+        }
+        if res < lhs {
+            f |= C;
+        }
+
+        self.setting_index_register(index, res).setting_f(f)
+    }
+
+    // ADC HL,rr and SBC HL,rr, which unlike ADD set every flag
+    fn adding_pair_with_carry(self, value: u16, subtract: bool) -> Self {
+        let lhs: u16 = self.core.hl().into();
+        let carry = (self.f() & C) as u32;
+        let (res, carry_out, overflow) = if subtract {
+            let res = (lhs as u32).wrapping_sub(value as u32).wrapping_sub(carry);
+            let overflow = (lhs ^ value) & (lhs ^ res as u16) & 0x8000 != 0;
+
+            (res as u16, res > 0xffff, overflow)
+        } else {
+            let res = lhs as u32 + value as u32 + carry;
+            let overflow = (lhs ^ res as u16) & (value ^ res as u16) & 0x8000 != 0;
+
+            (res as u16, res > 0xffff, overflow)
+        };
+
+        let mut f = (res >> 8) as u8 & (S | X | Y);
+        if res == 0 {
+            f |= Z;
+        }
+        if (lhs ^ value ^ res) & 0x1000 != 0 {
+            f |= H;
+        }
+        if overflow {
+            f |= PV;
+        }
+        if subtract {
+            f |= N;
+        }
+        if carry_out {
+            f |= C;
+        }
+
+        self.setting_index_register(Index::Hl, res).setting_f(f)
+    }
+
+    // Unlike the 8080, DAA also corrects after a subtraction, telling them apart with N
+    fn decimal_adjusting(self) -> Self {
+        let a = self.core.a;
+        let f = self.f();
+        let mut correction = 0;
+        let mut carry = f & C;
+        if f & H != 0 || a & 0x0f > 9 {
+            correction |= 0x06;
+        }
+        if carry != 0 || a > 0x99 {
+            correction |= 0x60;
+            carry = C;
+        }
+
+        let (res, half_carry) = if f & N != 0 {
+            (a.wrapping_sub(correction), f & H != 0 && a & 0x0f < 6)
+        } else {
+            (a.wrapping_add(correction), a & 0x0f > 9)
+        };
+        let mut f = sz53p(res) | (f & N) | carry;
+        if half_carry {
+            f |= H;
+        }
+
+        self.setting_a(res).setting_f(f)
+    }
+
+    fn testing_bit(self, bit: u8, value: u8, xy_source: u8) -> Self {
+        let set = value & (1 << bit) != 0;
+        let mut f = (self.f() & C) | H | (xy_source & (X | Y));
+        if !set {
+            f |= Z | PV;
+        }
+        if bit == 7 && set {
+            f |= S;
+        }
+
+        self.setting_f(f)
+    }
+
+    fn jumping_relative(self, condition: bool) -> (Self, bool) {
+        let (state, displacement) = self.reading_next_byte();
+
+        if condition {
+            let pc = state.core.pc.wrapping_add(displacement as i8 as u16);
+
+            (state.setting_pc(pc), true)
+        } else {
+            (state, false)
+        }
+    }
+
+    fn input<I: IOHandler>(self, port: u8, io_handler: Option<&mut I>) -> (Self, u8) {
+        match io_handler {
+            Some(handler) => {
+                let mut state = self;
+                let a = state.core.a;
+                let core = core::mem::take(&mut state.core);
+                state.core = handler.inp(core, port);
+                let value = state.core.a;
+
+                (state.setting_a(a), value)
+            }
+            // Nothing drives the data bus without a handler
+            None => (self, 0xff),
+        }
+    }
+
+    fn output<I: IOHandler>(self, port: u8, value: u8, io_handler: Option<&mut I>) -> Self {
+        match io_handler {
+            Some(handler) => {
+                let a = self.core.a;
+                let mut state = self.setting_a(value);
+                let core = core::mem::take(&mut state.core);
+                state.core = handler.out(core, port);
+
+                state.setting_a(a)
+            }
+            None => self,
+        }
+    }
+
+    fn evaluating_op_code<I: IOHandler>(
+        self,
+        op_code: u8,
+        index: Index,
+        io_handler: Option<&mut I>,
+    ) -> Self {
+        let x = op_code >> 6;
+        let y = (op_code >> 3) & 0x07;
+        let z = op_code & 0x07;
+        let p = y >> 1;
+        let q = y & 0x01;
+
+        // (IX+d) and (IY+d) operands take extra time to compute the address
+        let indexed_memory = index != Index::Hl
+            && match x {
+                0 => (4..=6).contains(&z) && y == 6,
+                1 => (y == 6) != (z == 6),
+                2 => z == 6,
+                _ => false,
+            };
+        let cycles = match (indexed_memory, op_code) {
+            (false, _) => CYCLES[op_code as usize],
+            (true, 0x36) => CYCLES[op_code as usize] + 5,
+            (true, _) => CYCLES[op_code as usize] + 8,
+        };
+        let state = self.setting_cycles(cycles);
+
+        match (x, z) {
+            (0, 0) => match y {
+                // NOP
+                0 => state,
+                // EX AF,AF'
+                1 => {
+                    let mut state = state;
+                    let (a, f) = (state.core.a, state.f());
+                    state.core.a = state.alternate.a;
+                    let alternate_f = state.alternate.f;
+                    state.alternate.a = a;
+                    state.alternate.f = f;
+
+                    state.setting_f(alternate_f)
+                }
+                // DJNZ d
+                2 => {
+                    let mut state = state;
+                    state.core.b = state.core.b.wrapping_sub(1);
+                    let condition = state.core.b != 0;
+                    let (state, taken) = state.jumping_relative(condition);
+
+                    state.adding_branch_cycles(taken, 5)
+                }
+                // JR d
+                3 => state.jumping_relative(true).0,
+                // JR cc,d
+                _ => {
+                    let condition = state.condition(y - 4);
+                    let (state, taken) = state.jumping_relative(condition);
+
+                    state.adding_branch_cycles(taken, 5)
+                }
+            },
+            (0, 1) => {
+                if q == 0 {
+                    // LD rr,nn
+                    let (state, value) = state.reading_next_pair();
+
+                    state.setting_register_pair(p, index, value)
+                } else {
+                    // ADD HL,rr
+                    let value = state.register_pair(p, index);
+
+                    state.adding_pair(index, value)
+                }
+            }
+            (0, 2) => match (q, p) {
+                // LD (BC),A and LD (DE),A
+                (0, 0) | (0, 1) => {
+                    let address = state.register_pair(p, index);
+                    let a = state.core.a;
+
+                    state.writing(address, a)
+                }
+                // LD (nn),HL
+                (0, 2) => {
+                    let (state, address) = state.reading_next_pair();
+                    let value = state.index_register(index);
+
+                    state.writing_pair(address, value)
+                }
+                // LD (nn),A
+                (0, _) => {
+                    let (state, address) = state.reading_next_pair();
+                    let a = state.core.a;
+
+                    state.writing(address, a)
+                }
+                // LD A,(BC) and LD A,(DE)
+                (_, 0) | (_, 1) => {
+                    let value = state.read(state.register_pair(p, index));
+
+                    state.setting_a(value)
+                }
+                // LD HL,(nn)
+                (_, 2) => {
+                    let (state, address) = state.reading_next_pair();
+                    let value = state.read_pair(address);
+
+                    state.setting_index_register(index, value)
+                }
+                // LD A,(nn)
+                _ => {
+                    let (state, address) = state.reading_next_pair();
+                    let value = state.read(address);
+
+                    state.setting_a(value)
+                }
+            },
+            // INC rr and DEC rr
+            (0, 3) => {
+                let value = state.register_pair(p, index);
+                let res = if q == 0 {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+
+                state.setting_register_pair(p, index, res)
+            }
+            // INC r and DEC r
+            (0, 4) | (0, 5) => {
+                if y == 6 {
+                    let (state, address) = state.memory_operand(index);
+                    let value = state.read(address);
+                    let (state, res) = if z == 4 {
+                        state.incrementing(value)
+                    } else {
+                        state.decrementing(value)
+                    };
+
+                    state.writing(address, res)
+                } else {
+                    let value = state.register(y, index);
+                    let (state, res) = if z == 4 {
+                        state.incrementing(value)
+                    } else {
+                        state.decrementing(value)
+                    };
+
+                    state.setting_register(y, index, res)
+                }
+            }
+            // LD r,n
+            (0, 6) => {
+                if y == 6 {
+                    let (state, address) = state.memory_operand(index);
+                    let (state, value) = state.reading_next_byte();
+
+                    state.writing(address, value)
+                } else {
+                    let (state, value) = state.reading_next_byte();
+
+                    state.setting_register(y, index, value)
+                }
+            }
+            (0, _) => match y {
+                // RLCA, RRCA, RLA and RRA
+                0..=3 => state.rotating_a(y),
+                // DAA
+                4 => state.decimal_adjusting(),
+                // CPL
+                5 => {
+                    let a = !state.core.a;
+                    let f = (state.f() & (S | Z | PV | C)) | H | N | (a & (X | Y));
+
+                    state.setting_a(a).setting_f(f)
+                }
+                // SCF
+                6 => {
+                    let f = (state.f() & (S | Z | PV)) | C | (state.core.a & (X | Y));
+
+                    state.setting_f(f)
+                }
+                // CCF
+                _ => {
+                    let f = state.f();
+                    let carry = if f & C != 0 { H } else { C };
+                    let f = (f & (S | Z | PV)) | carry | (state.core.a & (X | Y));
+
+                    state.setting_f(f)
+                }
+            },
+
+            // HALT
+            (1, 6) if y == 6 => StateZ80 {
+                halted: true,
+                ..state
+            },
+            // LD r,r'. With an (IX+d) operand the other register is always H or L, not half of
+            // the index register.
+            (1, _) => {
+                if z == 6 {
+                    let (state, address) = state.memory_operand(index);
+                    let value = state.read(address);
+
+                    state.setting_register(y, Index::Hl, value)
+                } else if y == 6 {
+                    let (state, address) = state.memory_operand(index);
+                    let value = state.register(z, Index::Hl);
+
+                    state.writing(address, value)
+                } else {
+                    let value = state.register(z, index);
+
+                    state.setting_register(y, index, value)
+                }
+            }
+
+            // ALU A,r
+            (2, _) => {
+                if z == 6 {
+                    let (state, address) = state.memory_operand(index);
+                    let value = state.read(address);
+
+                    state.alu(y, value)
+                } else {
+                    let value = state.register(z, index);
+
+                    state.alu(y, value)
+                }
+            }
+
+            // RET cc
+            (3, 0) => {
+                if state.condition(y) {
+                    let (state, address) = state.popping();
+
+                    state.setting_pc(address).adding_branch_cycles(true, 6)
+                } else {
+                    state
+                }
+            }
+            (3, 1) => match (q, p) {
+                // POP rr
+                (0, _) => {
+                    let (state, value) = state.popping();
+
+                    state.setting_stack_pair(p, index, value)
+                }
+                // RET
+                (_, 0) => {
+                    let (state, address) = state.popping();
+
+                    state.setting_pc(address)
+                }
+                // EXX
+                (_, 1) => {
+                    let mut state = state;
+                    let core = &mut state.core;
+                    let alternate = &mut state.alternate;
+                    core::mem::swap(&mut core.b, &mut alternate.b);
+                    core::mem::swap(&mut core.c, &mut alternate.c);
+                    core::mem::swap(&mut core.d, &mut alternate.d);
+                    core::mem::swap(&mut core.e, &mut alternate.e);
+                    core::mem::swap(&mut core.h, &mut alternate.h);
+                    core::mem::swap(&mut core.l, &mut alternate.l);
+
+                    state
+                }
+                // JP (HL)
+                (_, 2) => {
+                    let address = state.index_register(index);
+
+                    state.setting_pc(address)
+                }
+                // LD SP,HL
+                _ => {
+                    let sp = state.index_register(index);
+
+                    state.setting_sp(sp)
+                }
+            },
+            // JP cc,nn
+            (3, 2) => {
+                let condition = state.condition(y);
+                let (state, address) = state.reading_next_pair();
+
+                if condition {
+                    state.setting_pc(address)
+                } else {
+                    state
+                }
+            }
+            (3, 3) => match y {
+                // JP nn
+                0 => {
+                    let (state, address) = state.reading_next_pair();
+
+                    state.setting_pc(address)
+                }
+                1 => match index {
+                    Index::Hl => {
+                        let (state, op_code) = state.fetching_op_code();
+
+                        state.evaluating_bit_op_code(op_code)
+                    }
+                    _ => {
+                        let (state, address) = state.memory_operand(index);
+                        let (state, op_code) = state.reading_next_byte();
+
+                        state.evaluating_indexed_bit_op_code(op_code, address)
+                    }
+                },
+                // OUT (n),A
+                2 => {
+                    let (state, port) = state.reading_next_byte();
+                    let a = state.core.a;
+
+                    state.output(port, a, io_handler)
+                }
+                // IN A,(n)
+                3 => {
+                    let (state, port) = state.reading_next_byte();
+                    let (state, value) = state.input(port, io_handler);
+
+                    state.setting_a(value)
+                }
+                // EX (SP),HL
+                4 => {
+                    let sp = state.core.sp;
+                    let value = state.read_pair(sp);
+                    let hl = state.index_register(index);
+
+                    state
+                        .writing_pair(sp, hl)
+                        .setting_index_register(index, value)
+                }
+                // EX DE,HL, which always uses HL
+                5 => {
+                    let mut state = state;
+                    core::mem::swap(&mut state.core.d, &mut state.core.h);
+                    core::mem::swap(&mut state.core.e, &mut state.core.l);
+
+                    state
+                }
+                // DI
+                6 => {
+                    let mut state = state;
+                    state.core.interrupt_enabled = false;
+                    state.iff2 = false;
+
+                    state
+                }
+                // EI
+                _ => {
+                    let mut state = state;
+                    state.core.interrupt_enabled = true;
+                    state.iff2 = true;
+                    state.interrupt_delay = true;
+
+                    state
+                }
+            },
+            // CALL cc,nn
+            (3, 4) => {
+                let condition = state.condition(y);
+
+                state.calling(condition)
+            }
+            (3, 5) => match (q, p) {
+                // PUSH rr
+                (0, _) => {
+                    let value = state.stack_pair(p, index);
+
+                    state.pushing(value)
+                }
+                // CALL nn
+                (_, 0) => state.calling(true).setting_cycles(17),
+                (_, 2) => {
+                    let (state, op_code) = state.fetching_op_code();
+
+                    state.evaluating_extended_op_code(op_code, io_handler)
+                }
+                // DD and FD, the last of a run of prefixes wins
+                _ => {
+                    let (state, op_code) = state.fetching_op_code();
+                    let index = if p == 1 { Index::Ix } else { Index::Iy };
+                    let state = state.evaluating_op_code(op_code, index, io_handler);
+                    let cycles = state.last_cycles + 4;
+
+                    state.setting_cycles(cycles)
+                }
+            },
+            // ALU A,n
+            (3, 6) => {
+                let (state, value) = state.reading_next_byte();
+
+                state.alu(y, value)
+            }
+            // RST
+            _ => {
+                let pc = state.core.pc;
+
+                state.pushing(pc).setting_pc((y * 8) as u16)
+            }
+        }
+    }
+
+    fn adding_branch_cycles(self, taken: bool, cycles: u8) -> Self {
+        if taken {
+            let cycles = self.last_cycles + cycles;
+
+            self.setting_cycles(cycles)
+        } else {
+            self
+        }
+    }
+
+    fn calling(self, condition: bool) -> Self {
+        let (state, address) = self.reading_next_pair();
+
+        if condition {
+            let pc = state.core.pc;
+
+            state
+                .pushing(pc)
+                .setting_pc(address)
+                .adding_branch_cycles(true, 7)
+        } else {
+            state
+        }
+    }
+
+    // CB prefixed rotates, shifts and bit operations on registers and (HL)
+    fn evaluating_bit_op_code(self, op_code: u8) -> Self {
+        let x = op_code >> 6;
+        let y = (op_code >> 3) & 0x07;
+        let z = op_code & 0x07;
+
+        if z == 6 {
+            let address: u16 = self.core.hl().into();
+            let value = self.read(address);
+            let cycles = if x == 1 { 12 } else { 15 };
+            let state = self.setting_cycles(cycles);
+
+            match x {
+                1 => state.testing_bit(y, value, (address >> 8) as u8),
+                _ => {
+                    let (state, res) = state.evaluating_bit_operation(x, y, value);
+
+                    state.writing(address, res)
+                }
+            }
+        } else {
+            let value = self.register(z, Index::Hl);
+            let state = self.setting_cycles(8);
+
+            match x {
+                1 => state.testing_bit(y, value, value),
+                _ => {
+                    let (state, res) = state.evaluating_bit_operation(x, y, value);
+
+                    state.setting_register(z, Index::Hl, res)
+                }
+            }
+        }
+    }
+
+    // DDCB and FDCB always operate on (IX+d) or (IY+d). Except for BIT, the result is also
+    // copied into the register the opcode would otherwise name.
+    fn evaluating_indexed_bit_op_code(self, op_code: u8, address: u16) -> Self {
+        let x = op_code >> 6;
+        let y = (op_code >> 3) & 0x07;
+        let z = op_code & 0x07;
+        let value = self.read(address);
+
+        if x == 1 {
+            return self
+                .setting_cycles(16)
+                .testing_bit(y, value, (address >> 8) as u8);
+        }
+
+        let (state, res) = self
+            .setting_cycles(19)
+            .evaluating_bit_operation(x, y, value);
+        let state = state.writing(address, res);
+
+        if z == 6 {
+            state
+        } else {
+            state.setting_register(z, Index::Hl, res)
+        }
+    }
+
+    fn evaluating_bit_operation(self, x: u8, y: u8, value: u8) -> (Self, u8) {
+        match x {
+            0 => self.rotating(y, value),
+            2 => (self, value & !(1 << y)),
+            _ => (self, value | (1 << y)),
+        }
+    }
+
+    // ED prefixed instructions. Undefined ones act as an 8 T-state NOP.
+    fn evaluating_extended_op_code<I: IOHandler>(
+        self,
+        op_code: u8,
+        io_handler: Option<&mut I>,
+    ) -> Self {
+        let x = op_code >> 6;
+        let y = (op_code >> 3) & 0x07;
+        let z = op_code & 0x07;
+        let p = y >> 1;
+        let q = y & 0x01;
+
+        match (x, z) {
+            // IN r,(C), where (HL) only sets the flags
+            (1, 0) => {
+                let port = self.core.c;
+                let (state, value) = self.input(port, io_handler);
+                let f = (state.f() & C) | sz53p(value);
+                let state = state.setting_f(f).setting_cycles(12);
+
+                if y == 6 {
+                    state
+                } else {
+                    state.setting_register(y, Index::Hl, value)
+                }
+            }
+            // OUT (C),r, where (HL) outputs 0
+            (1, 1) => {
+                let port = self.core.c;
+                let value = if y == 6 {
+                    0
+                } else {
+                    self.register(y, Index::Hl)
+                };
+
+                self.output(port, value, io_handler).setting_cycles(12)
+            }
+            // SBC HL,rr and ADC HL,rr
+            (1, 2) => {
+                let value = self.register_pair(p, Index::Hl);
+
+                self.adding_pair_with_carry(value, q == 0)
+                    .setting_cycles(15)
+            }
+            (1, 3) => {
+                let (state, address) = self.reading_next_pair();
+                let state = state.setting_cycles(20);
+
+                if q == 0 {
+                    // LD (nn),rr
+                    let value = state.register_pair(p, Index::Hl);
+
+                    state.writing_pair(address, value)
+                } else {
+                    // LD rr,(nn)
+                    let value = state.read_pair(address);
+
+                    state.setting_register_pair(p, Index::Hl, value)
+                }
+            }
+            // NEG
+            (1, 4) => {
+                let (res, f) = sub8(0, self.core.a, false);
+
+                self.setting_a(res).setting_f(f).setting_cycles(8)
+            }
+            // RETN and RETI
+            (1, 5) => {
+                let (mut state, address) = self.popping();
+                state.core.interrupt_enabled = state.iff2;
+
+                state.setting_pc(address).setting_cycles(14)
+            }
+            // IM 0, IM 1 and IM 2
+            (1, 6) => {
+                let interrupt_mode = match y & 0x03 {
+                    2 => InterruptMode::Mode1,
+                    3 => InterruptMode::Mode2,
+                    _ => InterruptMode::Mode0,
+                };
+
+                StateZ80 {
+                    interrupt_mode,
+                    ..self.setting_cycles(8)
+                }
+            }
+            (1, 7) => match y {
+                // LD I,A
+                0 => StateZ80 {
+                    i: self.core.a,
+                    ..self.setting_cycles(9)
+                },
+                // LD R,A
+                1 => StateZ80 {
+                    r: self.core.a,
+                    ..self.setting_cycles(9)
+                },
+                // LD A,I and LD A,R
+                2 | 3 => {
+                    let value = if y == 2 { self.i } else { self.r };
+                    let mut f = (self.f() & C) | sz53(value);
+                    if self.iff2 {
+                        f |= PV;
+                    }
+
+                    self.setting_a(value).setting_f(f).setting_cycles(9)
+                }
+                // RRD and RLD
+                4 | 5 => {
+                    let address: u16 = self.core.hl().into();
+                    let value = self.read(address);
+                    let a = self.core.a;
+                    let (res_a, res) = if y == 4 {
+                        (a & 0xf0 | value & 0x0f, a << 4 | value >> 4)
+                    } else {
+                        (a & 0xf0 | value >> 4, value << 4 | a & 0x0f)
+                    };
+                    let f = (self.f() & C) | sz53p(res_a);
+
+                    self.writing(address, res)
+                        .setting_a(res_a)
+                        .setting_f(f)
+                        .setting_cycles(18)
+                }
+                _ => self.setting_cycles(8),
+            },
+            (2, 0..=3) if y >= 4 => self.evaluating_block_op_code(y, z, io_handler),
+            _ => self.setting_cycles(8),
+        }
+    }
+
+    // LDI, CPI, INI and OUTI, their decrementing and their repeating versions. The repeating ones
+    // run one iteration per step and rewind PC until done.
+    fn evaluating_block_op_code<I: IOHandler>(
+        self,
+        y: u8,
+        z: u8,
+        io_handler: Option<&mut I>,
+    ) -> Self {
+        let decrementing = y & 0x01 != 0;
+        let repeating = y >= 6;
+        let step = if decrementing { 0xffff } else { 0x0001 };
+        let hl: u16 = self.core.hl().into();
+        let next_hl = hl.wrapping_add(step);
+
+        let (state, repeat) = match z {
+            // LDI
+            0 => {
+                let value = self.read(hl);
+                let de: u16 = self.core.de().into();
+                let bc = u16::from(self.core.bc()).wrapping_sub(1);
+                let n = value.wrapping_add(self.core.a);
+                let mut f = (self.f() & (S | Z | C)) | (n & X) | ((n << 4) & Y);
+                if bc != 0 {
+                    f |= PV;
+                }
+
+                let state = self
+                    .writing(de, value)
+                    .setting_register_pair(1, Index::Hl, de.wrapping_add(step))
+                    .setting_register_pair(0, Index::Hl, bc)
+                    .setting_index_register(Index::Hl, next_hl)
+                    .setting_f(f);
+
+                (state, bc != 0)
+            }
+            // CPI
+            1 => {
+                let value = self.read(hl);
+                let a = self.core.a;
+                let res = a.wrapping_sub(value);
+                let bc = u16::from(self.core.bc()).wrapping_sub(1);
+                let half_borrow = (a ^ value ^ res) & 0x10 != 0;
+                let n = res.wrapping_sub(half_borrow as u8);
+                let mut f = (self.f() & C) | N | (res & S) | (n & X) | ((n << 4) & Y);
+                if res == 0 {
+                    f |= Z;
+                }
+                if half_borrow {
+                    f |= H;
+                }
+                if bc != 0 {
+                    f |= PV;
+                }
+
+                let state = self
+                    .setting_register_pair(0, Index::Hl, bc)
+                    .setting_index_register(Index::Hl, next_hl)
+                    .setting_f(f);
+
+                (state, bc != 0 && res != 0)
+            }
+            // INI
+            2 => {
+                let port = self.core.c;
+                let (state, value) = self.input(port, io_handler);
+                let mut state = state.writing(hl, value);
+                state.core.b = state.core.b.wrapping_sub(1);
+                let b = state.core.b;
+                let f = block_io_flags(b, value);
+
+                (
+                    state
+                        .setting_index_register(Index::Hl, next_hl)
+                        .setting_f(f),
+                    b != 0,
+                )
+            }
+            // OUTI
+            _ => {
+                let value = self.read(hl);
+                let mut state = self;
+                state.core.b = state.core.b.wrapping_sub(1);
+                let b = state.core.b;
+                let port = state.core.c;
+                let f = block_io_flags(b, value);
+
+                (
+                    state
+                        .output(port, value, io_handler)
+                        .setting_index_register(Index::Hl, next_hl)
+                        .setting_f(f),
+                    b != 0,
+                )
+            }
+        };
+
+        if repeating && repeat {
+            let pc = state.core.pc.wrapping_sub(2);
+
+            state.setting_pc(pc).setting_cycles(21)
+        } else {
+            state.setting_cycles(16)
+        }
+    }
+}
+
+fn sz53(value: u8) -> u8 {
+    let f = value & (S | X | Y);
+
+    if value == 0 {
+        f | Z
+    } else {
+        f
+    }
+}
+
+fn sz53p(value: u8) -> u8 {
+    if value.count_ones() & 1 == 0 {
+        sz53(value) | PV
+    } else {
+        sz53(value)
+    }
+}
+
+fn add8(a: u8, b: u8, carry: bool) -> (u8, u8) {
+    let sum = a as u16 + b as u16 + carry as u16;
+    let res = sum as u8;
+    let mut f = sz53(res);
+    if (a ^ b ^ res) & 0x10 != 0 {
+        f |= H;
+    }
+    if (a ^ res) & (b ^ res) & 0x80 != 0 {
+        f |= PV;
+    }
+    if sum > 0xff {
+        f |= C;
+    }
+
+    (res, f)
+}
+
+fn sub8(a: u8, b: u8, carry: bool) -> (u8, u8) {
+    let difference = a as i16 - b as i16 - carry as i16;
+    let res = difference as u8;
+    let mut f = sz53(res) | N;
+    if (a ^ b ^ res) & 0x10 != 0 {
+        f |= H;
+    }
+    if (a ^ b) & (a ^ res) & 0x80 != 0 {
+        f |= PV;
+    }
+    if difference < 0 {
+        f |= C;
+    }
+
+    (res, f)
+}
+
+// INI, IND, OUTI and OUTD report on the decremented B, with N copied from bit 7 of the value
+fn block_io_flags(b: u8, value: u8) -> u8 {
+    sz53(b) | ((value >> 6) & N)
+}
@@ -8,16 +8,288 @@
 #include <stdlib.h>
 
 
+/**
+ * The flags of the 8080, by their bit in the flags byte.
+ */
+typedef enum Flag8080 {
+  Flag8080_S = 128,
+  Flag8080_Z = 64,
+  Flag8080_Ac = 16,
+  Flag8080_P = 4,
+  Flag8080_Cy = 1,
+} Flag8080;
+
+/**
+ * The registers of the 8080, `Flags` being the flags byte as PUSH PSW stores it. 8-bit registers
+ * are read and written through the low byte.
+ */
+typedef enum Register8080 {
+  Register8080_A,
+  Register8080_B,
+  Register8080_C,
+  Register8080_D,
+  Register8080_E,
+  Register8080_H,
+  Register8080_L,
+  Register8080_Flags,
+  Register8080_Sp,
+  Register8080_Pc,
+} Register8080;
+
+/**
+ * Result of the functions that can fail, 0 on success.
+ */
+typedef enum Status8080 {
+  Status8080_Ok = 0,
+  /**
+   * A required pointer was null.
+   */
+  Status8080_NullPointer,
+  /**
+   * The path is not valid UTF-8.
+   */
+  Status8080_InvalidPath,
+  /**
+   * The file could not be read.
+   */
+  Status8080_ReadFailed,
+  /**
+   * The data does not fit in memory at the given address.
+   */
+  Status8080_OutOfRange,
+} Status8080;
+
 typedef struct State8080 State8080;
 
+/**
+ * IO handler made of C functions, each called with `user_data`. Any of them can be left null,
+ * IN then reading 0xff. Memory stays in the CPU state, so there is no callback for reads:
+ * `state8080_read_memory` sees what the CPU reads.
+ */
+typedef struct IoHandler8080 {
+  void *user_data;
+  /**
+   * Serves IN from `port`, returning the byte read.
+   */
+  uint8_t (*input)(void *user_data, uint8_t port);
+  /**
+   * Serves OUT of `value` to `port`.
+   */
+  void (*output)(void *user_data, uint8_t port, uint8_t value);
+  /**
+   * Called after an instruction for every byte it wrote to memory, in write order.
+   */
+  void (*memory_write)(void *user_data, uint16_t address, uint8_t value);
+} IoHandler8080;
+
+/**
+ * Prefer `state8080_step`, which does not move the state.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library. It is consumed by this call
+ * and must not be used afterwards; use the returned pointer instead.
+ */
 struct State8080 *state8080_evaluating_next(struct State8080 *ptr);
 
+/**
+ * Same as `state8080_evaluating_next`, with IN and OUT served by `io`.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library. It is consumed by this call
+ * and must not be used afterwards; use the returned pointer instead. `io` must be null or point
+ * to a valid `IoHandler8080` whose callbacks can be called with its `user_data`. Nothing is
+ * consumed when either is null.
+ */
+struct State8080 *state8080_evaluating_next_with_io(struct State8080 *ptr,
+                                                    struct IoHandler8080 *io);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+bool state8080_flag(const struct State8080 *ptr, enum Flag8080 flag);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a pointer previously returned by this library that has not been freed.
+ */
 void state8080_free(struct State8080 *ptr);
 
+/**
+ * Runs RST `int_num`, whether interrupts are enabled or not, and wakes the CPU up from HLT.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+void state8080_generating_interrupt(struct State8080 *ptr, uint16_t int_num);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+bool state8080_halted(const struct State8080 *ptr);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+bool state8080_interrupt_enabled(const struct State8080 *ptr);
+
+/**
+ * Cycles taken by the last evaluated instruction.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+uint8_t state8080_last_cycles(const struct State8080 *ptr);
+
+/**
+ * Copies `len` bytes from `buffer` into memory at `index`. Nothing is loaded unless all of it
+ * fits.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library, and `buffer` must point to
+ * `len` readable bytes.
+ */
+enum Status8080 state8080_load_buffer(struct State8080 *ptr,
+                                      const uint8_t *buffer,
+                                      size_t len,
+                                      uint16_t index);
+
+/**
+ * Loads the file at `path` into memory at `index`. Nothing is loaded unless all of it fits.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library, and `path` null or a valid
+ * NUL-terminated string.
+ */
+enum Status8080 state8080_load_file(struct State8080 *ptr, const char *path, uint16_t index);
+
+/**
+ * Loads `len` bytes from `buffer` at `index`, dropping whatever does not fit below 0x10000.
+ * Prefer `state8080_load_buffer`, which does not move the state.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library and `buffer` must point to
+ * `len` readable bytes. On success `ptr` is consumed and must not be used afterwards.
+ */
+struct State8080 *state8080_loading_buffer_into_memory_at(struct State8080 *ptr,
+                                                          const uint8_t *buffer,
+                                                          size_t len,
+                                                          uint16_t index);
+
+/**
+ * Prefer `state8080_load_file`, which does not move the state and tells why it failed.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library, and `path` null or a valid
+ * NUL-terminated string. On success `ptr` is consumed and must not be used afterwards. On
+ * failure null is returned and `ptr` is left as it was.
+ */
 struct State8080 *state8080_loading_file_into_memory_at(struct State8080 *ptr,
                                                         const char *path,
                                                         uint16_t index);
 
 struct State8080 *state8080_new(void);
 
+/**
+ * Copies `len` bytes of memory from `address` into `buffer`, stopping at the end of memory.
+ * Returns the number of bytes copied.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library, and `buffer` null or a
+ * pointer to `len` writable bytes.
+ */
+size_t state8080_read_memory(const struct State8080 *ptr,
+                             uint16_t address,
+                             uint8_t *buffer,
+                             size_t len);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+uint16_t state8080_register(const struct State8080 *ptr, enum Register8080 register_);
+
+/**
+ * Evaluates instructions for at least `cycles` cycles, without IO. Stops early when the CPU
+ * halts, since only an interrupt can wake it up. Returns the number of cycles run.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+uint64_t state8080_running(struct State8080 *ptr, uint64_t cycles);
+
+/**
+ * Same as `state8080_running`, with IN and OUT served by `io` unless it is null.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library, and `io` null or a pointer to
+ * a valid `IoHandler8080` whose callbacks can be called with its `user_data`.
+ */
+uint64_t state8080_running_with_io(struct State8080 *ptr,
+                                   struct IoHandler8080 *io,
+                                   uint64_t cycles);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+void state8080_set_flag(struct State8080 *ptr, enum Flag8080 flag, bool value);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+void state8080_set_interrupt_enabled(struct State8080 *ptr, bool value);
+
+/**
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library.
+ */
+void state8080_set_register(struct State8080 *ptr, enum Register8080 register_, uint16_t value);
+
+/**
+ * Evaluates the next instruction in place, with IN and OUT served by `io` unless it is null.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library, and `io` null or a pointer to
+ * a valid `IoHandler8080` whose callbacks can be called with its `user_data`.
+ */
+enum Status8080 state8080_step(struct State8080 *ptr, struct IoHandler8080 *io);
+
+/**
+ * Copies `len` bytes from `buffer` into memory at `address`, stopping at the end of memory.
+ * Returns the number of bytes copied.
+ *
+ * # Safety
+ *
+ * `ptr` must be null or a valid pointer returned by this library, and `buffer` null or a
+ * pointer to `len` readable bytes.
+ */
+size_t state8080_write_memory(struct State8080 *ptr,
+                              uint16_t address,
+                              const uint8_t *buffer,
+                              size_t len);
+
 #endif /* emu_8080_h */
@@ -0,0 +1,164 @@
+//! Port decoding for machines built from several devices.
+//!
+//! An `IoBus` is the `IOHandler` of the CPU and passes every IN and OUT to the device mapped on
+//! the port. Devices get the full port number and decode the address lines they use themselves,
+//! so a device mapped on an aligned range sees the same ports as if it was wired alone.
+//!
+//! ```
+//! use emu_8080::bus::IoBus;
+//! use emu_8080::devices::{i8251::I8251, i8253::I8253};
+//!
+//! let bus = IoBus::new()
+//!     .mapping(0x00..=0x01, Box::new(I8251::new()))
+//!     .mapping(0x10..=0x13, Box::new(I8253::new()));
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::emulator::{IOHandler, State8080};
+
+/// What happens on ports no device is mapped on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Unmapped {
+    /// Reads return 0xff, like the pulled up data bus, and writes are ignored.
+    #[default]
+    OpenBus,
+    /// Like `OpenBus`, printing every access.
+    Logging,
+    /// Like `OpenBus`, recording every access as a `BusFault`.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    In,
+    Out(u8),
+}
+
+/// An access to a port no device is mapped on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusFault {
+    pub port: u8,
+    pub access: Access,
+    /// Address of the IN or OUT instruction.
+    pub pc: u16,
+}
+
+impl fmt::Display for BusFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::In => write!(f, "IN from unmapped port {:#04x}", self.port)?,
+            Access::Out(value) => write!(
+                f,
+                "OUT of {:#04x} to unmapped port {:#04x}",
+                value, self.port
+            )?,
+        }
+
+        write!(f, " at {:#06x}", self.pc)
+    }
+}
+
+impl Error for BusFault {}
+
+struct Mapping {
+    ports: RangeInclusive<u8>,
+    device: Box<dyn IOHandler>,
+}
+
+#[derive(Default)]
+pub struct IoBus {
+    mappings: Vec<Mapping>,
+    unmapped: Unmapped,
+    faults: Vec<BusFault>,
+}
+
+impl IoBus {
+    pub fn new() -> Self {
+        IoBus::default()
+    }
+
+    /// Maps `device` on `ports`. Where mappings overlap the one mapped first gets the access.
+    pub fn mapping(self, ports: RangeInclusive<u8>, device: Box<dyn IOHandler>) -> Self {
+        let mut bus = self;
+        bus.mappings.push(Mapping { ports, device });
+
+        bus
+    }
+
+    pub fn mapping_port(self, port: u8, device: Box<dyn IOHandler>) -> Self {
+        self.mapping(port..=port, device)
+    }
+
+    pub fn setting_unmapped(self, unmapped: Unmapped) -> Self {
+        IoBus { unmapped, ..self }
+    }
+
+    pub fn is_mapped(&self, port: u8) -> bool {
+        self.mappings
+            .iter()
+            .any(|mapping| mapping.ports.contains(&port))
+    }
+
+    /// Accesses to unmapped ports so far, with `Unmapped::Error`.
+    pub fn faults(&self) -> &[BusFault] {
+        &self.faults
+    }
+
+    pub fn clearing_faults(self) -> Self {
+        IoBus {
+            faults: vec![],
+            ..self
+        }
+    }
+
+    fn device(&mut self, port: u8) -> Option<&mut Box<dyn IOHandler>> {
+        self.mappings
+            .iter_mut()
+            .find(|mapping| mapping.ports.contains(&port))
+            .map(|mapping| &mut mapping.device)
+    }
+
+    fn unmapped_access(&mut self, fault: BusFault) {
+        match self.unmapped {
+            Unmapped::OpenBus => (),
+            Unmapped::Logging => println!("{}", fault),
+            Unmapped::Error => self.faults.push(fault),
+        }
+    }
+}
+
+impl IOHandler for IoBus {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        if let Some(device) = self.device(port) {
+            return device.inp(state, port);
+        }
+
+        // The instruction was already read
+        let pc = state.pc.wrapping_sub(2);
+        self.unmapped_access(BusFault {
+            port,
+            access: Access::In,
+            pc,
+        });
+
+        state.setting_a(0xff)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        if let Some(device) = self.device(port) {
+            return device.out(state, port);
+        }
+
+        let pc = state.pc.wrapping_sub(2);
+        self.unmapped_access(BusFault {
+            port,
+            access: Access::Out(state.a),
+            pc,
+        });
+
+        state
+    }
+}
@@ -0,0 +1,309 @@
+//! Intel 8251 USART.
+//!
+//! The C/D pin is wired to A0, so even ports read and write data and odd ports write the mode and
+//! command words and read the status. Characters take the time set by the mode word and the
+//! clock period to shift in and out, counted in CPU cycles passed to `ticking`. With a clock
+//! period of 0 characters move as soon as the port is accessed.
+//!
+//! Synchronous mode is accepted, with its sync characters, but characters are sent and received
+//! as in asynchronous mode without start and stop bits.
+
+use crate::emulator::{IOHandler, State8080};
+use crate::serial::SerialHost;
+
+pub const STATUS_TX_READY: u8 = 0x01;
+pub const STATUS_RX_READY: u8 = 0x02;
+pub const STATUS_TX_EMPTY: u8 = 0x04;
+pub const STATUS_PARITY_ERROR: u8 = 0x08;
+pub const STATUS_OVERRUN_ERROR: u8 = 0x10;
+pub const STATUS_FRAMING_ERROR: u8 = 0x20;
+pub const STATUS_SYNC_DETECT: u8 = 0x40;
+pub const STATUS_DSR: u8 = 0x80;
+
+pub const COMMAND_TX_ENABLE: u8 = 0x01;
+pub const COMMAND_DTR: u8 = 0x02;
+pub const COMMAND_RX_ENABLE: u8 = 0x04;
+pub const COMMAND_SEND_BREAK: u8 = 0x08;
+pub const COMMAND_ERROR_RESET: u8 = 0x10;
+pub const COMMAND_RTS: u8 = 0x20;
+pub const COMMAND_INTERNAL_RESET: u8 = 0x40;
+pub const COMMAND_HUNT: u8 = 0x80;
+
+/// Which control word the next write to the control port is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Expecting {
+    #[default]
+    Mode,
+    SyncCharacter(u8),
+    Command,
+}
+
+/// A character moving through a shift register.
+#[derive(Debug, Default, Clone, Copy)]
+struct Shifting {
+    byte: u8,
+    remaining: u32,
+}
+
+#[derive(Default)]
+pub struct I8251 {
+    host: Option<Box<dyn SerialHost>>,
+    clock_period: u32,
+    expecting: Expecting,
+    mode: u8,
+    command: u8,
+    tx_buffer: Option<u8>,
+    transmitting: Option<Shifting>,
+    receiving: Option<Shifting>,
+    rx_buffer: Option<u8>,
+    errors: u8,
+    dsr: bool,
+}
+
+impl I8251 {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn attaching(self, host: Box<dyn SerialHost>) -> Self {
+        I8251 {
+            host: Some(host),
+            ..self
+        }
+    }
+
+    /// Sets the period of the TxC and RxC clocks in CPU cycles. At 2 MHz with a 16x baud rate
+    /// factor, 9600 baud is a period of 13 cycles.
+    pub fn setting_clock_period(self, cycles: u32) -> Self {
+        I8251 {
+            clock_period: cycles,
+            ..self
+        }
+        .ticking(0)
+    }
+
+    /// Sets the DSR input, which is only reflected in the status.
+    pub fn setting_dsr(self, dsr: bool) -> Self {
+        I8251 { dsr, ..self }
+    }
+
+    pub fn status(&self) -> u8 {
+        let mut status = self.errors;
+        if self.tx_buffer.is_none() {
+            status |= STATUS_TX_READY;
+        }
+        if self.rx_buffer.is_some() {
+            status |= STATUS_RX_READY;
+        }
+        if self.tx_buffer.is_none() && self.transmitting.is_none() {
+            status |= STATUS_TX_EMPTY;
+        }
+        if self.dsr {
+            status |= STATUS_DSR;
+        }
+
+        status
+    }
+
+    /// The TxRDY pin, which unlike the status bit also needs the transmitter enabled.
+    pub fn tx_ready(&self) -> bool {
+        self.command & COMMAND_TX_ENABLE != 0 && self.tx_buffer.is_none()
+    }
+
+    /// The RxRDY pin.
+    pub fn rx_ready(&self) -> bool {
+        self.rx_buffer.is_some()
+    }
+
+    pub fn dtr(&self) -> bool {
+        self.command & COMMAND_DTR != 0
+    }
+
+    pub fn rts(&self) -> bool {
+        self.command & COMMAND_RTS != 0
+    }
+
+    fn is_synchronous(&self) -> bool {
+        self.mode & 0x03 == 0
+    }
+
+    fn data_bits(&self) -> u32 {
+        5 + ((self.mode >> 2) & 0x03) as u32
+    }
+
+    fn data_mask(&self) -> u8 {
+        (0xff_u16 >> (8 - self.data_bits())) as u8
+    }
+
+    // Cycles to shift a whole character in or out
+    fn character_cycles(&self) -> u32 {
+        let parity = ((self.mode >> 4) & 0x01) as u32;
+        if self.is_synchronous() {
+            return self.clock_period * (self.data_bits() + parity);
+        }
+
+        let factor = match self.mode & 0x03 {
+            0x01 => 1,
+            0x02 => 16,
+            _ => 64,
+        };
+        // Counted in half bits for 1.5 stop bits
+        let stop_halves = match self.mode >> 6 {
+            0x02 => 3,
+            0x03 => 4,
+            _ => 2,
+        };
+
+        self.clock_period * factor * (2 * (1 + self.data_bits() + parity) + stop_halves) / 2
+    }
+
+    /// Reads the data (even) or status (odd) port.
+    pub fn reading_port(self, port: u8) -> (Self, u8) {
+        let mut usart = self.ticking(0);
+
+        if port & 0x01 == 1 {
+            let status = usart.status();
+            (usart, status)
+        } else {
+            let byte = usart.rx_buffer.take().unwrap_or(0);
+            (usart.ticking(0), byte)
+        }
+    }
+
+    /// Writes the data (even) or control (odd) port.
+    pub fn writing_port(self, port: u8, value: u8) -> Self {
+        let mut usart = self;
+
+        if port & 0x01 == 0 {
+            // Writing over a character that has not been sent yet loses it
+            usart.tx_buffer = Some(value & usart.data_mask());
+            return usart.ticking(0);
+        }
+
+        match usart.expecting {
+            Expecting::Mode => {
+                usart.mode = value;
+                usart.expecting = if usart.is_synchronous() {
+                    Expecting::SyncCharacter(0)
+                } else {
+                    Expecting::Command
+                };
+            }
+            // Sync characters are only needed to find character boundaries on a real line
+            Expecting::SyncCharacter(index) => {
+                // Bit 7 of the mode selects a single sync character
+                let single = usart.mode & 0x80 != 0;
+                usart.expecting = if index == 0 && !single {
+                    Expecting::SyncCharacter(1)
+                } else {
+                    Expecting::Command
+                };
+            }
+            Expecting::Command if value & COMMAND_INTERNAL_RESET != 0 => {
+                let I8251 {
+                    host,
+                    clock_period,
+                    dsr,
+                    ..
+                } = usart;
+                usart = I8251 {
+                    host,
+                    clock_period,
+                    dsr,
+                    ..Default::default()
+                };
+            }
+            Expecting::Command => {
+                if value & COMMAND_ERROR_RESET != 0 {
+                    usart.errors = 0;
+                }
+                // The command is not latched for the error reset
+                usart.command = value & !COMMAND_ERROR_RESET;
+            }
+        }
+
+        usart.ticking(0)
+    }
+
+    /// Advances the transmitter and receiver by `cycles` CPU cycles.
+    pub fn ticking(self, cycles: u32) -> Self {
+        let mut usart = self;
+
+        let mut elapsed = cycles;
+        loop {
+            if usart.transmitting.is_none() && usart.command & COMMAND_TX_ENABLE != 0 {
+                if let Some(byte) = usart.tx_buffer.take() {
+                    usart.transmitting = Some(Shifting {
+                        byte,
+                        remaining: usart.character_cycles(),
+                    });
+                }
+            }
+            match usart.transmitting {
+                Some(shifting) if shifting.remaining <= elapsed => {
+                    elapsed -= shifting.remaining;
+                    usart.transmitting = None;
+                    if let Some(host) = usart.host.as_mut() {
+                        host.transmit(shifting.byte);
+                    }
+                }
+                Some(shifting) => {
+                    usart.transmitting = Some(Shifting {
+                        remaining: shifting.remaining - elapsed,
+                        ..shifting
+                    });
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let mut elapsed = cycles;
+        loop {
+            // Without a clock a character only comes in once the last one was read
+            let waiting = usart.clock_period == 0 && usart.rx_buffer.is_some();
+            if usart.receiving.is_none() && usart.command & COMMAND_RX_ENABLE != 0 && !waiting {
+                let received = usart.host.as_mut().and_then(|host| host.receive());
+                usart.receiving = received.map(|byte| Shifting {
+                    byte,
+                    remaining: usart.character_cycles(),
+                });
+            }
+            match usart.receiving {
+                Some(shifting) if shifting.remaining <= elapsed => {
+                    elapsed -= shifting.remaining;
+                    usart.receiving = None;
+                    if usart.rx_buffer.is_some() {
+                        usart.errors |= STATUS_OVERRUN_ERROR;
+                    }
+                    usart.rx_buffer = Some(shifting.byte & usart.data_mask());
+                }
+                Some(shifting) => {
+                    usart.receiving = Some(Shifting {
+                        remaining: shifting.remaining - elapsed,
+                        ..shifting
+                    });
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        usart
+    }
+}
+
+impl IOHandler for I8251 {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let (usart, value) = std::mem::take(self).reading_port(port);
+        *self = usart;
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        *self = std::mem::take(self).writing_port(port, state.a);
+
+        state
+    }
+}
@@ -0,0 +1,451 @@
+//! Intel 8253 programmable interval timer.
+//!
+//! A1 and A0 select the port: counters 0 to 2 and the control word on 3. Counters are clocked by
+//! passing CPU cycles to `ticking`, each counter's CLK input running at a configurable number of
+//! CPU cycles per pulse. Gates are high unless set otherwise.
+//!
+//! In mode 3 the count read back goes down by two on every clock like on the chip, with the
+//! first step after a reload shortened for odd counts.
+
+use crate::emulator::{IOHandler, State8080};
+
+pub const COUNTERS: usize = 3;
+
+/// Which bytes of the count are read and written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Lsb,
+    Msb,
+    /// Least significant byte first, then the most significant one.
+    Word,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+    mode: u8,
+    access: Access,
+    bcd: bool,
+    // Initial count, 0 counting 65536 in binary and 10000 in BCD
+    reload: u16,
+    count: u16,
+    output: bool,
+    gate: bool,
+    counting: bool,
+    // A count was written and goes into the counter on the next clock
+    load_pending: bool,
+    // Rising edge on the gate, waiting for the next clock
+    triggered: bool,
+    latch: Option<u16>,
+    reading_msb: bool,
+    writing_msb: bool,
+    written_lsb: u8,
+    clock_period: u32,
+    cycles: u32,
+    rose: bool,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter {
+            mode: 0,
+            access: Access::Word,
+            bcd: false,
+            reload: 0,
+            count: 0,
+            output: false,
+            gate: true,
+            counting: false,
+            load_pending: false,
+            triggered: false,
+            latch: None,
+            reading_msb: false,
+            writing_msb: false,
+            written_lsb: 0,
+            clock_period: 1,
+            cycles: 0,
+            rose: false,
+        }
+    }
+}
+
+impl Counter {
+    fn decremented(&self, count: u16) -> u16 {
+        if !self.bcd {
+            return count.wrapping_sub(1);
+        }
+        if count == 0 {
+            return 0x9999;
+        }
+
+        // Every nibble that borrowed went to 0xf and has to become 9
+        let mut count = count - 1;
+        for shift in [0, 4, 8].iter() {
+            if (count >> shift) & 0x0f == 0x0f {
+                count -= 6 << shift;
+            }
+        }
+
+        count
+    }
+
+    fn setting_output(self, output: bool) -> Self {
+        Counter {
+            output,
+            rose: self.rose || (output && !self.output),
+            ..self
+        }
+    }
+
+    fn programming(self, mode: u8, access: Access, bcd: bool) -> Self {
+        Counter {
+            // Modes 6 and 7 are modes 2 and 3
+            mode: if mode > 5 { mode - 4 } else { mode },
+            access,
+            bcd,
+            counting: false,
+            load_pending: false,
+            triggered: false,
+            latch: None,
+            reading_msb: false,
+            writing_msb: false,
+            // Setting the initial output is not an edge that can interrupt
+            output: mode != 0,
+            ..self
+        }
+    }
+
+    fn latching(self) -> Self {
+        Counter {
+            latch: self.latch.or(Some(self.count)),
+            ..self
+        }
+    }
+
+    fn reading(self) -> (Self, u8) {
+        let mut counter = self;
+        let value = counter.latch.unwrap_or(counter.count);
+
+        let byte = match counter.access {
+            Access::Lsb => {
+                counter.latch = None;
+                value as u8
+            }
+            Access::Msb => {
+                counter.latch = None;
+                (value >> 8) as u8
+            }
+            Access::Word if counter.reading_msb => {
+                counter.latch = None;
+                counter.reading_msb = false;
+                (value >> 8) as u8
+            }
+            Access::Word => {
+                counter.reading_msb = true;
+                value as u8
+            }
+        };
+
+        (counter, byte)
+    }
+
+    fn writing(self, byte: u8) -> Self {
+        let mut counter = self;
+
+        let reload = match counter.access {
+            Access::Lsb => byte as u16,
+            Access::Msb => (byte as u16) << 8,
+            Access::Word if counter.writing_msb => {
+                counter.writing_msb = false;
+                (byte as u16) << 8 | counter.written_lsb as u16
+            }
+            Access::Word => {
+                counter.writing_msb = true;
+                counter.written_lsb = byte;
+                // Mode 0 stops counting while the count is half written
+                if counter.mode == 0 {
+                    counter.counting = false;
+                    return counter.setting_output(false);
+                }
+                return counter;
+            }
+        };
+        counter.reload = reload;
+
+        match counter.mode {
+            0 => {
+                counter.load_pending = true;
+                counter.counting = false;
+                counter.setting_output(false)
+            }
+            4 => {
+                counter.load_pending = true;
+                counter.counting = false;
+                counter
+            }
+            // A new count only takes effect on the next reload once counting
+            2 | 3 if !counter.counting => {
+                counter.load_pending = true;
+                counter
+            }
+            _ => counter,
+        }
+    }
+
+    fn setting_gate(self, gate: bool) -> Self {
+        let rising = gate && !self.gate;
+        let counter = Counter {
+            gate,
+            triggered: self.triggered || rising,
+            ..self
+        };
+
+        // The rate and square wave generators hold their output high while the gate is low
+        match counter.mode {
+            2 | 3 if !gate => counter.setting_output(true),
+            _ => counter,
+        }
+    }
+
+    // A single pulse on CLK
+    fn clocking(self) -> Self {
+        let mut counter = self;
+
+        let loading = match counter.mode {
+            1 | 5 => counter.triggered,
+            2 | 3 => counter.load_pending || (counter.triggered && counter.counting),
+            _ => counter.load_pending,
+        };
+        if loading {
+            counter.count = counter.reload;
+            counter.counting = true;
+            counter.load_pending = false;
+            counter.triggered = false;
+            return match counter.mode {
+                1 => counter.setting_output(false),
+                2 | 3 => counter.setting_output(true),
+                _ => counter,
+            };
+        }
+
+        let gated = matches!(counter.mode, 0 | 2 | 3 | 4) && !counter.gate;
+        if !counter.counting || gated {
+            return counter;
+        }
+
+        match counter.mode {
+            0 | 1 => {
+                counter.count = counter.decremented(counter.count);
+                if counter.count == 0 {
+                    counter = counter.setting_output(true);
+                }
+                counter
+            }
+            2 => {
+                if counter.count == 1 {
+                    counter.count = counter.reload;
+                    counter.setting_output(true)
+                } else {
+                    counter.count = counter.decremented(counter.count);
+                    let low = counter.count == 1;
+                    counter.setting_output(!low)
+                }
+            }
+            3 => {
+                let step = if counter.count & 0x01 == 0 {
+                    2
+                } else if counter.output {
+                    1
+                } else {
+                    3
+                };
+                for _ in 0..step {
+                    counter.count = counter.decremented(counter.count);
+                    if counter.count == 0 {
+                        break;
+                    }
+                }
+                if counter.count == 0 {
+                    counter.count = counter.reload;
+                    let output = !counter.output;
+                    counter = counter.setting_output(output);
+                }
+                counter
+            }
+            _ => {
+                // Modes 4 and 5 strobe the output low for a single clock
+                if !counter.output {
+                    counter.counting = false;
+                    return counter.setting_output(true);
+                }
+                counter.count = counter.decremented(counter.count);
+                if counter.count == 0 {
+                    counter = counter.setting_output(false);
+                }
+                counter
+            }
+        }
+    }
+
+    fn ticking(self, cycles: u32) -> Self {
+        let mut counter = self;
+        counter.cycles += cycles;
+        while counter.cycles >= counter.clock_period.max(1) {
+            counter.cycles -= counter.clock_period.max(1);
+            counter = counter.clocking();
+        }
+
+        counter
+    }
+}
+
+// Counters are picked by the caller, so a bad index is a bug in the machine wiring them
+fn checked(counter: usize) -> usize {
+    assert!(counter < COUNTERS, "the 8253 has no counter {}", counter);
+
+    counter
+}
+
+#[derive(Debug, Clone)]
+pub struct I8253 {
+    counters: [Counter; COUNTERS],
+    interrupt: Option<(usize, u16)>,
+    interrupt_pending: bool,
+}
+
+impl Default for I8253 {
+    fn default() -> Self {
+        I8253::new()
+    }
+}
+
+impl I8253 {
+    pub fn new() -> Self {
+        I8253 {
+            counters: [Counter::default(); COUNTERS],
+            interrupt: None,
+            interrupt_pending: false,
+        }
+    }
+
+    /// Sets the number of CPU cycles per pulse on the CLK input of `counter`. Counters are
+    /// numbered 0 to 2, any other number panics here and in the other methods taking one.
+    pub fn setting_clock_period(self, counter: usize, cycles: u32) -> Self {
+        let mut timer = self;
+        timer.counters[checked(counter)].clock_period = cycles;
+
+        timer
+    }
+
+    pub fn setting_gate(self, counter: usize, level: bool) -> Self {
+        let mut timer = self;
+        let counter = checked(counter);
+        timer.counters[counter] = timer.counters[counter].setting_gate(level);
+
+        timer
+    }
+
+    pub fn output(&self, counter: usize) -> bool {
+        self.counters[checked(counter)].output
+    }
+
+    /// Connects the output of `counter` to the CPU interrupt line, raising RST `int_num` on every
+    /// rising edge.
+    pub fn wiring_interrupt(self, counter: usize, int_num: u16) -> Self {
+        I8253 {
+            interrupt: Some((checked(counter), int_num)),
+            ..self
+        }
+    }
+
+    /// Reads a counter, port 3 reads nothing on the 8253.
+    pub fn reading_port(self, port: u8) -> (Self, u8) {
+        let mut timer = self;
+        let index = (port & 0x03) as usize;
+        if index == COUNTERS {
+            return (timer, 0xff);
+        }
+
+        let (counter, byte) = timer.counters[index].reading();
+        timer.counters[index] = counter;
+
+        (timer, byte)
+    }
+
+    /// Writes a count or the control word.
+    pub fn writing_port(self, port: u8, value: u8) -> Self {
+        let mut timer = self;
+        let index = (port & 0x03) as usize;
+        if index < COUNTERS {
+            timer.counters[index] = timer.counters[index].writing(value);
+            return timer;
+        }
+
+        let selected = (value >> 6) as usize;
+        // Select 3 is the read-back command of the 8254
+        if selected == COUNTERS {
+            return timer;
+        }
+        let counter = timer.counters[selected];
+        timer.counters[selected] = match (value >> 4) & 0x03 {
+            0 => counter.latching(),
+            access => counter.programming(
+                (value >> 1) & 0x07,
+                match access {
+                    1 => Access::Lsb,
+                    2 => Access::Msb,
+                    _ => Access::Word,
+                },
+                value & 0x01 != 0,
+            ),
+        };
+
+        timer
+    }
+
+    /// Advances every counter by `cycles` CPU cycles.
+    pub fn ticking(self, cycles: u32) -> Self {
+        let mut timer = self;
+        for counter in timer.counters.iter_mut() {
+            *counter = counter.ticking(cycles);
+        }
+
+        if let Some((index, _)) = timer.interrupt {
+            timer.interrupt_pending |= timer.counters[index].rose;
+        }
+        for counter in timer.counters.iter_mut() {
+            counter.rose = false;
+        }
+
+        timer
+    }
+
+    /// Advances by the cycles of the instruction `state` just evaluated, then raises the wired
+    /// interrupt if its counter output went high. The interrupt stays pending while interrupts
+    /// are disabled.
+    pub fn ticking_after(self, state: State8080) -> (Self, State8080) {
+        let mut timer = self.ticking(state.last_cycles() as u32);
+
+        match timer.interrupt {
+            Some((_, int_num)) if timer.interrupt_pending && state.interrupt_enabled => {
+                timer.interrupt_pending = false;
+                (timer, state.generating_interrupt(int_num))
+            }
+            _ => (timer, state),
+        }
+    }
+}
+
+impl IOHandler for I8253 {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let (timer, value) = std::mem::take(self).reading_port(port);
+        *self = timer;
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        *self = std::mem::take(self).writing_port(port, state.a);
+
+        state
+    }
+}
@@ -0,0 +1,429 @@
+//! Intel 8255 programmable peripheral interface.
+//!
+//! A1 and A0 select port A, B, C or the control word on 3. Whatever is wired to the pins of a
+//! port is a `PortHost`: the 8255 reads it when the CPU reads an input port, and tells it the new
+//! levels of the output pins when they change. In modes 1 and 2 the peripheral latches input
+//! with `strobing` and takes output with `acknowledging`, and port C carries the handshake lines.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::emulator::{IOHandler, State8080};
+
+const MODE_SET: u8 = 0x80;
+// Mode word after reset, every port an input in mode 0
+const RESET_MODE: u8 = 0x9b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    A,
+    B,
+    C,
+}
+
+/// The peripheral side of a port.
+pub trait PortHost {
+    /// Levels on the pins when the 8255 reads them, pins nothing drives float high.
+    fn reading(&mut self) -> u8 {
+        0xff
+    }
+
+    /// Levels the 8255 now drives on the pins, input pins reading as 0.
+    fn writing(&mut self, _value: u8) {}
+}
+
+impl<H: PortHost> PortHost for Rc<RefCell<H>> {
+    fn reading(&mut self) -> u8 {
+        self.borrow_mut().reading()
+    }
+
+    fn writing(&mut self, value: u8) {
+        self.borrow_mut().writing(value)
+    }
+}
+
+/// Pins driven to a fixed level by the host, remembering every output of the 8255.
+#[derive(Debug, Default, Clone)]
+pub struct Pins {
+    pub input: u8,
+    pub outputs: Vec<u8>,
+}
+
+impl Pins {
+    pub fn with_input(input: u8) -> Self {
+        Pins {
+            input,
+            outputs: vec![],
+        }
+    }
+
+    pub fn last_output(&self) -> Option<u8> {
+        self.outputs.last().copied()
+    }
+}
+
+impl PortHost for Pins {
+    fn reading(&mut self) -> u8 {
+        self.input
+    }
+
+    fn writing(&mut self, value: u8) {
+        self.outputs.push(value);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Handshake {
+    input_full: bool,
+    output_full: bool,
+    // The peripheral took the output, interrupting until the CPU writes again
+    acknowledged: bool,
+}
+
+pub struct I8255 {
+    control: u8,
+    latches: [u8; 3],
+    // Input latched by STB in modes 1 and 2, for ports A and B
+    strobed: [u8; 2],
+    handshakes: [Handshake; 2],
+    hosts: [Option<Box<dyn PortHost>>; 3],
+    // Last levels told to the host of port C
+    c_pins: u8,
+}
+
+impl Default for I8255 {
+    fn default() -> Self {
+        I8255::new()
+    }
+}
+
+impl I8255 {
+    pub fn new() -> Self {
+        I8255 {
+            control: RESET_MODE,
+            latches: [0; 3],
+            strobed: [0; 2],
+            handshakes: [Handshake::default(); 2],
+            hosts: [None, None, None],
+            c_pins: 0,
+        }
+    }
+
+    pub fn attaching(self, port: Port, host: Box<dyn PortHost>) -> Self {
+        let mut ppi = self;
+        ppi.hosts[port as usize] = Some(host);
+
+        ppi
+    }
+
+    fn group_a_mode(&self) -> u8 {
+        if self.control & 0x40 != 0 {
+            2
+        } else {
+            (self.control >> 5) & 0x01
+        }
+    }
+
+    fn group_b_mode(&self) -> u8 {
+        (self.control >> 2) & 0x01
+    }
+
+    fn is_input(&self, port: Port) -> bool {
+        match port {
+            Port::A => self.control & 0x10 != 0,
+            Port::B => self.control & 0x02 != 0,
+            Port::C => false,
+        }
+    }
+
+    // Whether the CPU reads from and writes to the peripheral through `port` in a handshake mode
+    fn handshaking(&self, port: Port) -> (bool, bool) {
+        let mode = match port {
+            Port::A => self.group_a_mode(),
+            Port::B => self.group_b_mode(),
+            Port::C => 0,
+        };
+
+        match mode {
+            0 => (false, false),
+            1 => (self.is_input(port), !self.is_input(port)),
+            _ => (true, true),
+        }
+    }
+
+    // Port C bits that are inputs in mode 0
+    fn c_inputs(&self) -> u8 {
+        let upper = if self.control & 0x08 != 0 { 0xf0 } else { 0 };
+        let lower = if self.control & 0x01 != 0 { 0x0f } else { 0 };
+
+        upper | lower
+    }
+
+    // Port C bits taken by the handshake lines, and the ones among them the 8255 drives
+    fn handshake_lines(&self) -> (u8, u8) {
+        let (a_input, a_output) = self.handshaking(Port::A);
+        let (b_input, b_output) = self.handshaking(Port::B);
+
+        let mut lines = 0;
+        let mut driven = 0;
+        if a_input {
+            lines |= 0x38;
+            driven |= 0x28;
+        }
+        if a_output {
+            lines |= 0xc8;
+            driven |= 0x88;
+        }
+        if b_input || b_output {
+            lines |= 0x07;
+            driven |= 0x03;
+        }
+
+        (lines, driven)
+    }
+
+    // INTE flip-flops are set and reset through port C bits
+    fn interrupt_enabled(&self, port: Port, input: bool) -> bool {
+        let bit = match (port, input) {
+            (Port::A, true) => 0x10,
+            (Port::A, false) => 0x40,
+            _ => 0x04,
+        };
+
+        self.latches[2] & bit != 0
+    }
+
+    /// Level of INTR for port A or B, which is always low in mode 0.
+    pub fn interrupt_requested(&self, port: Port) -> bool {
+        if port == Port::C {
+            return false;
+        }
+
+        let (input, output) = self.handshaking(port);
+        let handshake = self.handshakes[port as usize];
+
+        (input && handshake.input_full && self.interrupt_enabled(port, true))
+            || (output && handshake.acknowledged && self.interrupt_enabled(port, false))
+    }
+
+    // Handshake lines and INTE bits as port C reads them
+    fn handshake_status(&self) -> u8 {
+        let mut status = 0;
+
+        let (input, output) = self.handshaking(Port::A);
+        let handshake = self.handshakes[0];
+        if self.interrupt_requested(Port::A) {
+            status |= 0x08;
+        }
+        if input && handshake.input_full {
+            status |= 0x20;
+        }
+        // OBF is active low
+        if output && !handshake.output_full {
+            status |= 0x80;
+        }
+
+        let (input, output) = self.handshaking(Port::B);
+        let handshake = self.handshakes[1];
+        if self.interrupt_requested(Port::B) {
+            status |= 0x01;
+        }
+        if (input && handshake.input_full) || (output && !handshake.output_full) {
+            status |= 0x02;
+        }
+
+        status | (self.latches[2] & 0x54)
+    }
+
+    /// Levels the 8255 drives on the pins of `port`. Input pins read as 0, and so does port A in
+    /// mode 2 which only drives its pins while ACK is low.
+    pub fn output(&self, port: Port) -> u8 {
+        match port {
+            Port::C => {
+                let (lines, driven) = self.handshake_lines();
+                let outputs = !self.c_inputs() & !lines;
+
+                (self.latches[2] & outputs) | (self.handshake_status() & driven)
+            }
+            _ if self.is_input(port) || self.handshaking(port) == (true, true) => 0,
+            _ => self.latches[port as usize],
+        }
+    }
+
+    fn host_reading(&mut self, port: Port) -> u8 {
+        match &mut self.hosts[port as usize] {
+            Some(host) => host.reading(),
+            None => 0xff,
+        }
+    }
+
+    fn host_writing(&mut self, port: Port, value: u8) {
+        if let Some(host) = &mut self.hosts[port as usize] {
+            host.writing(value);
+        }
+    }
+
+    // Tells the host of port C about changed pins
+    fn updating_c(self) -> Self {
+        let mut ppi = self;
+        let pins = ppi.output(Port::C);
+        if pins != ppi.c_pins {
+            ppi.c_pins = pins;
+            ppi.host_writing(Port::C, pins);
+        }
+
+        ppi
+    }
+
+    /// Reads a port, port 3 reads nothing.
+    pub fn reading_port(self, port: u8) -> (Self, u8) {
+        let mut ppi = self;
+
+        let value = match port & 0x03 {
+            0 => ppi.reading_data(Port::A),
+            1 => ppi.reading_data(Port::B),
+            2 => {
+                let (lines, _) = ppi.handshake_lines();
+                let inputs = ppi.c_inputs() & !lines;
+                let pins = if inputs != 0 {
+                    ppi.host_reading(Port::C)
+                } else {
+                    0
+                };
+
+                (pins & inputs)
+                    | (ppi.latches[2] & !inputs & !lines)
+                    | (ppi.handshake_status() & lines)
+            }
+            _ => 0xff,
+        };
+
+        (ppi.updating_c(), value)
+    }
+
+    fn reading_data(&mut self, port: Port) -> u8 {
+        let index = port as usize;
+
+        match self.handshaking(port) {
+            (true, _) => {
+                self.handshakes[index].input_full = false;
+                self.strobed[index]
+            }
+            (false, true) => self.latches[index],
+            _ if self.is_input(port) => self.host_reading(port),
+            _ => self.latches[index],
+        }
+    }
+
+    /// Writes a port or the control word.
+    pub fn writing_port(self, port: u8, value: u8) -> Self {
+        let mut ppi = self;
+
+        match port & 0x03 {
+            0 => ppi.writing_data(Port::A, value),
+            1 => ppi.writing_data(Port::B, value),
+            2 => ppi.latches[2] = value,
+            _ if value & MODE_SET != 0 => return ppi.setting_mode(value),
+            _ => {
+                // Bit set/reset
+                let bit = 1 << ((value >> 1) & 0x07);
+                if value & 0x01 != 0 {
+                    ppi.latches[2] |= bit;
+                } else {
+                    ppi.latches[2] &= !bit;
+                }
+            }
+        }
+
+        ppi.updating_c()
+    }
+
+    fn writing_data(&mut self, port: Port, value: u8) {
+        let index = port as usize;
+        self.latches[index] = value;
+
+        match self.handshaking(port) {
+            // The bus is only driven when the peripheral acknowledges
+            (true, true) => {
+                self.handshakes[index].output_full = true;
+                self.handshakes[index].acknowledged = false;
+            }
+            (false, true) => {
+                self.handshakes[index].output_full = true;
+                self.handshakes[index].acknowledged = false;
+                self.host_writing(port, value);
+            }
+            (false, false) if !self.is_input(port) => self.host_writing(port, value),
+            _ => (),
+        }
+    }
+
+    // Every output and handshake is cleared when the mode is set
+    fn setting_mode(self, control: u8) -> Self {
+        let mut ppi = I8255 {
+            control,
+            latches: [0; 3],
+            strobed: [0; 2],
+            handshakes: [Handshake::default(); 2],
+            ..self
+        };
+
+        for &port in [Port::A, Port::B].iter() {
+            if !ppi.is_input(port) && ppi.handshaking(port) != (true, true) {
+                ppi.host_writing(port, 0);
+            }
+        }
+
+        ppi.updating_c()
+    }
+
+    /// Pulses STB on `port`, latching `value` from the peripheral. Only ports reading in mode 1
+    /// or 2 have the latch.
+    pub fn strobing(self, port: Port, value: u8) -> Self {
+        let mut ppi = self;
+        if port != Port::C && ppi.handshaking(port).0 {
+            ppi.strobed[port as usize] = value;
+            ppi.handshakes[port as usize].input_full = true;
+        }
+
+        ppi.updating_c()
+    }
+
+    /// Pulses ACK on `port` once the peripheral has taken the output. In mode 2 this is when
+    /// port A drives the output onto its pins.
+    pub fn acknowledging(self, port: Port) -> Self {
+        let mut ppi = self;
+        if port == Port::C {
+            return ppi;
+        }
+
+        let index = port as usize;
+        match ppi.handshaking(port) {
+            (true, true) => {
+                let value = ppi.latches[index];
+                ppi.host_writing(port, value);
+            }
+            (false, true) => (),
+            _ => return ppi,
+        }
+        ppi.handshakes[index].output_full = false;
+        ppi.handshakes[index].acknowledged = true;
+
+        ppi.updating_c()
+    }
+}
+
+impl IOHandler for I8255 {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let (ppi, value) = std::mem::take(self).reading_port(port);
+        *self = ppi;
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        *self = std::mem::take(self).writing_port(port, state.a);
+
+        state
+    }
+}
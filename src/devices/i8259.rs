@@ -0,0 +1,330 @@
+//! Intel 8259 programmable interrupt controller, in 8080/8085 mode.
+//!
+//! A0 selects the port. During the acknowledge cycles the controller puts a CALL to the vector
+//! of the highest priority request on the bus, the CPU takes it through
+//! `State8080::acknowledging_interrupt`. Cascading is accepted when initializing but slaves are
+//! not emulated, and the controller always runs in 8080 mode whatever ICW4 says.
+
+use crate::emulator::{IOHandler, InterruptController, State8080};
+
+pub const LINES: u8 = 8;
+
+/// Which initialization command word comes next.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Initializing {
+    /// Waiting for ICW1 after power up.
+    #[default]
+    Uninitialized,
+    Icw2,
+    Icw3,
+    Icw4,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct I8259 {
+    initializing: Initializing,
+    icw1: u8,
+    icw2: u8,
+    icw4: u8,
+    request: u8,
+    in_service: u8,
+    mask: u8,
+    levels: u8,
+    lowest_priority: u8,
+    reading_in_service: bool,
+    polling: bool,
+    special_mask: bool,
+    rotating_on_auto_eoi: bool,
+}
+
+impl Default for I8259 {
+    fn default() -> Self {
+        I8259::new()
+    }
+}
+
+impl I8259 {
+    pub fn new() -> Self {
+        I8259 {
+            initializing: Initializing::Uninitialized,
+            icw1: 0,
+            icw2: 0,
+            icw4: 0,
+            request: 0,
+            in_service: 0,
+            mask: 0,
+            levels: 0,
+            lowest_priority: LINES - 1,
+            reading_in_service: false,
+            polling: false,
+            special_mask: false,
+            rotating_on_auto_eoi: false,
+        }
+    }
+
+    fn is_level_triggered(&self) -> bool {
+        self.icw1 & 0x08 != 0
+    }
+
+    fn is_auto_eoi(&self) -> bool {
+        self.icw4 & 0x02 != 0
+    }
+
+    /// Drives the IR `line` (0-7).
+    pub fn setting_request(self, line: u8, level: bool) -> Self {
+        let mut controller = self;
+        let bit = 1 << line;
+
+        if level {
+            // Edge triggered requests are latched on the rising edge only
+            if controller.is_level_triggered() || controller.levels & bit == 0 {
+                controller.request |= bit;
+            }
+            controller.levels |= bit;
+        } else {
+            // Requests that go away before they are acknowledged are lost
+            controller.request &= !bit;
+            controller.levels &= !bit;
+        }
+
+        controller
+    }
+
+    pub fn request_register(&self) -> u8 {
+        self.request
+    }
+
+    pub fn in_service_register(&self) -> u8 {
+        self.in_service
+    }
+
+    pub fn mask_register(&self) -> u8 {
+        self.mask
+    }
+
+    // Highest priority line set in `bits`, starting after the lowest priority one
+    fn highest(&self, bits: u8) -> Option<u8> {
+        (1..=LINES)
+            .map(|offset| (self.lowest_priority + offset) % LINES)
+            .find(|&line| bits & 1 << line != 0)
+    }
+
+    // 0 is the highest priority
+    fn priority(&self, line: u8) -> u8 {
+        (line + LINES - self.lowest_priority - 1) % LINES
+    }
+
+    /// The request that would be acknowledged next.
+    fn resolving(&self) -> Option<u8> {
+        if self.initializing != Initializing::Done {
+            return None;
+        }
+
+        let pending = self.request & !self.mask;
+        if self.special_mask {
+            // Only masked levels are blocked, in service or not
+            return self.highest(pending & !self.in_service);
+        }
+
+        let line = self.highest(pending)?;
+        match self.highest(self.in_service) {
+            Some(serviced) if self.priority(serviced) <= self.priority(line) => None,
+            _ => Some(line),
+        }
+    }
+
+    /// Level of the INT output.
+    pub fn interrupt_requested(&self) -> bool {
+        self.resolving().is_some()
+    }
+
+    // Moves the highest request in service, if there is still one
+    fn accepting(self) -> (Self, Option<u8>) {
+        let mut controller = self;
+        let line = controller.resolving();
+        if let Some(line) = line {
+            let bit = 1 << line;
+            controller.in_service |= bit;
+            controller.request &= !bit;
+            if controller.is_level_triggered() {
+                controller.request |= controller.levels & bit;
+            }
+        }
+
+        (controller, line)
+    }
+
+    fn ending_interrupt(self, line: u8, rotating: bool) -> Self {
+        let mut controller = self;
+        controller.in_service &= !(1 << line);
+        if rotating {
+            controller.lowest_priority = line;
+        }
+
+        controller
+    }
+
+    /// Runs the three INTA cycles, returning the CALL instruction put on the bus. A request that
+    /// went away before it was acknowledged gets the IR7 vector.
+    pub fn acknowledging(self) -> (Self, [u8; 3]) {
+        let (mut controller, line) = self.accepting();
+        let vector = line.unwrap_or(LINES - 1);
+
+        let low = if controller.icw1 & 0x04 != 0 {
+            (controller.icw1 & 0xe0) | vector << 2
+        } else {
+            (controller.icw1 & 0xc0) | vector << 3
+        };
+
+        if let (Some(line), true) = (line, controller.is_auto_eoi()) {
+            let rotating = controller.rotating_on_auto_eoi;
+            controller = controller.ending_interrupt(line, rotating);
+        }
+
+        let high = controller.icw2;
+        (controller, [0xcd, low, high])
+    }
+
+    /// Reads the IRR or ISR (A0 low), as selected by OCW3, or the mask (A0 high).
+    pub fn reading_port(self, port: u8) -> (Self, u8) {
+        let mut controller = self;
+
+        if port & 0x01 != 0 {
+            let mask = controller.mask;
+            return (controller, mask);
+        }
+
+        if controller.polling {
+            controller.polling = false;
+            let (controller, line) = controller.accepting();
+            let word = line.map_or(0x00, |line| 0x80 | line);
+            return (controller, word);
+        }
+
+        let value = if controller.reading_in_service {
+            controller.in_service
+        } else {
+            controller.request
+        };
+
+        (controller, value)
+    }
+
+    /// Writes an initialization or operation command word.
+    pub fn writing_port(self, port: u8, value: u8) -> Self {
+        let mut controller = self;
+
+        if port & 0x01 == 0 {
+            if value & 0x10 != 0 {
+                return I8259 {
+                    initializing: Initializing::Icw2,
+                    icw1: value,
+                    // Without IC4 every ICW4 bit is zero
+                    icw4: 0,
+                    levels: controller.levels,
+                    ..I8259::new()
+                };
+            }
+
+            if value & 0x08 == 0 {
+                return controller.executing_ocw2(value);
+            }
+
+            // OCW3
+            if value & 0x02 != 0 {
+                controller.reading_in_service = value & 0x01 != 0;
+            }
+            controller.polling = value & 0x04 != 0;
+            if value & 0x40 != 0 {
+                controller.special_mask = value & 0x20 != 0;
+            }
+
+            return controller;
+        }
+
+        let single = controller.icw1 & 0x02 != 0;
+        let needs_icw4 = controller.icw1 & 0x01 != 0;
+        let done_or = |next: Initializing, needed: bool| {
+            if needed {
+                next
+            } else {
+                Initializing::Done
+            }
+        };
+
+        match controller.initializing {
+            Initializing::Icw2 => {
+                controller.icw2 = value;
+                controller.initializing = if !single {
+                    Initializing::Icw3
+                } else {
+                    done_or(Initializing::Icw4, needs_icw4)
+                };
+            }
+            // Cascading is not emulated
+            Initializing::Icw3 => controller.initializing = done_or(Initializing::Icw4, needs_icw4),
+            Initializing::Icw4 => {
+                controller.icw4 = value;
+                controller.initializing = Initializing::Done;
+            }
+            // OCW1
+            _ => controller.mask = value,
+        }
+
+        controller
+    }
+
+    fn executing_ocw2(self, value: u8) -> Self {
+        let controller = self;
+        let level = value & 0x07;
+        let highest = controller.highest(controller.in_service);
+
+        match value >> 5 {
+            // Non-specific EOI, with or without rotation
+            0b001 | 0b101 => match highest {
+                Some(line) => controller.ending_interrupt(line, value & 0x80 != 0),
+                None => controller,
+            },
+            // Specific EOI, with or without rotation
+            0b011 | 0b111 => controller.ending_interrupt(level, value & 0x80 != 0),
+            0b100 | 0b000 => I8259 {
+                rotating_on_auto_eoi: value & 0x80 != 0,
+                ..controller
+            },
+            0b110 => I8259 {
+                lowest_priority: level,
+                ..controller
+            },
+            _ => controller,
+        }
+    }
+}
+
+impl IOHandler for I8259 {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let (controller, value) = std::mem::take(self).reading_port(port);
+        *self = controller;
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        *self = std::mem::take(self).writing_port(port, state.a);
+
+        state
+    }
+}
+
+impl InterruptController for I8259 {
+    fn interrupt_requested(&self) -> bool {
+        I8259::interrupt_requested(self)
+    }
+
+    fn acknowledging(&mut self) -> [u8; 3] {
+        let (controller, instruction) = std::mem::take(self).acknowledging();
+        *self = controller;
+
+        instruction
+    }
+}
@@ -0,0 +1,6 @@
+//! Peripheral chips that plug into a machine's IO handler.
+
+pub mod i8251;
+pub mod i8253;
+pub mod i8259;
+pub mod i8255;
@@ -1,48 +1,520 @@
+//! C interface to the 8080 core.
+//!
+//! The caller owns every `State8080` it gets from `state8080_new` until it passes it to
+//! `state8080_free`. Functions taking the state by pointer work on it in place, so the pointer
+//! stays the same for as long as the state lives. The exceptions are `state8080_evaluating_next`,
+//! `state8080_evaluating_next_with_io` and the `state8080_loading_*` functions, which consume the
+//! state and return a new one. `state8080_step`, `state8080_load_file` and `state8080_load_buffer`
+//! do the same in place and report failures with a `Status8080`.
+//!
+//! Null pointers are accepted everywhere. Functions returning a `Status8080` report them, the
+//! ones returning a new state return null, getters return 0 or false and the others do nothing.
+
 use std::ffi::CStr;
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 use std::ptr::null_mut;
 
-use crate::emulator::{DummyIOHandler, State8080};
+use crate::emulator::{ConditionCodes, DummyIOHandler, IOHandler, State8080};
+
+/// The registers of the 8080, `Flags` being the flags byte as PUSH PSW stores it. 8-bit registers
+/// are read and written through the low byte.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register8080 {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Flags,
+    Sp,
+    Pc,
+}
+
+/// The flags of the 8080, by their bit in the flags byte.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag8080 {
+    S = 0x80,
+    Z = 0x40,
+    Ac = 0x10,
+    P = 0x04,
+    Cy = 0x01,
+}
+
+impl From<Flag8080> for ConditionCodes {
+    fn from(flag: Flag8080) -> Self {
+        ConditionCodes::from_bits_truncate(flag as u8)
+    }
+}
+
+/// Result of the functions that can fail, 0 on success.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status8080 {
+    Ok = 0,
+    /// A required pointer was null.
+    NullPointer,
+    /// The path is not valid UTF-8.
+    InvalidPath,
+    /// The file could not be read.
+    ReadFailed,
+    /// The data does not fit in memory at the given address.
+    OutOfRange,
+}
+
+/// IO handler made of C functions, each called with `user_data`. Any of them can be left null,
+/// IN then reading 0xff. Memory stays in the CPU state, so there is no callback for reads:
+/// `state8080_read_memory` sees what the CPU reads.
+#[repr(C)]
+pub struct IoHandler8080 {
+    pub user_data: *mut c_void,
+    /// Serves IN from `port`, returning the byte read.
+    pub input: Option<extern "C" fn(user_data: *mut c_void, port: u8) -> u8>,
+    /// Serves OUT of `value` to `port`.
+    pub output: Option<extern "C" fn(user_data: *mut c_void, port: u8, value: u8)>,
+    /// Called after an instruction for every byte it wrote to memory, in write order.
+    pub memory_write: Option<extern "C" fn(user_data: *mut c_void, address: u16, value: u8)>,
+}
+
+impl IOHandler for IoHandler8080 {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let value = match self.input {
+            Some(input) => input(self.user_data, port),
+            None => 0xff,
+        };
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        if let Some(output) = self.output {
+            output(self.user_data, port, state.a);
+        }
+
+        state
+    }
+}
+
+impl IoHandler8080 {
+    fn stepping(&mut self, state: State8080) -> State8080 {
+        let state = state.evaluating_next(Some(&mut *self));
+        if let Some(memory_write) = self.memory_write {
+            for &address in state.last_memory_writes() {
+                memory_write(self.user_data, address, state.memory[address as usize]);
+            }
+        }
+
+        state
+    }
+}
 
 fn create_raw_pointer(state: State8080) -> *mut State8080 {
     Box::into_raw(Box::new(state))
 }
 
+// `len` bytes from `buffer`, which may be null when there are none
+unsafe fn slice<'a>(buffer: *const u8, len: usize) -> Option<&'a [u8]> {
+    match len {
+        0 => Some(&[]),
+        _ if buffer.is_null() => None,
+        _ => Some(std::slice::from_raw_parts(buffer, len)),
+    }
+}
+
+unsafe fn reading_file(path: *const c_char) -> Result<Vec<u8>, Status8080> {
+    if path.is_null() {
+        return Err(Status8080::NullPointer);
+    }
+    let path = CStr::from_ptr(path)
+        .to_str()
+        .map_err(|_| Status8080::InvalidPath)?;
+
+    std::fs::read(path).map_err(|_| Status8080::ReadFailed)
+}
+
+fn loading(state: &mut State8080, buffer: &[u8], index: u16) -> Status8080 {
+    let start = index as usize;
+    match state.memory.get_mut(start..start + buffer.len()) {
+        Some(memory) => {
+            memory.copy_from_slice(buffer);
+            Status8080::Ok
+        }
+        None => Status8080::OutOfRange,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn state8080_new() -> *mut State8080 {
     create_raw_pointer(State8080::new())
 }
 
+/// # Safety
+///
+/// `ptr` must be null or a pointer previously returned by this library that has not been freed.
 #[no_mangle]
-pub extern "C" fn state8080_free(ptr: *mut State8080) {
+pub unsafe extern "C" fn state8080_free(ptr: *mut State8080) {
     if ptr.is_null() {
         return;
     }
-    unsafe {
-        Box::from_raw(ptr);
-    }
+    drop(Box::from_raw(ptr));
 }
 
+/// Prefer `state8080_step`, which does not move the state.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library. It is consumed by this call
+/// and must not be used afterwards; use the returned pointer instead.
 #[no_mangle]
-pub extern "C" fn state8080_evaluating_next(ptr: *mut State8080) -> *mut State8080 {
-    let state = unsafe { Box::from_raw(ptr) };
+pub unsafe extern "C" fn state8080_evaluating_next(ptr: *mut State8080) -> *mut State8080 {
+    if ptr.is_null() {
+        return null_mut();
+    }
+    let state = Box::from_raw(ptr);
 
     create_raw_pointer(state.evaluating_next::<DummyIOHandler>(None))
 }
 
+/// Prefer `state8080_load_file`, which does not move the state and tells why it failed.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library, and `path` null or a valid
+/// NUL-terminated string. On success `ptr` is consumed and must not be used afterwards. On
+/// failure null is returned and `ptr` is left as it was.
 #[no_mangle]
-pub extern "C" fn state8080_loading_file_into_memory_at(
+pub unsafe extern "C" fn state8080_loading_file_into_memory_at(
     ptr: *mut State8080,
     path: *const c_char,
     index: u16,
 ) -> *mut State8080 {
-    let cstr_path = unsafe { CStr::from_ptr(path) };
-    let path = match cstr_path.to_str() {
+    if ptr.is_null() {
+        return null_mut();
+    }
+    let buffer = match reading_file(path) {
+        Ok(buffer) => buffer,
         Err(_) => return null_mut(),
-        Ok(string) => string,
     };
+    if loading(&mut *ptr, &buffer, index) != Status8080::Ok {
+        return null_mut();
+    }
+
+    create_raw_pointer(*Box::from_raw(ptr))
+}
+
+/// Same as `state8080_evaluating_next`, with IN and OUT served by `io`.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library. It is consumed by this call
+/// and must not be used afterwards; use the returned pointer instead. `io` must be null or point
+/// to a valid `IoHandler8080` whose callbacks can be called with its `user_data`. Nothing is
+/// consumed when either is null.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_evaluating_next_with_io(
+    ptr: *mut State8080,
+    io: *mut IoHandler8080,
+) -> *mut State8080 {
+    if ptr.is_null() || io.is_null() {
+        return null_mut();
+    }
+    let state = Box::from_raw(ptr);
+
+    create_raw_pointer((*io).stepping(*state))
+}
+
+/// Loads `len` bytes from `buffer` at `index`, dropping whatever does not fit below 0x10000.
+/// Prefer `state8080_load_buffer`, which does not move the state.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library and `buffer` must point to
+/// `len` readable bytes. On success `ptr` is consumed and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_loading_buffer_into_memory_at(
+    ptr: *mut State8080,
+    buffer: *const u8,
+    len: usize,
+    index: u16,
+) -> *mut State8080 {
+    if ptr.is_null() {
+        return null_mut();
+    }
+    let len = len.min((*ptr).memory.len() - index as usize);
+    let buffer = match slice(buffer, len) {
+        Some(buffer) => buffer.to_vec(),
+        None => return null_mut(),
+    };
+    let state = Box::from_raw(ptr);
+
+    create_raw_pointer(state.loading_buffer_into_memory_at(buffer, index))
+}
+
+/// Evaluates the next instruction in place, with IN and OUT served by `io` unless it is null.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library, and `io` null or a pointer to
+/// a valid `IoHandler8080` whose callbacks can be called with its `user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_step(ptr: *mut State8080, io: *mut IoHandler8080) -> Status8080 {
+    let state = match ptr.as_mut() {
+        Some(state) => state,
+        None => return Status8080::NullPointer,
+    };
+
+    let taken = std::mem::take(state);
+    *state = match io.as_mut() {
+        Some(io) => io.stepping(taken),
+        None => taken.evaluating_next::<DummyIOHandler>(None),
+    };
+
+    Status8080::Ok
+}
+
+/// Loads the file at `path` into memory at `index`. Nothing is loaded unless all of it fits.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library, and `path` null or a valid
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_load_file(
+    ptr: *mut State8080,
+    path: *const c_char,
+    index: u16,
+) -> Status8080 {
+    let state = match ptr.as_mut() {
+        Some(state) => state,
+        None => return Status8080::NullPointer,
+    };
+
+    match reading_file(path) {
+        Ok(buffer) => loading(state, &buffer, index),
+        Err(status) => status,
+    }
+}
+
+/// Copies `len` bytes from `buffer` into memory at `index`. Nothing is loaded unless all of it
+/// fits.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library, and `buffer` must point to
+/// `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_load_buffer(
+    ptr: *mut State8080,
+    buffer: *const u8,
+    len: usize,
+    index: u16,
+) -> Status8080 {
+    match (ptr.as_mut(), slice(buffer, len)) {
+        (Some(state), Some(buffer)) => loading(state, buffer, index),
+        _ => Status8080::NullPointer,
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_register(ptr: *const State8080, register: Register8080) -> u16 {
+    let state = match ptr.as_ref() {
+        Some(state) => state,
+        None => return 0,
+    };
+
+    match register {
+        Register8080::A => state.a as u16,
+        Register8080::B => state.b as u16,
+        Register8080::C => state.c as u16,
+        Register8080::D => state.d as u16,
+        Register8080::E => state.e as u16,
+        Register8080::H => state.h as u16,
+        Register8080::L => state.l as u16,
+        Register8080::Flags => state.cc.bits() as u16,
+        Register8080::Sp => state.sp,
+        Register8080::Pc => state.pc,
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_set_register(
+    ptr: *mut State8080,
+    register: Register8080,
+    value: u16,
+) {
+    let state = match ptr.as_mut() {
+        Some(state) => state,
+        None => return,
+    };
+    let byte = value as u8;
+
+    match register {
+        Register8080::A => state.a = byte,
+        Register8080::B => state.b = byte,
+        Register8080::C => state.c = byte,
+        Register8080::D => state.d = byte,
+        Register8080::E => state.e = byte,
+        Register8080::H => state.h = byte,
+        Register8080::L => state.l = byte,
+        Register8080::Flags => state.cc = ConditionCodes::from_bits_truncate(byte),
+        Register8080::Sp => state.sp = value,
+        Register8080::Pc => state.pc = value,
+    }
+}
 
-    let state = unsafe { Box::from_raw(ptr) };
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_flag(ptr: *const State8080, flag: Flag8080) -> bool {
+    ptr.as_ref()
+        .is_some_and(|state| state.cc.contains(flag.into()))
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_set_flag(ptr: *mut State8080, flag: Flag8080, value: bool) {
+    if let Some(state) = ptr.as_mut() {
+        state.cc.set(flag.into(), value);
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_interrupt_enabled(ptr: *const State8080) -> bool {
+    ptr.as_ref().is_some_and(|state| state.interrupt_enabled)
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_set_interrupt_enabled(ptr: *mut State8080, value: bool) {
+    if let Some(state) = ptr.as_mut() {
+        state.interrupt_enabled = value;
+    }
+}
+
+/// Copies `len` bytes of memory from `address` into `buffer`, stopping at the end of memory.
+/// Returns the number of bytes copied.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library, and `buffer` null or a
+/// pointer to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_read_memory(
+    ptr: *const State8080,
+    address: u16,
+    buffer: *mut u8,
+    len: usize,
+) -> usize {
+    let state = match ptr.as_ref() {
+        Some(state) if !buffer.is_null() => state,
+        _ => return 0,
+    };
+    let memory = &state.memory[address as usize..];
+    let len = len.min(memory.len());
+    std::slice::from_raw_parts_mut(buffer, len).copy_from_slice(&memory[..len]);
+
+    len
+}
+
+/// Copies `len` bytes from `buffer` into memory at `address`, stopping at the end of memory.
+/// Returns the number of bytes copied.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library, and `buffer` null or a
+/// pointer to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_write_memory(
+    ptr: *mut State8080,
+    address: u16,
+    buffer: *const u8,
+    len: usize,
+) -> usize {
+    let state = match ptr.as_mut() {
+        Some(state) if !buffer.is_null() => state,
+        _ => return 0,
+    };
+    let memory = &mut state.memory[address as usize..];
+    let len = len.min(memory.len());
+    memory[..len].copy_from_slice(std::slice::from_raw_parts(buffer, len));
+
+    len
+}
+
+/// Runs RST `int_num`, whether interrupts are enabled or not, and wakes the CPU up from HLT.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_generating_interrupt(ptr: *mut State8080, int_num: u16) {
+    if let Some(state) = ptr.as_mut() {
+        *state = std::mem::take(state).generating_interrupt(int_num);
+    }
+}
+
+/// Cycles taken by the last evaluated instruction.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_last_cycles(ptr: *const State8080) -> u8 {
+    ptr.as_ref().map_or(0, |state| state.last_cycles())
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_halted(ptr: *const State8080) -> bool {
+    ptr.as_ref().is_some_and(|state| state.halted)
+}
+
+/// Evaluates instructions for at least `cycles` cycles, without IO. Stops early when the CPU
+/// halts, since only an interrupt can wake it up. Returns the number of cycles run.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_running(ptr: *mut State8080, cycles: u64) -> u64 {
+    state8080_running_with_io(ptr, null_mut(), cycles)
+}
+
+/// Same as `state8080_running`, with IN and OUT served by `io` unless it is null.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid pointer returned by this library, and `io` null or a pointer to
+/// a valid `IoHandler8080` whose callbacks can be called with its `user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn state8080_running_with_io(
+    ptr: *mut State8080,
+    io: *mut IoHandler8080,
+    cycles: u64,
+) -> u64 {
+    let mut run = 0;
+    while run < cycles && !state8080_halted(ptr) && state8080_step(ptr, io) == Status8080::Ok {
+        run += state8080_last_cycles(ptr) as u64;
+    }
 
-    create_raw_pointer(state.loading_file_into_memory_at(path, index))
+    run
 }
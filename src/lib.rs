@@ -1,3 +1,17 @@
-pub mod disassembler;
-pub mod emulator;
+//! The CPU cores, the block cache and the disassembler are in `emu-8080-core`, which builds without
+//! `std`, and are re-exported here.
+
+pub use emu_8080_core::{blocks, disassembler, emulator, z80};
+
+pub mod bus;
+pub mod devices;
+// Its `state8080_*` functions would clash with the exports wasm-bindgen generates for `State8080`.
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
 pub mod ffi;
+pub mod lockstep;
+pub mod machines;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod serial;
+#[cfg(feature = "wasm")]
+pub mod wasm;
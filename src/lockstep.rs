@@ -0,0 +1,361 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::disassembler::Instruction;
+use crate::emulator::{ConditionCodes, DummyIOHandler, IOHandler, State8080};
+
+/// Snapshot of the programmer-visible 8080 registers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub f: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+/// Externally visible side effect of a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BusEvent {
+    MemoryWrite { address: u16, value: u8 },
+    PortIn { port: u8, value: u8 },
+    PortOut { port: u8, value: u8 },
+}
+
+impl fmt::Display for BusEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusEvent::MemoryWrite { address, value } => {
+                write!(f, "write {:#04x} to {:#06x}", value, address)
+            }
+            BusEvent::PortIn { port, value } => {
+                write!(f, "in {:#04x} from port {:#04x}", value, port)
+            }
+            BusEvent::PortOut { port, value } => {
+                write!(f, "out {:#04x} to port {:#04x}", value, port)
+            }
+        }
+    }
+}
+
+/// An 8080 implementation that can be run in lockstep with another one.
+pub trait Reference {
+    fn registers(&self) -> Registers;
+
+    fn read_memory(&self, address: u16) -> u8;
+
+    /// Executes a single instruction, appending the memory writes and port traffic it caused to
+    /// `events`. The order of the events does not matter.
+    fn step(&mut self, events: &mut Vec<BusEvent>);
+}
+
+// Passes port accesses on to `io_handler`, recording them
+struct PortRecorder<'a, I: IOHandler> {
+    io_handler: Option<&'a mut I>,
+    events: Vec<BusEvent>,
+}
+
+impl<I: IOHandler> IOHandler for PortRecorder<'_, I> {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let state = match self.io_handler.as_deref_mut() {
+            Some(io_handler) => io_handler.inp(state, port),
+            None => state,
+        };
+        self.events.push(BusEvent::PortIn {
+            port,
+            value: state.a,
+        });
+
+        state
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        self.events.push(BusEvent::PortOut {
+            port,
+            value: state.a,
+        });
+
+        match self.io_handler.as_deref_mut() {
+            Some(io_handler) => io_handler.out(state, port),
+            None => state,
+        }
+    }
+}
+
+fn registers_of(state: &State8080) -> Registers {
+    Registers {
+        a: state.a,
+        b: state.b,
+        c: state.c,
+        d: state.d,
+        e: state.e,
+        h: state.h,
+        l: state.l,
+        f: state.cc.bits(),
+        sp: state.sp,
+        pc: state.pc,
+    }
+}
+
+fn stepping<I: IOHandler>(
+    state: &mut State8080,
+    io_handler: Option<&mut I>,
+    events: &mut Vec<BusEvent>,
+) {
+    let mut recorder = PortRecorder {
+        io_handler,
+        events: std::mem::take(events),
+    };
+    *state = std::mem::take(state).evaluating_next(Some(&mut recorder));
+
+    *events = recorder.events;
+    events.extend(
+        state
+            .last_memory_writes()
+            .iter()
+            .map(|&address| BusEvent::MemoryWrite {
+                address,
+                value: state.memory[address as usize],
+            }),
+    );
+}
+
+/// Without devices, IN leaves A unchanged. Use `StateWithIo` to serve IN and OUT.
+impl Reference for State8080 {
+    fn registers(&self) -> Registers {
+        registers_of(self)
+    }
+
+    fn read_memory(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn step(&mut self, events: &mut Vec<BusEvent>) {
+        stepping::<DummyIOHandler>(self, None, events);
+    }
+}
+
+/// A `State8080` whose IN and OUT are served by `io_handler`, to run it with real devices.
+pub struct StateWithIo<I: IOHandler> {
+    pub state: State8080,
+    pub io_handler: I,
+}
+
+impl<I: IOHandler> Reference for StateWithIo<I> {
+    fn registers(&self) -> Registers {
+        registers_of(&self.state)
+    }
+
+    fn read_memory(&self, address: u16) -> u8 {
+        self.state.memory[address as usize]
+    }
+
+    fn step(&mut self, events: &mut Vec<BusEvent>) {
+        stepping(&mut self.state, Some(&mut self.io_handler), events);
+    }
+}
+
+/// A single difference between the subject and the reference. `expected` is always the value
+/// seen on the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Register {
+        name: &'static str,
+        expected: u16,
+        actual: u16,
+    },
+    Flag {
+        name: &'static str,
+        expected: bool,
+        actual: bool,
+    },
+    Bus {
+        expected: Vec<BusEvent>,
+        actual: Vec<BusEvent>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Register {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Reg {} mismatch: Should be {:#x}, but is {:#x}",
+                name, expected, actual
+            ),
+            Mismatch::Flag {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Flag {} mismatch: Should be {}, but is {}",
+                name, *expected as u8, *actual as u8
+            ),
+            Mismatch::Bus { expected, actual } => {
+                write!(f, "Bus traffic mismatch: Should be [")?;
+                write_events(f, expected)?;
+                write!(f, "], but is [")?;
+                write_events(f, actual)?;
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+fn write_events(f: &mut fmt::Formatter, events: &[BusEvent]) -> fmt::Result {
+    for (i, event) in events.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", event)?;
+    }
+
+    Ok(())
+}
+
+/// Every mismatch found after executing the instruction at `pc`.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub step: u64,
+    pub pc: u16,
+    pub opcode: u8,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Diverged at step {}, {:04x}    {:#04x}",
+            self.step, self.pc, self.opcode
+        )?;
+        if let Ok(instruction) = Instruction::try_from(self.opcode) {
+            write!(f, "    {}", instruction)?;
+        }
+        for mismatch in &self.mismatches {
+            write!(f, "\n    {}", mismatch)?;
+        }
+
+        Ok(())
+    }
+}
+
+const FLAGS: [(&str, ConditionCodes); 6] = [
+    ("S", ConditionCodes::S),
+    ("Z", ConditionCodes::Z),
+    ("AC", ConditionCodes::AC),
+    ("P", ConditionCodes::P),
+    ("CY", ConditionCodes::CY),
+    ("PAD", ConditionCodes::PAD),
+];
+
+/// Compares the registers and flags of two implementations.
+pub fn compare_registers(reference: &Registers, subject: &Registers) -> Vec<Mismatch> {
+    let pairs = [
+        ("A", reference.a as u16, subject.a as u16),
+        ("B", reference.b as u16, subject.b as u16),
+        ("C", reference.c as u16, subject.c as u16),
+        ("D", reference.d as u16, subject.d as u16),
+        ("E", reference.e as u16, subject.e as u16),
+        ("H", reference.h as u16, subject.h as u16),
+        ("L", reference.l as u16, subject.l as u16),
+        ("SP", reference.sp, subject.sp),
+        ("PC", reference.pc, subject.pc),
+    ];
+
+    let registers = pairs
+        .iter()
+        .filter(|(_, expected, actual)| expected != actual)
+        .map(|&(name, expected, actual)| Mismatch::Register {
+            name,
+            expected,
+            actual,
+        });
+
+    let expected_cc = ConditionCodes::from_bits_truncate(reference.f);
+    let actual_cc = ConditionCodes::from_bits_truncate(subject.f);
+    let flags = FLAGS
+        .iter()
+        .filter(|(_, flag)| expected_cc.contains(*flag) != actual_cc.contains(*flag))
+        .map(|&(name, flag)| Mismatch::Flag {
+            name,
+            expected: expected_cc.contains(flag),
+            actual: actual_cc.contains(flag),
+        });
+
+    registers.chain(flags).collect()
+}
+
+/// Runs a subject implementation side by side with a reference, one instruction at a time.
+pub struct Lockstep<S: Reference, R: Reference> {
+    pub subject: S,
+    pub reference: R,
+    steps: u64,
+    subject_events: Vec<BusEvent>,
+    reference_events: Vec<BusEvent>,
+}
+
+impl<S: Reference, R: Reference> Lockstep<S, R> {
+    pub fn new(subject: S, reference: R) -> Self {
+        Lockstep {
+            subject,
+            reference,
+            steps: 0,
+            subject_events: Vec::new(),
+            reference_events: Vec::new(),
+        }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Compares the current registers without executing anything.
+    pub fn compare(&self) -> Vec<Mismatch> {
+        compare_registers(&self.reference.registers(), &self.subject.registers())
+    }
+
+    /// Executes one instruction on both sides and reports every difference found afterwards.
+    pub fn step(&mut self) -> Result<(), Divergence> {
+        let pc = self.subject.registers().pc;
+        let opcode = self.subject.read_memory(pc);
+
+        self.subject_events.clear();
+        self.reference_events.clear();
+        self.subject.step(&mut self.subject_events);
+        self.reference.step(&mut self.reference_events);
+        self.steps += 1;
+
+        let mut mismatches = self.compare();
+
+        self.subject_events.sort_unstable();
+        self.reference_events.sort_unstable();
+        if self.subject_events != self.reference_events {
+            mismatches.push(Mismatch::Bus {
+                expected: self.reference_events.clone(),
+                actual: self.subject_events.clone(),
+            });
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(Divergence {
+                step: self.steps,
+                pc,
+                opcode,
+                mismatches,
+            })
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! MITS Altair 8800 with its front panel, serial cards and disk controller.
+//!
+//! The machine has an 88-SIO card on ports 0x00-0x01, an 88-2SIO card on ports 0x10-0x13 and an
+//! 88-DCDD floppy controller on ports 0x08-0x0a. The front panel sense switches are read on port
+//! 0xff. Serial cards talk to a `SerialHost`, so a terminal can be stdio, a TCP client or a
+//! buffer.
+//!
+//! Altair BASIC images are loaded with `loading_image` and run from their load address. MITS Disk
+//! BASIC is booted from a disk image with the disk boot loader ROM:
+//!
+//! ```no_run
+//! use emu_8080::machines::altair::{Altair, SerialCard};
+//! use emu_8080::serial::Stdio;
+//!
+//! let disk = std::fs::read("disk_basic.dsk").unwrap();
+//! let boot_rom = std::fs::read("dbl.bin").unwrap();
+//! let mut altair = Altair::new()
+//!     .attaching_terminal(SerialCard::Sio2A, Box::new(Stdio::new()))
+//!     .inserting_disk(0, disk)
+//!     .unwrap()
+//!     .loading_image(boot_rom, 0xff00)
+//!     .unwrap();
+//! loop {
+//!     altair = altair.running(1_000_000);
+//! }
+//! ```
+
+use std::fmt;
+
+use crate::emulator::{IOHandler, State8080};
+use crate::serial::SerialHost;
+
+/// The CPU runs at 2 MHz.
+pub const CLOCK_HZ: u32 = 2_000_000;
+
+pub const SIO_STATUS_PORT: u8 = 0x00;
+pub const SIO_DATA_PORT: u8 = 0x01;
+pub const DISK_SELECT_PORT: u8 = 0x08;
+pub const DISK_CONTROL_PORT: u8 = 0x09;
+pub const DISK_DATA_PORT: u8 = 0x0a;
+/// Control and status port of the first 88-2SIO channel, data is on the next port and the second
+/// channel follows.
+pub const SIO2_PORT: u8 = 0x10;
+pub const SENSE_SWITCHES_PORT: u8 = 0xff;
+
+pub const DISK_TRACKS: usize = 77;
+pub const DISK_SECTORS: usize = 32;
+pub const DISK_SECTOR_SIZE: usize = 137;
+/// Size of an 8" disk image, as used by simh.
+pub const DISK_SIZE: usize = DISK_TRACKS * DISK_SECTORS * DISK_SECTOR_SIZE;
+pub const DISK_DRIVES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AltairError {
+    /// Drives are numbered from 0 to `DISK_DRIVES - 1`.
+    NoSuchDrive(usize),
+    /// An image of `len` bytes loaded at `address` would run past the end of memory.
+    ImageTooLarge { address: u16, len: usize },
+}
+
+impl fmt::Display for AltairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AltairError::NoSuchDrive(drive) => write!(f, "there is no disk drive {}", drive),
+            AltairError::ImageTooLarge { address, len } => write!(
+                f,
+                "an image of {} bytes at {:#06x} runs past the end of memory",
+                len, address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AltairError {}
+
+/// Serial channels a terminal can be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialCard {
+    Sio,
+    Sio2A,
+    Sio2B,
+}
+
+/// A serial channel with a one byte receive buffer. Without a host nothing is ever received and
+/// transmitted bytes are dropped.
+#[derive(Default)]
+pub struct SerialChannel {
+    host: Option<Box<dyn SerialHost>>,
+    received: Option<u8>,
+}
+
+impl SerialChannel {
+    fn polling(self) -> Self {
+        let mut channel = self;
+        if channel.received.is_none() {
+            channel.received = channel.host.as_mut().and_then(|host| host.receive());
+        }
+
+        channel
+    }
+
+    fn reading(self) -> (Self, u8) {
+        let mut channel = self.polling();
+        let byte = channel.received.take().unwrap_or(0);
+
+        (channel, byte)
+    }
+
+    fn transmitting(self, byte: u8) -> Self {
+        let mut channel = self;
+        if let Some(host) = channel.host.as_mut() {
+            host.transmit(byte);
+        }
+
+        channel
+    }
+}
+
+/// One drive of the 88-DCDD.
+#[derive(Default, Clone)]
+struct Drive {
+    image: Option<Vec<u8>>,
+    track: usize,
+    sector: usize,
+    byte: usize,
+    head_loaded: bool,
+    writing: bool,
+}
+
+impl Drive {
+    fn offset(&self) -> usize {
+        (self.track * DISK_SECTORS + self.sector) * DISK_SECTOR_SIZE + self.byte
+    }
+}
+
+/// The 88-DCDD floppy controller, following the status and control bits of the MITS manual.
+#[derive(Default, Clone)]
+pub struct DiskController {
+    drives: [Drive; DISK_DRIVES],
+    selected: Option<usize>,
+}
+
+impl DiskController {
+    fn selected_drive(&mut self) -> Option<&mut Drive> {
+        let drives = &mut self.drives;
+
+        self.selected.map(move |drive| &mut drives[drive])
+    }
+
+    fn selecting(self, value: u8) -> Self {
+        let drive = (value & 0x0f) as usize;
+        let selected = if value & 0x80 == 0 && drive < DISK_DRIVES {
+            Some(drive).filter(|&drive| self.drives[drive].image.is_some())
+        } else {
+            None
+        };
+
+        DiskController { selected, ..self }
+    }
+
+    // Status bits are active low: 0 enter new write data, 1 move head, 2 head status,
+    // 5 interrupts enabled, 6 track 0 and 7 new read data available
+    fn status(&self) -> u8 {
+        let drive = match self.selected {
+            Some(drive) => &self.drives[drive],
+            None => return 0xff,
+        };
+
+        // The head can always move, unused bits 3 and 4 read low
+        let mut status = 0xe5;
+        if drive.writing {
+            status &= !0x01;
+        }
+        if drive.head_loaded {
+            status &= !0x04 & !0x80;
+        }
+        if drive.track == 0 {
+            status &= !0x40;
+        }
+
+        status
+    }
+
+    fn controlling(self, value: u8) -> Self {
+        let mut controller = self;
+        if let Some(drive) = controller.selected_drive() {
+            if value & 0x01 != 0 && drive.track < DISK_TRACKS - 1 {
+                drive.track += 1;
+            }
+            if value & 0x02 != 0 && drive.track > 0 {
+                drive.track -= 1;
+            }
+            if value & 0x04 != 0 {
+                drive.head_loaded = true;
+            }
+            if value & 0x08 != 0 {
+                drive.head_loaded = false;
+            }
+            if value & 0x80 != 0 {
+                drive.writing = true;
+                drive.byte = 0;
+            }
+        }
+
+        controller
+    }
+
+    // The disk spins past the head, so every read of the sector position moves to the next sector
+    fn reading_sector(self) -> (Self, u8) {
+        let mut controller = self;
+        let position = match controller.selected_drive() {
+            Some(drive) if drive.head_loaded => {
+                drive.sector = (drive.sector + 1) % DISK_SECTORS;
+                drive.byte = 0;
+                drive.writing = false;
+
+                // Bit 0 low means the sector is under the head
+                0xc0 | (drive.sector as u8) << 1
+            }
+            _ => 0xff,
+        };
+
+        (controller, position)
+    }
+
+    fn reading_data(self) -> (Self, u8) {
+        let mut controller = self;
+        let byte = match controller.selected_drive() {
+            Some(drive) if drive.byte < DISK_SECTOR_SIZE => {
+                let offset = drive.offset();
+                drive.byte += 1;
+
+                drive.image.as_ref().map_or(0, |image| image[offset])
+            }
+            _ => 0,
+        };
+
+        (controller, byte)
+    }
+
+    fn writing_data(self, value: u8) -> Self {
+        let mut controller = self;
+        if let Some(drive) = controller.selected_drive() {
+            if drive.writing && drive.byte < DISK_SECTOR_SIZE {
+                let offset = drive.offset();
+                drive.byte += 1;
+                drive.writing = drive.byte < DISK_SECTOR_SIZE;
+                if let Some(image) = drive.image.as_mut() {
+                    image[offset] = value;
+                }
+            }
+        }
+
+        controller
+    }
+}
+
+/// Everything on the S-100 bus besides the CPU and memory.
+#[derive(Default)]
+pub struct AltairIO {
+    /// Address and data switches of the front panel. The upper 8 are the sense switches.
+    pub switches: u16,
+    pub sio: SerialChannel,
+    pub sio2: [SerialChannel; 2],
+    pub disk: DiskController,
+}
+
+impl AltairIO {
+    /// Reads an input port, which can consume a received byte.
+    pub fn reading_port(self, port: u8) -> (Self, u8) {
+        let mut io = self;
+
+        let value = match port {
+            SIO_STATUS_PORT => {
+                io.sio = io.sio.polling();
+                // Active low: bit 0 input available, bit 7 output ready
+                io.sio.received.is_none() as u8
+            }
+            SIO_DATA_PORT => {
+                let (sio, byte) = io.sio.reading();
+                io.sio = sio;
+
+                byte
+            }
+            DISK_SELECT_PORT => io.disk.status(),
+            DISK_CONTROL_PORT => {
+                let (disk, position) = io.disk.reading_sector();
+                io.disk = disk;
+
+                position
+            }
+            DISK_DATA_PORT => {
+                let (disk, byte) = io.disk.reading_data();
+                io.disk = disk;
+
+                byte
+            }
+            // 6850 ACIA status: bit 0 receive data register full, bit 1 transmit register empty
+            0x10 | 0x12 => {
+                let channel = (port - SIO2_PORT) as usize / 2;
+                let polled = std::mem::take(&mut io.sio2[channel]).polling();
+                let value = 0x02 | polled.received.is_some() as u8;
+                io.sio2[channel] = polled;
+
+                value
+            }
+            0x11 | 0x13 => {
+                let channel = (port - SIO2_PORT) as usize / 2;
+                let (read, byte) = std::mem::take(&mut io.sio2[channel]).reading();
+                io.sio2[channel] = read;
+
+                byte
+            }
+            SENSE_SWITCHES_PORT => (io.switches >> 8) as u8,
+            _ => 0xff,
+        };
+
+        (io, value)
+    }
+
+    /// Handles a write to an output port. Control words of the serial cards only configure
+    /// interrupts and framing, which are not emulated.
+    pub fn writing_port(self, port: u8, value: u8) -> Self {
+        let mut io = self;
+
+        match port {
+            SIO_DATA_PORT => io.sio = io.sio.transmitting(value),
+            DISK_SELECT_PORT => io.disk = io.disk.selecting(value),
+            DISK_CONTROL_PORT => io.disk = io.disk.controlling(value),
+            DISK_DATA_PORT => io.disk = io.disk.writing_data(value),
+            // Master reset drops anything received
+            0x10 | 0x12 if value & 0x03 == 0x03 => {
+                io.sio2[(port - SIO2_PORT) as usize / 2].received = None
+            }
+            0x11 | 0x13 => {
+                let channel = (port - SIO2_PORT) as usize / 2;
+                io.sio2[channel] = std::mem::take(&mut io.sio2[channel]).transmitting(value);
+            }
+            _ => {}
+        }
+
+        io
+    }
+}
+
+impl IOHandler for AltairIO {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let (io, value) = std::mem::take(self).reading_port(port);
+        *self = io;
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        *self = std::mem::take(self).writing_port(port, state.a);
+
+        state
+    }
+}
+
+/// An Altair 8800 with 64K of RAM.
+#[derive(Default)]
+pub struct Altair {
+    pub cpu: State8080,
+    pub io: AltairIO,
+    cycles: u64,
+}
+
+impl Altair {
+    pub fn new() -> Self {
+        Altair {
+            cpu: State8080::new(),
+            ..Default::default()
+        }
+    }
+
+    pub fn attaching_terminal(self, card: SerialCard, host: Box<dyn SerialHost>) -> Self {
+        let mut altair = self;
+        let channel = match card {
+            SerialCard::Sio => &mut altair.io.sio,
+            SerialCard::Sio2A => &mut altair.io.sio2[0],
+            SerialCard::Sio2B => &mut altair.io.sio2[1],
+        };
+        *channel = SerialChannel {
+            host: Some(host),
+            received: None,
+        };
+
+        altair
+    }
+
+    /// Inserts a disk image in `drive` (0-3). Images shorter than a full disk are padded.
+    pub fn inserting_disk(self, drive: usize, image: Vec<u8>) -> Result<Self, AltairError> {
+        if drive >= DISK_DRIVES {
+            return Err(AltairError::NoSuchDrive(drive));
+        }
+
+        let mut altair = self;
+        let mut image = image;
+        image.resize(image.len().max(DISK_SIZE), 0);
+        altair.io.disk.drives[drive] = Drive {
+            image: Some(image),
+            ..Default::default()
+        };
+
+        Ok(altair)
+    }
+
+    /// Contents of the disk in `drive`, including everything written to it.
+    pub fn disk_image(&self, drive: usize) -> Option<&[u8]> {
+        self.io.disk.drives.get(drive)?.image.as_deref()
+    }
+
+    /// Loads `image` at `address` and starts running from there, like toggling in a loader and
+    /// running it from the front panel. Fails if the image doesn't fit in memory from `address`.
+    pub fn loading_image(self, image: Vec<u8>, address: u16) -> Result<Self, AltairError> {
+        if address as usize + image.len() > self.cpu.memory.len() {
+            return Err(AltairError::ImageTooLarge {
+                address,
+                len: image.len(),
+            });
+        }
+
+        let mut altair = self;
+        altair.cpu = altair.cpu.loading_buffer_into_memory_at(image, address);
+        altair.cpu.pc = address;
+
+        Ok(altair)
+    }
+
+    /// Cycles run since the machine was created.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Runs for at least `cycles` cycles.
+    pub fn running(self, cycles: u64) -> Self {
+        let until = self.cycles + cycles;
+        let mut altair = self;
+        while altair.cycles < until {
+            altair = altair.stepping();
+        }
+
+        altair
+    }
+
+    /// Evaluates a single instruction.
+    pub fn stepping(self) -> Self {
+        let mut io = self.io;
+        let cpu = self.cpu.evaluating_next(Some(&mut io));
+        let cycles = self.cycles + cpu.last_cycles() as u64;
+
+        Altair { cpu, io, cycles }
+    }
+
+    pub fn setting_switches(self, switches: u16) -> Self {
+        let mut altair = self;
+        altair.io.switches = switches;
+
+        altair
+    }
+
+    /// Front panel EXAMINE: jumps to the address set on the switches.
+    pub fn examining(self) -> Self {
+        let mut altair = self;
+        altair.cpu.pc = altair.io.switches;
+
+        altair
+    }
+
+    /// Front panel EXAMINE NEXT.
+    pub fn examining_next(self) -> Self {
+        let mut altair = self;
+        altair.cpu.pc = altair.cpu.pc.wrapping_add(1);
+
+        altair
+    }
+
+    /// Front panel DEPOSIT: stores the lower 8 switches at the current address.
+    pub fn depositing(self) -> Self {
+        let byte = self.io.switches as u8;
+        let pc = self.cpu.pc;
+
+        Altair {
+            cpu: self.cpu.setting_memory_at(byte, pc),
+            ..self
+        }
+    }
+
+    /// Front panel DEPOSIT NEXT: moves to the next address before depositing.
+    pub fn depositing_next(self) -> Self {
+        self.examining_next().depositing()
+    }
+
+    /// Front panel RESET, which also restarts a halted CPU.
+    pub fn resetting(self) -> Self {
+        let mut altair = self;
+        altair.cpu.pc = 0;
+        altair.cpu.interrupt_enabled = false;
+        altair.cpu.halted = false;
+
+        altair
+    }
+
+    /// The address lights, which show the program counter while stopped.
+    pub fn address_lights(&self) -> u16 {
+        self.cpu.pc
+    }
+
+    /// The data lights, which show the byte at the program counter while stopped.
+    pub fn data_lights(&self) -> u8 {
+        self.cpu.memory[self.cpu.pc as usize]
+    }
+}
@@ -0,0 +1,666 @@
+//! Midway 8080 style boards built from a declarative description.
+//!
+//! Many games on this hardware only differ in ROM layout, memory map, port wiring and DIP switches.
+//! A `BoardDescription` captures those in TOML, so a game can be added without any new code:
+//!
+//! ```toml
+//! name = "Space Invaders"
+//! clock_hz = 2_000_000
+//!
+//! [[rom]]
+//! file = "invaders.h"
+//! address = 0x0000
+//! size = 0x0800
+//!
+//! [[memory]]
+//! kind = "ram"
+//! start = 0x2000
+//! end = 0x3fff
+//!
+//! [[memory]]
+//! kind = "mirror"
+//! start = 0x4000
+//! end = 0x5fff
+//! of = 0x2000
+//!
+//! [shift_register]
+//! data_port = 4
+//! offset_port = 2
+//! result_port = 3
+//!
+//! [[input]]
+//! port = 1
+//! value = 0x08
+//! controls = { coin = 0, p2_start = 1, p1_start = 2 }
+//!
+//! [[dip]]
+//! name = "ships"
+//! port = 2
+//! mask = 0x03
+//!
+//! [[output]]
+//! port = 3
+//! name = "sound1"
+//! ```
+//!
+//! Descriptions of the supported games are in `resources/boards`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::emulator::{IOHandler, State8080};
+use crate::machines::invaders::{ShiftRegister, HEIGHT, WIDTH};
+use crate::machines::png;
+
+/// A ROM file and where it is loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomFile {
+    pub file: String,
+    pub address: u16,
+    /// Expected size of the file in bytes, checked when loading.
+    pub size: Option<u32>,
+}
+
+/// An address range of the memory map, with `end` inclusive. Addresses outside of every region
+/// behave as RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Region {
+    /// Writes are ignored.
+    Rom {
+        start: u16,
+        end: u16,
+    },
+    Ram {
+        start: u16,
+        end: u16,
+    },
+    /// Another view of the range starting at `of`.
+    Mirror {
+        start: u16,
+        end: u16,
+        of: u16,
+    },
+}
+
+impl Region {
+    pub fn start(&self) -> u16 {
+        match *self {
+            Region::Rom { start, .. }
+            | Region::Ram { start, .. }
+            | Region::Mirror { start, .. } => start,
+        }
+    }
+
+    pub fn end(&self) -> u16 {
+        match *self {
+            Region::Rom { end, .. } | Region::Ram { end, .. } | Region::Mirror { end, .. } => end,
+        }
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start()..=self.end()).contains(&address)
+    }
+}
+
+/// Ports of the MB14241 shift register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShiftRegisterPorts {
+    pub data_port: u8,
+    pub offset_port: u8,
+    pub result_port: u8,
+}
+
+/// An input port and the controls wired to it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputPort {
+    pub port: u8,
+    /// Bits that read as set when nothing is pressed, such as bits tied high.
+    #[serde(default)]
+    pub value: u8,
+    /// Control names and the bit each one drives. A control can be wired to several ports.
+    #[serde(default)]
+    pub controls: BTreeMap<String, u8>,
+    /// Pressed controls clear their bit instead of setting it.
+    #[serde(default)]
+    pub active_low: bool,
+}
+
+/// A DIP switch setting, read through the bits of `mask` on `port`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DipSwitch {
+    pub name: String,
+    pub port: u8,
+    pub mask: u8,
+    /// Factory setting, shifted into place under `mask`.
+    #[serde(default)]
+    pub value: u8,
+}
+
+impl DipSwitch {
+    /// Highest setting that fits under `mask`.
+    pub fn maximum(&self) -> u8 {
+        self.mask
+            .checked_shr(self.mask.trailing_zeros())
+            .unwrap_or(0)
+    }
+}
+
+/// A latched output port, such as sound or lamp outputs. Writes to ports that are not described
+/// are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputPort {
+    pub port: u8,
+    pub name: String,
+}
+
+fn default_clock_hz() -> u32 {
+    2_000_000
+}
+
+fn default_video_ram() -> u16 {
+    0x2400
+}
+
+/// Everything that sets a board apart from the others.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardDescription {
+    pub name: String,
+    #[serde(default = "default_clock_hz")]
+    pub clock_hz: u32,
+    /// Start of the 256×224 bitmap.
+    #[serde(default = "default_video_ram")]
+    pub video_ram: u16,
+    #[serde(default, rename = "rom")]
+    pub roms: Vec<RomFile>,
+    #[serde(default)]
+    pub memory: Vec<Region>,
+    pub shift_register: Option<ShiftRegisterPorts>,
+    #[serde(default, rename = "input")]
+    pub inputs: Vec<InputPort>,
+    #[serde(default, rename = "dip")]
+    pub dips: Vec<DipSwitch>,
+    #[serde(default, rename = "output")]
+    pub outputs: Vec<OutputPort>,
+}
+
+impl BoardDescription {
+    pub fn from_toml(source: &str) -> Result<Self, BoardError> {
+        let description: BoardDescription = toml::from_str(source).map_err(BoardError::Parse)?;
+
+        description.validated()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, BoardError> {
+        let source = std::fs::read_to_string(path).map_err(BoardError::Io)?;
+
+        BoardDescription::from_toml(&source)
+    }
+
+    fn validated(self) -> Result<Self, BoardError> {
+        let invalid = |reason: String| Err(BoardError::Invalid(reason));
+
+        if self.clock_hz < 60 {
+            return invalid(format!("clock of {} Hz is too slow", self.clock_hz));
+        }
+        if self.video_ram as usize + WIDTH * HEIGHT / 8 > 0x10000 {
+            return invalid(format!(
+                "video RAM at {:#06x} runs past 0xffff",
+                self.video_ram
+            ));
+        }
+        for (i, region) in self.memory.iter().enumerate() {
+            if region.start() > region.end() {
+                return invalid(format!(
+                    "region {:#06x}-{:#06x} ends before it starts",
+                    region.start(),
+                    region.end()
+                ));
+            }
+            if let Region::Mirror { start, end, of } = *region {
+                if of as usize + (end - start) as usize > 0xffff {
+                    return invalid(format!("mirror of {:#06x} runs past 0xffff", of));
+                }
+                let of_end = of + (end - start);
+                if self.memory.iter().any(|other| {
+                    matches!(other, Region::Mirror { .. })
+                        && other.start() <= of_end
+                        && of <= other.end()
+                }) {
+                    return invalid(format!(
+                        "mirror of {:#06x}-{:#06x} points to another mirror",
+                        of, of_end
+                    ));
+                }
+            }
+            if let Some(other) = self.memory[..i]
+                .iter()
+                .find(|other| other.start() <= region.end() && region.start() <= other.end())
+            {
+                return invalid(format!(
+                    "regions {:#06x}-{:#06x} and {:#06x}-{:#06x} overlap",
+                    other.start(),
+                    other.end(),
+                    region.start(),
+                    region.end()
+                ));
+            }
+        }
+        for rom in self.roms.iter() {
+            if rom.address as u32 + rom.size.unwrap_or(0) > 0x10000 {
+                return invalid(format!("{} runs past 0xffff", rom.file));
+            }
+        }
+        for input in self.inputs.iter() {
+            if let Some((name, bit)) = input.controls.iter().find(|(_, &bit)| bit > 7) {
+                return invalid(format!(
+                    "{} is wired to bit {} of port {}",
+                    name, bit, input.port
+                ));
+            }
+        }
+        for dip in self.dips.iter() {
+            if dip.mask == 0 {
+                return invalid(format!("{} has an empty mask", dip.name));
+            }
+            if dip.value > dip.maximum() {
+                return invalid(format!(
+                    "{} is set to {}, more than its mask allows",
+                    dip.name, dip.value
+                ));
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+#[derive(Debug)]
+pub enum BoardError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    /// The description parsed but does not make sense.
+    Invalid(String),
+    RomSize {
+        file: String,
+        expected: u32,
+        found: usize,
+    },
+    /// A DIP switch setting that does not fit under its mask.
+    DipSetting {
+        name: String,
+        value: u8,
+    },
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BoardError::Io(error) => write!(f, "{}", error),
+            BoardError::Parse(error) => write!(f, "invalid board description: {}", error),
+            BoardError::Invalid(reason) => write!(f, "invalid board description: {}", reason),
+            BoardError::RomSize {
+                file,
+                expected,
+                found,
+            } => write!(f, "{} is {} bytes, expected {}", file, found, expected),
+            BoardError::DipSetting { name, value } => {
+                write!(f, "{} does not fit DIP switch {}", value, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+/// Ports of a board, as wired by its description.
+#[derive(Debug, Clone)]
+pub struct BoardIO {
+    description: Arc<BoardDescription>,
+    shift_register: ShiftRegister,
+    pressed: BTreeSet<String>,
+    dips: BTreeMap<String, u8>,
+    latches: BTreeMap<u8, u8>,
+}
+
+impl BoardIO {
+    pub fn new(description: BoardDescription) -> Self {
+        let dips = description
+            .dips
+            .iter()
+            .map(|dip| (dip.name.clone(), dip.value))
+            .collect();
+
+        BoardIO {
+            description: Arc::new(description),
+            shift_register: ShiftRegister::default(),
+            pressed: BTreeSet::new(),
+            dips,
+            latches: BTreeMap::new(),
+        }
+    }
+
+    /// Presses or releases a control. Names that are not wired to any port are ignored.
+    pub fn setting_input(self, control: &str, pressed: bool) -> Self {
+        let mut io = self;
+        if pressed {
+            io.pressed.insert(control.to_string());
+        } else {
+            io.pressed.remove(control);
+        }
+
+        io
+    }
+
+    /// Sets a DIP switch, failing if `value` does not fit under its mask. Names that are not in
+    /// the description are ignored.
+    pub fn setting_dip(self, name: &str, value: u8) -> Result<Self, BoardError> {
+        let fits = self
+            .description
+            .dips
+            .iter()
+            .filter(|dip| dip.name == name)
+            .all(|dip| value <= dip.maximum());
+        if !fits {
+            return Err(BoardError::DipSetting {
+                name: name.to_string(),
+                value,
+            });
+        }
+
+        let mut io = self;
+        if let Some(setting) = io.dips.get_mut(name) {
+            *setting = value;
+        }
+
+        Ok(io)
+    }
+
+    pub fn dip(&self, name: &str) -> Option<u8> {
+        self.dips.get(name).copied()
+    }
+
+    /// Last value written to the output named `name`.
+    pub fn latch(&self, name: &str) -> Option<u8> {
+        let output = self
+            .description
+            .outputs
+            .iter()
+            .find(|output| output.name == name)?;
+
+        Some(self.latches.get(&output.port).copied().unwrap_or(0))
+    }
+
+    /// Value the CPU reads from an input port.
+    pub fn input_port(&self, port: u8) -> u8 {
+        let description = &self.description;
+        let mut value = 0;
+
+        for input in description.inputs.iter().filter(|input| input.port == port) {
+            let mut bits = input.value;
+            for (control, &bit) in input.controls.iter() {
+                if self.pressed.contains(control) {
+                    if input.active_low {
+                        bits &= !(1 << bit);
+                    } else {
+                        bits |= 1 << bit;
+                    }
+                }
+            }
+            value |= bits;
+        }
+
+        for dip in description.dips.iter().filter(|dip| dip.port == port) {
+            let setting = self.dips[&dip.name];
+            value |= setting.checked_shl(dip.mask.trailing_zeros()).unwrap_or(0) & dip.mask;
+        }
+
+        match description.shift_register {
+            Some(ports) if ports.result_port == port => value | self.shift_register.result(),
+            _ => value,
+        }
+    }
+
+    /// Handles a write to an output port.
+    pub fn writing_port(self, port: u8, value: u8) -> Self {
+        let mut io = self;
+
+        match io.description.shift_register {
+            Some(ports) if ports.data_port == port => {
+                io.shift_register = io.shift_register.shifting_in(value)
+            }
+            Some(ports) if ports.offset_port == port => {
+                io.shift_register = io.shift_register.setting_offset(value)
+            }
+            _ => {
+                if io
+                    .description
+                    .outputs
+                    .iter()
+                    .any(|output| output.port == port)
+                {
+                    io.latches.insert(port, value);
+                }
+            }
+        }
+
+        io
+    }
+}
+
+impl IOHandler for BoardIO {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        state.setting_a(self.input_port(port))
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        let empty = BoardIO {
+            description: Arc::clone(&self.description),
+            shift_register: ShiftRegister::default(),
+            pressed: BTreeSet::new(),
+            dips: BTreeMap::new(),
+            latches: BTreeMap::new(),
+        };
+        *self = std::mem::replace(self, empty).writing_port(port, state.a);
+
+        state
+    }
+}
+
+/// A machine built from a `BoardDescription`. Frames raise RST 1 mid-screen and RST 2 at the
+/// start of vertical blank, as on every Midway 8080 board.
+#[derive(Clone)]
+pub struct Board {
+    pub cpu: State8080,
+    pub io: BoardIO,
+    // Memory as loaded, to undo writes to ROM
+    rom_image: Vec<u8>,
+    frame_cycles: u32,
+    frames: u64,
+}
+
+impl Board {
+    /// Creates a board with empty memory, ready for `loading_rom`.
+    pub fn new(description: BoardDescription) -> Self {
+        let cpu = State8080::new();
+
+        Board {
+            rom_image: cpu.memory.clone(),
+            cpu,
+            io: BoardIO::new(description),
+            frame_cycles: 0,
+            frames: 0,
+        }
+    }
+
+    /// Creates a board with every ROM of the description loaded from `directory`.
+    pub fn from_directory<P: AsRef<Path>>(
+        description: BoardDescription,
+        directory: P,
+    ) -> Result<Self, BoardError> {
+        let roms = description.roms.clone();
+        let mut board = Board::new(description);
+        for rom in roms.iter() {
+            let data = std::fs::read(directory.as_ref().join(&rom.file)).map_err(BoardError::Io)?;
+            board = board.loading_rom(rom, data)?;
+        }
+
+        Ok(board)
+    }
+
+    pub fn description(&self) -> &BoardDescription {
+        &self.io.description
+    }
+
+    /// Loads the contents of `rom`, checking its size against the description.
+    pub fn loading_rom(self, rom: &RomFile, data: Vec<u8>) -> Result<Self, BoardError> {
+        let expected = rom.size.unwrap_or(data.len() as u32);
+        if data.len() != expected as usize || rom.address as usize + data.len() > 0x10000 {
+            return Err(BoardError::RomSize {
+                file: rom.file.clone(),
+                expected,
+                found: data.len(),
+            });
+        }
+
+        let mut board = self;
+        let range = rom.address as usize..rom.address as usize + data.len();
+        board.rom_image[range.clone()].copy_from_slice(&data);
+        board.cpu.memory[range.clone()].copy_from_slice(&data);
+        for address in range {
+            board = board.writing_through_map(address as u16);
+        }
+
+        Ok(board)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn setting_input(self, control: &str, pressed: bool) -> Self {
+        Board {
+            io: self.io.setting_input(control, pressed),
+            ..self
+        }
+    }
+
+    pub fn setting_dip(self, name: &str, value: u8) -> Result<Self, BoardError> {
+        let io = self.io.setting_dip(name, value)?;
+
+        Ok(Board { io, ..self })
+    }
+
+    /// Runs until the end of the current frame.
+    pub fn running_frame(self) -> Self {
+        let cycles_per_frame = self.description().clock_hz / 60;
+        let mut board = self;
+
+        for &(until, interrupt) in [(cycles_per_frame / 2, 1), (cycles_per_frame, 2)].iter() {
+            while board.frame_cycles < until {
+                board = board.stepping();
+            }
+
+            if board.cpu.interrupt_enabled {
+                board.cpu = board.cpu.generating_interrupt(interrupt);
+                board = board.applying_memory_map();
+            }
+        }
+
+        Board {
+            frame_cycles: board.frame_cycles - cycles_per_frame,
+            frames: board.frames + 1,
+            ..board
+        }
+    }
+
+    /// Evaluates a single instruction.
+    pub fn stepping(self) -> Self {
+        let mut io = self.io;
+        let cpu = self.cpu.evaluating_next(Some(&mut io));
+        let frame_cycles = self.frame_cycles + cpu.last_cycles() as u32;
+
+        Board {
+            cpu,
+            io,
+            frame_cycles,
+            ..self
+        }
+        .applying_memory_map()
+    }
+
+    // The core sees flat RAM, so writes of the last instruction are fixed up after the fact
+    fn applying_memory_map(self) -> Self {
+        let mut board = self;
+        for i in 0..board.cpu.last_memory_writes().len() {
+            let address = board.cpu.last_memory_writes()[i];
+            board = board.writing_through_map(address);
+        }
+
+        board
+    }
+
+    fn writing_through_map(self, address: u16) -> Self {
+        let mut board = self;
+        let regions = &board.io.description.memory;
+
+        let target = match regions.iter().find(|region| region.contains(address)) {
+            Some(&Region::Mirror { start, of, .. }) => of + (address - start),
+            _ => address,
+        };
+        let is_rom = regions
+            .iter()
+            .any(|region| matches!(region, Region::Rom { .. }) && region.contains(target));
+        let value = if is_rom {
+            board.rom_image[target as usize]
+        } else {
+            board.cpu.memory[address as usize]
+        };
+
+        board.cpu.memory[target as usize] = value;
+        for region in regions.iter() {
+            if let Region::Mirror { start, end, of } = *region {
+                if (of..=of + (end - start)).contains(&target) {
+                    board.cpu.memory[(start + (target - of)) as usize] = value;
+                }
+            }
+        }
+
+        board
+    }
+
+    /// Video memory, 32 bytes per row of 256 pixels with the least significant bit leftmost.
+    pub fn framebuffer(&self) -> &[u8] {
+        let start = self.description().video_ram as usize;
+
+        &self.cpu.memory[start..start + WIDTH * HEIGHT / 8]
+    }
+
+    /// Whether the pixel at `x` (0-255) on row `y` (0-223) of the framebuffer is lit.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let byte = self.framebuffer()[y * WIDTH / 8 + x / 8];
+
+        byte & (1 << (x % 8)) != 0
+    }
+
+    /// The framebuffer as a 256×224 black and white PNG, in memory orientation.
+    pub fn png(&self) -> Vec<u8> {
+        let rows: Vec<Vec<u8>> = self
+            .framebuffer()
+            .chunks(WIDTH / 8)
+            .map(|row| row.iter().map(|byte| byte.reverse_bits()).collect())
+            .collect();
+
+        png::encode_1bpp(WIDTH as u32, HEIGHT as u32, &rows)
+    }
+}
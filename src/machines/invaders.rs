@@ -0,0 +1,296 @@
+//! Taito Space Invaders, as found on the Midway 8080 board.
+//!
+//! The machine runs headless: frames are stepped explicitly and the video memory can be read as
+//! packed pixels or encoded as a PNG.
+
+use std::path::Path;
+
+use crate::emulator::{IOHandler, State8080};
+use crate::machines::png;
+
+/// The CPU runs at 2 MHz.
+pub const CLOCK_HZ: u32 = 2_000_000;
+
+/// Cycles in a 60 Hz frame.
+pub const CYCLES_PER_FRAME: u32 = CLOCK_HZ / 60;
+
+/// Width of the framebuffer as stored in memory. The monitor is mounted rotated 90° counter
+/// clockwise, so this is the height of the picture seen by the player.
+pub const WIDTH: usize = 256;
+
+pub const HEIGHT: usize = 224;
+
+pub const VIDEO_RAM_START: u16 = 0x2400;
+
+pub const VIDEO_RAM_END: u16 = 0x3fff;
+
+/// MAME names of the four 2K ROMs, in load order from 0x0000.
+pub const ROM_FILES: [&str; 4] = ["invaders.h", "invaders.g", "invaders.f", "invaders.e"];
+
+/// Controls wired to input ports 0, 1 and 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Coin,
+    P1Start,
+    P2Start,
+    P1Shot,
+    P1Left,
+    P1Right,
+    P2Shot,
+    P2Left,
+    P2Right,
+    Tilt,
+}
+
+/// Settings of the DIP switches read through port 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DipSwitches {
+    /// Ships per game, from 3 to 6.
+    pub ships: u8,
+    /// Awards the extra ship at 1000 points instead of 1500.
+    pub extra_ship_at_1000: bool,
+    /// Hides the coin information in the demo screen.
+    pub hide_coin_info: bool,
+}
+
+impl Default for DipSwitches {
+    fn default() -> Self {
+        DipSwitches {
+            ships: 3,
+            extra_ship_at_1000: false,
+            hide_coin_info: false,
+        }
+    }
+}
+
+/// 16-bit shift register used by the game to draw sprites at any horizontal offset. Data is
+/// written on port 4, the offset on port 2 and the result is read from port 3.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ShiftRegister {
+    value: u16,
+    offset: u8,
+}
+
+impl ShiftRegister {
+    pub fn shifting_in(self, byte: u8) -> Self {
+        ShiftRegister {
+            value: (self.value >> 8) | (byte as u16) << 8,
+            ..self
+        }
+    }
+
+    pub fn setting_offset(self, offset: u8) -> Self {
+        ShiftRegister {
+            offset: offset & 0x07,
+            ..self
+        }
+    }
+
+    pub fn result(&self) -> u8 {
+        (self.value >> (8 - self.offset)) as u8
+    }
+}
+
+/// Everything on the board besides the CPU and memory.
+#[derive(Debug, Default, Clone)]
+pub struct InvadersIO {
+    pub shift_register: ShiftRegister,
+    pub dip_switches: DipSwitches,
+    /// Last values written to the sound ports 3 and 5.
+    pub sound: [u8; 2],
+    inputs: [u8; 3],
+}
+
+impl InvadersIO {
+    pub fn setting_input(self, input: Input, pressed: bool) -> Self {
+        // Player one's controls are wired to both port 0 and port 1
+        let wiring: &[(usize, u8)] = match input {
+            Input::Coin => &[(1, 0)],
+            Input::P2Start => &[(1, 1)],
+            Input::P1Start => &[(1, 2)],
+            Input::P1Shot => &[(0, 4), (1, 4)],
+            Input::P1Left => &[(0, 5), (1, 5)],
+            Input::P1Right => &[(0, 6), (1, 6)],
+            Input::Tilt => &[(2, 2)],
+            Input::P2Shot => &[(2, 4)],
+            Input::P2Left => &[(2, 5)],
+            Input::P2Right => &[(2, 6)],
+        };
+
+        let mut inputs = self.inputs;
+        for &(port, bit) in wiring {
+            if pressed {
+                inputs[port] |= 1 << bit;
+            } else {
+                inputs[port] &= !(1 << bit);
+            }
+        }
+
+        InvadersIO { inputs, ..self }
+    }
+
+    /// Value the CPU reads from an input port.
+    pub fn input_port(&self, port: u8) -> u8 {
+        match port {
+            // Bits 1-3 are tied high
+            0 => 0x0e | self.inputs[0],
+            // Bit 3 is tied high
+            1 => 0x08 | self.inputs[1],
+            2 => {
+                let dips = &self.dip_switches;
+                let ships = dips.ships.clamp(3, 6) - 3;
+
+                ships
+                    | (dips.extra_ship_at_1000 as u8) << 3
+                    | (dips.hide_coin_info as u8) << 7
+                    | self.inputs[2]
+            }
+            3 => self.shift_register.result(),
+            _ => 0,
+        }
+    }
+
+    /// Handles a write to an output port.
+    pub fn writing_port(self, port: u8, value: u8) -> Self {
+        match port {
+            2 => InvadersIO {
+                shift_register: self.shift_register.setting_offset(value),
+                ..self
+            },
+            3 => InvadersIO {
+                sound: [value, self.sound[1]],
+                ..self
+            },
+            4 => InvadersIO {
+                shift_register: self.shift_register.shifting_in(value),
+                ..self
+            },
+            5 => InvadersIO {
+                sound: [self.sound[0], value],
+                ..self
+            },
+            // Port 6 is the watchdog, which is not emulated
+            _ => self,
+        }
+    }
+}
+
+impl IOHandler for InvadersIO {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        state.setting_a(self.input_port(port))
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        *self = std::mem::take(self).writing_port(port, state.a);
+
+        state
+    }
+}
+
+/// A complete Space Invaders machine.
+#[derive(Clone)]
+pub struct Invaders {
+    pub cpu: State8080,
+    pub io: InvadersIO,
+    frame_cycles: u32,
+    frames: u64,
+}
+
+impl Default for Invaders {
+    /// A machine without a ROM, whose memory is all NOPs.
+    fn default() -> Self {
+        Invaders::new(Vec::new())
+    }
+}
+
+impl Invaders {
+    /// Creates a machine with `rom` loaded at 0x0000.
+    pub fn new(rom: Vec<u8>) -> Self {
+        Invaders {
+            cpu: State8080::new().loading_buffer_into_memory_at(rom, 0),
+            io: InvadersIO::default(),
+            frame_cycles: 0,
+            frames: 0,
+        }
+    }
+
+    /// Creates a machine from the four ROM files in `directory`, named as in `ROM_FILES`.
+    pub fn from_directory<P: AsRef<Path>>(directory: P) -> std::io::Result<Self> {
+        let mut rom = Vec::with_capacity(0x2000);
+        for name in ROM_FILES.iter() {
+            rom.extend(std::fs::read(directory.as_ref().join(name))?);
+        }
+
+        Ok(Invaders::new(rom))
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn setting_input(self, input: Input, pressed: bool) -> Self {
+        Invaders {
+            io: self.io.setting_input(input, pressed),
+            ..self
+        }
+    }
+
+    /// Runs until the end of the current frame. The video hardware raises RST 1 when the beam
+    /// reaches the middle of the screen and RST 2 at the start of vertical blank.
+    pub fn running_frame(self) -> Self {
+        let mut machine = self;
+
+        for &(until, interrupt) in [(CYCLES_PER_FRAME / 2, 1), (CYCLES_PER_FRAME, 2)].iter() {
+            while machine.frame_cycles < until {
+                machine = machine.stepping();
+            }
+
+            if machine.cpu.interrupt_enabled {
+                machine.cpu = machine.cpu.generating_interrupt(interrupt);
+            }
+        }
+
+        Invaders {
+            frame_cycles: machine.frame_cycles - CYCLES_PER_FRAME,
+            frames: machine.frames + 1,
+            ..machine
+        }
+    }
+
+    /// Evaluates a single instruction.
+    pub fn stepping(self) -> Self {
+        let mut io = self.io;
+        let cpu = self.cpu.evaluating_next(Some(&mut io));
+        let frame_cycles = self.frame_cycles + cpu.last_cycles() as u32;
+
+        Invaders {
+            cpu,
+            io,
+            frame_cycles,
+            ..self
+        }
+    }
+
+    /// Video memory, 32 bytes per row of 256 pixels with the least significant bit leftmost.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.cpu.memory[VIDEO_RAM_START as usize..=VIDEO_RAM_END as usize]
+    }
+
+    /// Whether the pixel at `x` (0-255) on row `y` (0-223) of the framebuffer is lit.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let byte = self.framebuffer()[y * WIDTH / 8 + x / 8];
+
+        byte & (1 << (x % 8)) != 0
+    }
+
+    /// The framebuffer as a 256×224 black and white PNG, in memory orientation.
+    pub fn png(&self) -> Vec<u8> {
+        let rows: Vec<Vec<u8>> = self
+            .framebuffer()
+            .chunks(WIDTH / 8)
+            .map(|row| row.iter().map(|byte| byte.reverse_bits()).collect())
+            .collect();
+
+        png::encode_1bpp(WIDTH as u32, HEIGHT as u32, &rows)
+    }
+}
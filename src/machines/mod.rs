@@ -0,0 +1,6 @@
+//! Complete machines built around the 8080 core.
+
+pub mod altair;
+pub mod board;
+pub mod invaders;
+mod png;
@@ -0,0 +1,396 @@
+//! Python bindings, built with the `python` feature. `extension-module` builds them as a Python
+//! extension module.
+//!
+//! `emu_8080.State8080` wraps a `State8080` that is changed in place. IO goes through any Python
+//! object passed to `step` or `run`: its `input(port)` method answers IN, `output(port, value)`
+//! is called on OUT and `memory_write(address, value)` after every memory write, each of them
+//! being optional. An exception raised by one of them stops the CPU after the instruction and is
+//! raised again by `step` or `run`.
+//!
+//! ```python
+//! import emu_8080
+//!
+//! class Io:
+//!     def output(self, port, value):
+//!         print(f"OUT {port:#04x}, {value:#04x}")
+//!
+//! cpu = emu_8080.State8080()
+//! cpu.load(bytes([0x3e, 0x42, 0xd3, 0x01, 0x76]))
+//! cpu.run(1000, Io())
+//! ```
+
+// Fires on the conversions the PyO3 macros generate for `PyResult`
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::disassembler;
+use crate::emulator::{ConditionCodes, CpuVariant, IOHandler, State8080};
+
+fn variant_from_name(name: &str) -> PyResult<CpuVariant> {
+    match name {
+        "8080" => Ok(CpuVariant::Intel8080),
+        "8085" => Ok(CpuVariant::Intel8085),
+        _ => Err(PyValueError::new_err(format!("unknown CPU {:?}", name))),
+    }
+}
+
+// Forwards IO to a Python object, keeping the first exception it raises
+struct PythonIo<'a, 'py> {
+    io: Option<&'a Bound<'py, PyAny>>,
+    error: Option<PyErr>,
+}
+
+impl<'a, 'py> PythonIo<'a, 'py> {
+    fn new(io: Option<&'a Bound<'py, PyAny>>) -> Self {
+        PythonIo { io, error: None }
+    }
+
+    fn calling<A: IntoPy<Py<pyo3::types::PyTuple>>>(
+        &mut self,
+        method: &str,
+        args: A,
+    ) -> Option<Bound<'py, PyAny>> {
+        let io = self.io?;
+        if self.error.is_some() || !io.hasattr(method).unwrap_or(false) {
+            return None;
+        }
+
+        match io.call_method1(method, args) {
+            Ok(result) => Some(result),
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+
+    fn memory_writes(&mut self, state: &State8080) {
+        for &address in state.last_memory_writes() {
+            self.calling("memory_write", (address, state.memory[address as usize]));
+        }
+    }
+
+    fn finishing(self) -> PyResult<()> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl IOHandler for PythonIo<'_, '_> {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let value = match self
+            .calling("input", (port,))
+            .map(|value| value.extract::<u8>())
+        {
+            Some(Ok(value)) => value,
+            Some(Err(error)) => {
+                self.error = Some(error);
+                0xff
+            }
+            // Nothing drives the bus
+            None => 0xff,
+        };
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        self.calling("output", (port, state.a));
+
+        state
+    }
+}
+
+/// An 8080 or 8085 with 64K of memory.
+#[pyclass(name = "State8080")]
+pub struct PyState8080 {
+    state: State8080,
+}
+
+impl PyState8080 {
+    fn stepping(&mut self, io: &mut PythonIo) {
+        let state = std::mem::take(&mut self.state);
+        self.state = state.evaluating_next(Some(io));
+        io.memory_writes(&self.state);
+    }
+}
+
+#[pymethods]
+impl PyState8080 {
+    #[new]
+    #[pyo3(signature = (variant = "8080"))]
+    fn new(variant: &str) -> PyResult<Self> {
+        Ok(PyState8080 {
+            state: State8080::new().setting_variant(variant_from_name(variant)?),
+        })
+    }
+
+    #[getter]
+    fn variant(&self) -> &'static str {
+        match self.state.variant() {
+            CpuVariant::Intel8080 => "8080",
+            CpuVariant::Intel8085 => "8085",
+        }
+    }
+
+    #[getter]
+    fn a(&self) -> u8 {
+        self.state.a
+    }
+
+    #[setter]
+    fn set_a(&mut self, value: u8) {
+        self.state.a = value;
+    }
+
+    #[getter]
+    fn b(&self) -> u8 {
+        self.state.b
+    }
+
+    #[setter]
+    fn set_b(&mut self, value: u8) {
+        self.state.b = value;
+    }
+
+    #[getter]
+    fn c(&self) -> u8 {
+        self.state.c
+    }
+
+    #[setter]
+    fn set_c(&mut self, value: u8) {
+        self.state.c = value;
+    }
+
+    #[getter]
+    fn d(&self) -> u8 {
+        self.state.d
+    }
+
+    #[setter]
+    fn set_d(&mut self, value: u8) {
+        self.state.d = value;
+    }
+
+    #[getter]
+    fn e(&self) -> u8 {
+        self.state.e
+    }
+
+    #[setter]
+    fn set_e(&mut self, value: u8) {
+        self.state.e = value;
+    }
+
+    #[getter]
+    fn h(&self) -> u8 {
+        self.state.h
+    }
+
+    #[setter]
+    fn set_h(&mut self, value: u8) {
+        self.state.h = value;
+    }
+
+    #[getter]
+    fn l(&self) -> u8 {
+        self.state.l
+    }
+
+    #[setter]
+    fn set_l(&mut self, value: u8) {
+        self.state.l = value;
+    }
+
+    /// The flags byte as PUSH PSW stores it.
+    #[getter]
+    fn flags(&self) -> u8 {
+        self.state.cc.bits()
+    }
+
+    #[setter]
+    fn set_flags(&mut self, value: u8) {
+        self.state.cc = ConditionCodes::from_bits_truncate(value);
+    }
+
+    #[getter]
+    fn sp(&self) -> u16 {
+        self.state.sp
+    }
+
+    #[setter]
+    fn set_sp(&mut self, value: u16) {
+        self.state.sp = value;
+    }
+
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.state.pc
+    }
+
+    #[setter]
+    fn set_pc(&mut self, value: u16) {
+        self.state.pc = value;
+    }
+
+    #[getter]
+    fn interrupt_enabled(&self) -> bool {
+        self.state.interrupt_enabled
+    }
+
+    #[setter]
+    fn set_interrupt_enabled(&mut self, value: bool) {
+        self.state.interrupt_enabled = value;
+    }
+
+    #[getter]
+    fn halted(&self) -> bool {
+        self.state.halted
+    }
+
+    #[setter]
+    fn set_halted(&mut self, value: bool) {
+        self.state.halted = value;
+    }
+
+    /// Cycles taken by the last instruction.
+    #[getter]
+    fn last_cycles(&self) -> u8 {
+        self.state.last_cycles()
+    }
+
+    /// Reads `length` bytes from `address`, stopping at the end of memory.
+    fn read_memory<'py>(
+        &self,
+        py: Python<'py>,
+        address: u16,
+        length: usize,
+    ) -> Bound<'py, PyBytes> {
+        let memory = &self.state.memory[address as usize..];
+
+        PyBytes::new_bound(py, &memory[..length.min(memory.len())])
+    }
+
+    /// Writes `data` at `address`. Raises `ValueError` if it runs past the end of memory.
+    #[pyo3(signature = (data, address = 0))]
+    fn load(&mut self, data: &[u8], address: u16) -> PyResult<()> {
+        let start = address as usize;
+        match self.state.memory.get_mut(start..start + data.len()) {
+            Some(memory) => {
+                memory.copy_from_slice(data);
+                Ok(())
+            }
+            None => Err(PyValueError::new_err(format!(
+                "{} bytes at {:#06x} run past the end of memory",
+                data.len(),
+                address
+            ))),
+        }
+    }
+
+    #[pyo3(signature = (path, address = 0))]
+    fn load_file(&mut self, path: &str, address: u16) -> PyResult<()> {
+        let data = std::fs::read(path).map_err(|error| PyIOError::new_err(error.to_string()))?;
+
+        self.load(&data, address)
+    }
+
+    /// Evaluates one instruction, returning the cycles it took.
+    #[pyo3(signature = (io = None))]
+    fn step(&mut self, io: Option<&Bound<PyAny>>) -> PyResult<u8> {
+        let mut io = PythonIo::new(io);
+        self.stepping(&mut io);
+        io.finishing()?;
+
+        Ok(self.state.last_cycles())
+    }
+
+    /// Evaluates instructions for at least `cycles` cycles, stopping early when the CPU halts.
+    /// Returns the number of cycles run.
+    #[pyo3(signature = (cycles, io = None))]
+    fn run(&mut self, cycles: u64, io: Option<&Bound<PyAny>>) -> PyResult<u64> {
+        let mut io = PythonIo::new(io);
+        let mut run = 0;
+        while run < cycles && !self.state.halted && io.error.is_none() {
+            self.stepping(&mut io);
+            run += self.state.last_cycles() as u64;
+        }
+        io.finishing()?;
+
+        Ok(run)
+    }
+
+    /// Runs RST `int_num`, waking the CPU up from HLT.
+    fn interrupt(&mut self, int_num: u16) {
+        let state = std::mem::take(&mut self.state);
+        self.state = state.generating_interrupt(int_num);
+    }
+
+    /// The instruction at `address`, or at PC, as a `(text, size)` tuple.
+    #[pyo3(signature = (address = None))]
+    fn disassemble(&self, address: Option<u16>) -> (String, u8) {
+        let address = address.unwrap_or(self.state.pc);
+
+        disassembler::disassembling(&self.state.memory, address, self.state.variant())
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.state.save_state())
+    }
+
+    /// Restores a state saved with `save_state`, raising `ValueError` if it is not one.
+    fn load_state(&mut self, data: &[u8]) -> PyResult<()> {
+        self.state = State8080::from_save_state(data)
+            .map_err(|error| PyValueError::new_err(error.to_string()))?;
+
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        let state = &self.state;
+        format!(
+            "<State8080 a={:#04x} b={:#04x} c={:#04x} d={:#04x} e={:#04x} h={:#04x} l={:#04x} \
+             flags={:#04x} sp={:#06x} pc={:#06x}>",
+            state.a,
+            state.b,
+            state.c,
+            state.d,
+            state.e,
+            state.h,
+            state.l,
+            state.cc.bits(),
+            state.sp,
+            state.pc
+        )
+    }
+}
+
+/// Disassembles `data` as loaded at `address`, returning `(address, text, size)` tuples.
+#[pyfunction]
+#[pyo3(signature = (data, address = 0, variant = "8080"))]
+fn disassemble(data: &[u8], address: u16, variant: &str) -> PyResult<Vec<(u16, String, u8)>> {
+    let variant = variant_from_name(variant)?;
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let (text, size) = disassembler::disassembling(data, offset as u16, variant);
+        instructions.push((address.wrapping_add(offset as u16), text, size));
+        offset += size as usize;
+    }
+
+    Ok(instructions)
+}
+
+#[pymodule]
+fn emu_8080(module: &Bound<PyModule>) -> PyResult<()> {
+    module.add_class::<PyState8080>()?;
+    module.add_function(wrap_pyfunction!(disassemble, module)?)?;
+
+    Ok(())
+}
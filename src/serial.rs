@@ -0,0 +1,187 @@
+//! Host side of serial lines, used by the emulated serial cards and devices.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+
+/// Where the bytes of an emulated serial line come from and go to.
+pub trait SerialHost {
+    /// Next byte sent by the host, if one is waiting. Must not block.
+    fn receive(&mut self) -> Option<u8>;
+
+    fn transmit(&mut self, byte: u8);
+}
+
+/// Lets the caller keep a handle on a host after handing it to a machine.
+impl<H: SerialHost> SerialHost for Rc<RefCell<H>> {
+    fn receive(&mut self) -> Option<u8> {
+        self.borrow_mut().receive()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.borrow_mut().transmit(byte)
+    }
+}
+
+/// In-memory host, for tests and scripted sessions.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Buffer {
+    /// Bytes waiting to be received by the emulated machine.
+    pub input: VecDeque<u8>,
+    /// Bytes transmitted by the emulated machine.
+    pub output: Vec<u8>,
+}
+
+impl Buffer {
+    pub fn with_input(input: &[u8]) -> Self {
+        Buffer {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl SerialHost for Buffer {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+/// Receives every transmitted byte back, like a loopback plug.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Loopback {
+    queue: VecDeque<u8>,
+}
+
+impl SerialHost for Loopback {
+    fn receive(&mut self) -> Option<u8> {
+        self.queue.pop_front()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.queue.push_back(byte);
+    }
+}
+
+// Reads `reader` on a separate thread so polling it never blocks
+fn reading_in_background<R: Read + Send + 'static>(reader: R) -> Receiver<u8> {
+    let (sender, input) = mpsc::channel();
+    std::thread::spawn(move || {
+        for byte in BufReader::new(reader).bytes() {
+            match byte {
+                Ok(byte) if sender.send(byte).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+
+    input
+}
+
+/// Any pair of reader and writer, such as a named pipe, a file to replay or a child process.
+pub struct Pipe<W: Write> {
+    input: Receiver<u8>,
+    output: W,
+}
+
+impl<W: Write> Pipe<W> {
+    pub fn new<R: Read + Send + 'static>(reader: R, writer: W) -> Self {
+        Pipe {
+            input: reading_in_background(reader),
+            output: writer,
+        }
+    }
+}
+
+impl<W: Write> SerialHost for Pipe<W> {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        // Nothing sensible to do when the other end went away
+        let _ = self
+            .output
+            .write_all(&[byte])
+            .and_then(|_| self.output.flush());
+    }
+}
+
+/// The terminal the emulator runs in.
+pub struct Stdio {
+    input: Receiver<u8>,
+}
+
+impl Stdio {
+    pub fn new() -> Self {
+        Stdio {
+            input: reading_in_background(std::io::stdin()),
+        }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Stdio::new()
+    }
+}
+
+impl SerialHost for Stdio {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        let mut stdout = std::io::stdout();
+        // Nothing sensible to do when the terminal went away
+        let _ = stdout.write_all(&[byte]).and_then(|_| stdout.flush());
+    }
+}
+
+/// A TCP connection, such as a telnet client. Bytes transmitted after the connection dropped are
+/// discarded.
+pub struct Tcp {
+    stream: TcpStream,
+}
+
+impl Tcp {
+    pub fn new(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        Ok(Tcp { stream })
+    }
+
+    /// Listens on `address` and waits for a single client to connect.
+    pub fn accepting<A: ToSocketAddrs>(address: A) -> std::io::Result<Self> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+
+        Tcp::new(stream)
+    }
+}
+
+impl SerialHost for Tcp {
+    fn receive(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        loop {
+            match self.stream.write(&[byte]) {
+                Err(error) if error.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                _ => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! JavaScript API for WebAssembly builds, with the `wasm` feature.
+//!
+//! `State8080` wraps a `State8080` that is changed in place. IO goes through any object passed
+//! to `step` or `run`: its `input(port)` method answers IN, `output(port, value)` is called on OUT
+//! and `memoryWrite(address, value)` after every memory write, each of them being optional. An
+//! exception thrown by one of them stops the CPU after the instruction and is thrown again by
+//! `step` or `run`.
+//!
+//! ```js
+//! import { State8080 } from "emu_8080";
+//!
+//! const cpu = new State8080();
+//! cpu.load(new Uint8Array([0x3e, 0x42, 0xd3, 0x01, 0x76]), 0);
+//! cpu.run(1000, { output: (port, value) => console.log(port, value) });
+//! ```
+
+use js_sys::{Array, Function, RangeError, Reflect, TypeError, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::disassembler;
+use crate::emulator::{ConditionCodes, CpuVariant, IOHandler, State8080};
+
+fn variant_from_name(name: Option<String>) -> Result<CpuVariant, JsError> {
+    match name.as_deref() {
+        None | Some("8080") => Ok(CpuVariant::Intel8080),
+        Some("8085") => Ok(CpuVariant::Intel8085),
+        Some(name) => Err(JsError::new(&format!("unknown CPU {:?}", name))),
+    }
+}
+
+// Forwards IO to a JavaScript object, keeping the first exception it throws
+struct JsIo<'a> {
+    io: Option<&'a JsValue>,
+    error: Option<JsValue>,
+}
+
+impl<'a> JsIo<'a> {
+    fn new(io: Option<&'a JsValue>) -> Self {
+        JsIo { io, error: None }
+    }
+
+    fn calling(&mut self, method: &str, args: &[JsValue]) -> Option<JsValue> {
+        let io = self.io?;
+        if self.error.is_some() {
+            return None;
+        }
+        let function = Reflect::get(io, &JsValue::from_str(method))
+            .ok()?
+            .dyn_into::<Function>()
+            .ok()?;
+
+        match function.apply(io, &args.iter().collect::<Array>()) {
+            Ok(result) => Some(result),
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+
+    fn memory_writes(&mut self, state: &State8080) {
+        for &address in state.last_memory_writes() {
+            let value = state.memory[address as usize];
+            self.calling("memoryWrite", &[address.into(), value.into()]);
+        }
+    }
+
+    fn finishing(self) -> Result<(), JsValue> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl IOHandler for JsIo<'_> {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        let value = match self
+            .calling("input", &[port.into()])
+            .map(|value| value.as_f64())
+        {
+            Some(Some(value)) if value.fract() == 0.0 && (0.0..=255.0).contains(&value) => {
+                value as u8
+            }
+            Some(Some(value)) => {
+                let message = format!("input({}) returned {}, which is not a byte", port, value);
+                self.error = Some(RangeError::new(&message).into());
+                0xff
+            }
+            Some(None) => {
+                let message = format!("input({}) did not return a number", port);
+                self.error = Some(TypeError::new(&message).into());
+                0xff
+            }
+            // Nothing drives the bus
+            None => 0xff,
+        };
+
+        state.setting_a(value)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        self.calling("output", &[port.into(), state.a.into()]);
+
+        state
+    }
+}
+
+/// An 8080 or 8085 with 64K of memory.
+#[wasm_bindgen(js_name = State8080)]
+pub struct WasmState8080 {
+    state: State8080,
+}
+
+impl WasmState8080 {
+    fn stepping(&mut self, io: &mut JsIo) {
+        let state = std::mem::take(&mut self.state);
+        self.state = state.evaluating_next(Some(io));
+        io.memory_writes(&self.state);
+    }
+}
+
+#[wasm_bindgen(js_class = State8080)]
+impl WasmState8080 {
+    /// Takes `"8080"`, the default, or `"8085"`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(variant: Option<String>) -> Result<WasmState8080, JsError> {
+        Ok(WasmState8080 {
+            state: State8080::new().setting_variant(variant_from_name(variant)?),
+        })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn variant(&self) -> String {
+        match self.state.variant() {
+            CpuVariant::Intel8080 => "8080".to_string(),
+            CpuVariant::Intel8085 => "8085".to_string(),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn a(&self) -> u8 {
+        self.state.a
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_a(&mut self, value: u8) {
+        self.state.a = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn b(&self) -> u8 {
+        self.state.b
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_b(&mut self, value: u8) {
+        self.state.b = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn c(&self) -> u8 {
+        self.state.c
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_c(&mut self, value: u8) {
+        self.state.c = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn d(&self) -> u8 {
+        self.state.d
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_d(&mut self, value: u8) {
+        self.state.d = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn e(&self) -> u8 {
+        self.state.e
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_e(&mut self, value: u8) {
+        self.state.e = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn h(&self) -> u8 {
+        self.state.h
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_h(&mut self, value: u8) {
+        self.state.h = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn l(&self) -> u8 {
+        self.state.l
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_l(&mut self, value: u8) {
+        self.state.l = value;
+    }
+
+    /// The flags byte as PUSH PSW stores it.
+    #[wasm_bindgen(getter)]
+    pub fn flags(&self) -> u8 {
+        self.state.cc.bits()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_flags(&mut self, value: u8) {
+        self.state.cc = ConditionCodes::from_bits_truncate(value);
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sp(&self) -> u16 {
+        self.state.sp
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_sp(&mut self, value: u16) {
+        self.state.sp = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn pc(&self) -> u16 {
+        self.state.pc
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_pc(&mut self, value: u16) {
+        self.state.pc = value;
+    }
+
+    #[wasm_bindgen(getter, js_name = interruptEnabled)]
+    pub fn interrupt_enabled(&self) -> bool {
+        self.state.interrupt_enabled
+    }
+
+    #[wasm_bindgen(setter, js_name = interruptEnabled)]
+    pub fn set_interrupt_enabled(&mut self, value: bool) {
+        self.state.interrupt_enabled = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn halted(&self) -> bool {
+        self.state.halted
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_halted(&mut self, value: bool) {
+        self.state.halted = value;
+    }
+
+    /// Cycles taken by the last instruction.
+    #[wasm_bindgen(getter, js_name = lastCycles)]
+    pub fn last_cycles(&self) -> u8 {
+        self.state.last_cycles()
+    }
+
+    /// A copy of the whole memory.
+    pub fn memory(&self) -> Uint8Array {
+        Uint8Array::from(&self.state.memory[..])
+    }
+
+    /// A view of the memory without copying it. The view is only valid until the state is
+    /// changed or freed, and until the WebAssembly memory grows.
+    #[wasm_bindgen(js_name = memoryView)]
+    pub fn memory_view(&self) -> Uint8Array {
+        // Safety: nothing is allocated before the view is returned to JavaScript
+        unsafe { Uint8Array::view(&self.state.memory) }
+    }
+
+    /// Writes `data` at `address`, throwing if it runs past the end of memory.
+    pub fn load(&mut self, data: &[u8], address: u16) -> Result<(), JsError> {
+        let start = address as usize;
+        match self.state.memory.get_mut(start..start + data.len()) {
+            Some(memory) => {
+                memory.copy_from_slice(data);
+                Ok(())
+            }
+            None => Err(JsError::new(&format!(
+                "{} bytes at {:#06x} run past the end of memory",
+                data.len(),
+                address
+            ))),
+        }
+    }
+
+    /// Evaluates one instruction, returning the cycles it took.
+    pub fn step(&mut self, io: Option<JsValue>) -> Result<u8, JsValue> {
+        let mut io = JsIo::new(io.as_ref());
+        self.stepping(&mut io);
+        io.finishing()?;
+
+        Ok(self.state.last_cycles())
+    }
+
+    /// Evaluates instructions for at least `cycles` cycles, stopping early when the CPU halts.
+    /// Returns the number of cycles run.
+    pub fn run(&mut self, cycles: u32, io: Option<JsValue>) -> Result<u32, JsValue> {
+        let mut io = JsIo::new(io.as_ref());
+        let mut run: u32 = 0;
+        while run < cycles && !self.state.halted && io.error.is_none() {
+            self.stepping(&mut io);
+            run = run.saturating_add(self.state.last_cycles() as u32);
+        }
+        io.finishing()?;
+
+        Ok(run)
+    }
+
+    /// Runs RST `int_num`, waking the CPU up from HLT.
+    pub fn interrupt(&mut self, int_num: u16) {
+        let state = std::mem::take(&mut self.state);
+        self.state = state.generating_interrupt(int_num);
+    }
+
+    /// The instruction at `address`, or at PC.
+    pub fn disassemble(&self, address: Option<u16>) -> String {
+        let address = address.unwrap_or(self.state.pc);
+        let (text, _) =
+            disassembler::disassembling(&self.state.memory, address, self.state.variant());
+
+        text
+    }
+
+    #[wasm_bindgen(js_name = saveState)]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.state.save_state()
+    }
+
+    /// Restores a state saved with `saveState`, throwing if it is not one.
+    #[wasm_bindgen(js_name = loadState)]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), JsError> {
+        self.state = State8080::from_save_state(data)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+
+use emu_8080::machines::altair::{
+    Altair, AltairError, SerialCard, DISK_SECTORS, DISK_SECTOR_SIZE, DISK_SIZE,
+};
+use emu_8080::serial::{Buffer, SerialHost, Tcp};
+
+fn running_steps(altair: Altair, steps: usize) -> Altair {
+    (0..steps).fold(altair, |altair, _| altair.stepping())
+}
+
+#[test]
+fn sio_echoes_through_the_terminal() {
+    // loop: IN 0; RRC; JC loop; IN 1; OUT 1; JMP loop
+    let program = vec![
+        0xdb, 0x00, 0x0f, 0xda, 0x00, 0x00, 0xdb, 0x01, 0xd3, 0x01, 0xc3, 0x00, 0x00,
+    ];
+    let terminal = Rc::new(RefCell::new(Buffer::with_input(b"HELLO")));
+    let altair = Altair::new()
+        .attaching_terminal(SerialCard::Sio, Box::new(terminal.clone()))
+        .loading_image(program, 0)
+        .unwrap();
+
+    running_steps(altair, 200);
+
+    let terminal = terminal.borrow();
+    assert_eq!(terminal.output, b"HELLO");
+    assert!(terminal.input.is_empty());
+}
+
+#[test]
+fn sio2_reports_acia_status() {
+    let terminal = Rc::new(RefCell::new(Buffer::with_input(b"A")));
+    let altair = Altair::new().attaching_terminal(SerialCard::Sio2B, Box::new(terminal.clone()));
+
+    // Nothing attached to channel A
+    let (io, status) = altair.io.reading_port(0x10);
+    assert_eq!(status, 0x02);
+
+    let (io, status) = io.reading_port(0x12);
+    assert_eq!(status, 0x03);
+
+    // Master reset drops the received byte
+    let io = io.writing_port(0x12, 0x03);
+    let (io, status) = io.reading_port(0x12);
+    assert_eq!(status, 0x02);
+
+    terminal.borrow_mut().input.push_back(b'B');
+    let (io, byte) = io.reading_port(0x13);
+    assert_eq!(byte, b'B');
+    let (io, status) = io.reading_port(0x12);
+    assert_eq!(status, 0x02);
+
+    io.writing_port(0x13, b'C').writing_port(0x11, b'D');
+    assert_eq!(terminal.borrow().output, b"C");
+}
+
+#[test]
+fn front_panel_switches_and_lights() {
+    // Toggle in MVI A,0x2a; IN 0xff byte by byte
+    let mut altair = Altair::new().setting_switches(0x0100).examining();
+    for (i, &byte) in [0x3e, 0x2a, 0xdb, 0xff].iter().enumerate() {
+        altair = altair.setting_switches(0xa500 | byte as u16);
+        altair = if i == 0 {
+            altair.depositing()
+        } else {
+            altair.depositing_next()
+        };
+    }
+
+    assert_eq!(altair.address_lights(), 0x0103);
+    assert_eq!(altair.data_lights(), 0xff);
+    assert_eq!(&altair.cpu.memory[0x100..0x104], &[0x3e, 0x2a, 0xdb, 0xff]);
+
+    let altair = altair.setting_switches(0x0100).examining();
+    assert_eq!(altair.data_lights(), 0x3e);
+    let altair = running_steps(altair.setting_switches(0xa500), 2);
+    assert_eq!(altair.cpu.a, 0xa5);
+    assert_eq!(altair.cycles(), 17);
+
+    let altair = altair.resetting();
+    assert_eq!(altair.address_lights(), 0);
+}
+
+#[test]
+fn reset_restarts_a_halted_cpu() {
+    // INR A; HLT
+    let altair = Altair::new().loading_image(vec![0x3c, 0x76], 0).unwrap();
+    let altair = running_steps(altair, 4);
+    assert!(altair.cpu.halted);
+    assert_eq!(altair.cpu.a, 1);
+
+    let altair = running_steps(altair.resetting(), 1);
+    assert_eq!((altair.cpu.a, altair.cpu.pc), (2, 1));
+}
+
+#[test]
+fn images_have_to_fit_in_memory() {
+    assert!(Altair::new()
+        .loading_image(vec![0x76; 0x100], 0xff00)
+        .is_ok());
+    assert!(matches!(
+        Altair::new().loading_image(vec![0x76; 0x101], 0xff00),
+        Err(AltairError::ImageTooLarge {
+            address: 0xff00,
+            len: 0x101
+        })
+    ));
+}
+
+#[test]
+fn basic_images_run_from_their_load_address() {
+    // LXI H,message; loop: MOV A,M; ORA A; JZ done; OUT 0x11; INX H; JMP loop; done: JMP done
+    let mut image = vec![
+        0x21, 0x13, 0x00, 0x7e, 0xb7, 0xca, 0x10, 0x00, 0xd3, 0x11, 0x23, 0xc3, 0x03, 0x00, 0x00,
+        0x00, 0xc3, 0x10, 0x00,
+    ];
+    image.extend(b"OK\r\n\0");
+    let terminal = Rc::new(RefCell::new(Buffer::default()));
+
+    let altair = Altair::new()
+        .attaching_terminal(SerialCard::Sio2A, Box::new(terminal.clone()))
+        .loading_image(image, 0)
+        .unwrap()
+        .running(2_000);
+
+    assert_eq!(altair.cpu.pc, 0x10);
+    assert_eq!(terminal.borrow().output, b"OK\r\n");
+}
+
+#[test]
+fn disk_controller_reads_and_writes_sectors() {
+    let mut image = vec![0; DISK_SIZE];
+    let sector = (DISK_SECTORS + 3) * DISK_SECTOR_SIZE;
+    image[sector..sector + 3].copy_from_slice(&[0xde, 0xad, 0xbe]);
+    let mut altair = Altair::new().inserting_disk(1, image).unwrap();
+    assert!(matches!(
+        Altair::new().inserting_disk(4, Vec::new()),
+        Err(AltairError::NoSuchDrive(4))
+    ));
+
+    let io = std::mem::take(&mut altair.io);
+    let (io, status) = io.reading_port(0x08);
+    assert_eq!(status, 0xff);
+
+    // Select drive 1 and load the head, which is on track 0
+    let io = io.writing_port(0x08, 0x01).writing_port(0x09, 0x04);
+    let (io, status) = io.reading_port(0x08);
+    assert_eq!(status, 0x21);
+
+    // Step in and wait for sector 3
+    let mut io = io.writing_port(0x09, 0x01);
+    loop {
+        let (next, position) = io.reading_port(0x09);
+        io = next;
+        if position == 0xc0 | 3 << 1 {
+            break;
+        }
+    }
+    let mut bytes = vec![];
+    for _ in 0..3 {
+        let (next, byte) = io.reading_port(0x0a);
+        io = next;
+        bytes.push(byte);
+    }
+    assert_eq!(bytes, [0xde, 0xad, 0xbe]);
+
+    // Writing starts at the beginning of the next sector
+    let (io, _) = io.reading_port(0x09);
+    let io = io
+        .writing_port(0x09, 0x80)
+        .writing_port(0x0a, 0x12)
+        .writing_port(0x0a, 0x34);
+    let (io, status) = io.reading_port(0x08);
+    assert_eq!(status & 0x01, 0);
+
+    altair.io = io;
+    let disk = altair.disk_image(1).unwrap();
+    let written = (DISK_SECTORS + 4) * DISK_SECTOR_SIZE;
+    assert_eq!(&disk[written..written + 3], &[0x12, 0x34, 0x00]);
+    assert!(altair.disk_image(0).is_none());
+}
+
+#[test]
+fn tcp_terminal() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = std::thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"X").unwrap();
+        let mut reply = [0];
+        stream.read_exact(&mut reply).unwrap();
+
+        reply[0]
+    });
+
+    let mut host = Tcp::new(listener.accept().unwrap().0).unwrap();
+    let received = loop {
+        if let Some(byte) = host.receive() {
+            break byte;
+        }
+    };
+    host.transmit(received + 1);
+
+    assert_eq!(received, b'X');
+    assert_eq!(client.join().unwrap(), b'Y');
+}
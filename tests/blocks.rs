@@ -0,0 +1,274 @@
+mod utils;
+
+use emu_8080::blocks::BlockCache;
+use emu_8080::emulator::{ConditionCodes, CpuVariant, DummyIOHandler, InterruptPin, State8080};
+
+use utils::{assert_suite_passed, console_output, create_state_with_rom};
+
+const ORIGIN: u16 = 0x0100;
+
+fn loading(state: State8080, bytes: &[u8]) -> State8080 {
+    let mut state = state.loading_buffer_into_memory_at(bytes.to_vec(), ORIGIN);
+    state.pc = ORIGIN;
+
+    state
+}
+
+// Runs `state` a block at a time and an instruction at a time side by side, failing as soon as
+// they differ, until it jumps to 0x0000, halts or `max_blocks` blocks have run. `between` can
+// change both states after each block. Returns the state and the CP/M console output.
+fn comparing<F: Fn(State8080, usize) -> State8080>(
+    mut cache: BlockCache,
+    state: State8080,
+    max_blocks: usize,
+    between: F,
+) -> (State8080, String) {
+    let mut blocked = state.clone();
+    let mut stepped = state;
+    let mut console = String::new();
+
+    for block in 0..max_blocks {
+        let pc = blocked.pc;
+        blocked = blocked.evaluating_block::<DummyIOHandler>(&mut cache, None);
+        let mut cycles = 0;
+        for _ in 0..cache.last_instructions() {
+            stepped = stepped.evaluating_next::<DummyIOHandler>(None);
+            cycles += stepped.last_cycles() as u32;
+        }
+
+        assert!(
+            blocked.save_state() == stepped.save_state(),
+            "Block {} at {:#06x} ended at {:#06x}, instructions end at {:#06x}",
+            block,
+            pc,
+            blocked.pc,
+            stepped.pc
+        );
+        assert_eq!(blocked.last_memory_writes(), stepped.last_memory_writes());
+        assert_eq!(cache.last_cycles(), cycles);
+
+        if let Some(output) = console_output(&blocked) {
+            console.push_str(&output);
+        }
+        if blocked.pc == 0 || blocked.halted {
+            break;
+        }
+        blocked = between(blocked, block);
+        stepped = between(stepped, block);
+    }
+
+    (blocked, console)
+}
+
+fn comparing_suite(cache: BlockCache, path: &str) -> String {
+    let state = create_state_with_rom(path);
+    let (state, console) = comparing(cache, state, usize::MAX, |state, _| state);
+    assert_eq!(state.pc, 0);
+
+    console
+}
+
+#[test]
+fn runs_8080pre_like_single_steps() {
+    let output = comparing_suite(BlockCache::new(), "./resources/cpu_tests/8080PRE.COM");
+
+    assert_eq!(output, "8080 Preliminary tests complete");
+}
+
+#[test]
+fn runs_8080pre_with_lazy_flags() {
+    let cache = BlockCache::new().setting_lazy_flags(true);
+    let output = comparing_suite(cache, "./resources/cpu_tests/8080PRE.COM");
+
+    assert_eq!(output, "8080 Preliminary tests complete");
+}
+
+#[test]
+fn runs_tst8080_like_single_steps() {
+    let output = comparing_suite(BlockCache::new(), "./resources/cpu_tests/TST8080.COM");
+
+    assert_suite_passed(&output, "CPU IS OPERATIONAL", &["CPU HAS FAILED", "ERROR"]);
+}
+
+#[test]
+fn runs_tst8080_with_lazy_flags() {
+    let cache = BlockCache::new().setting_lazy_flags(true);
+    let output = comparing_suite(cache, "./resources/cpu_tests/TST8080.COM");
+
+    assert_suite_passed(&output, "CPU IS OPERATIONAL", &["CPU HAS FAILED", "ERROR"]);
+}
+
+#[test]
+fn runs_cpudiag_like_single_steps() {
+    let output = comparing_suite(BlockCache::new(), "./resources/cpu_tests/cpudiag.bin");
+
+    assert_suite_passed(&output, "CPU IS OPERATIONAL", &["CPU HAS FAILED", "ERROR"]);
+}
+
+#[test]
+fn runs_cpudiag_with_lazy_flags() {
+    let cache = BlockCache::new().setting_lazy_flags(true);
+    let output = comparing_suite(cache, "./resources/cpu_tests/cpudiag.bin");
+
+    assert_suite_passed(&output, "CPU IS OPERATIONAL", &["CPU HAS FAILED", "ERROR"]);
+}
+
+#[test]
+fn self_modifying_code_sees_its_writes() {
+    let program = [
+        0x31, 0x00, 0x02, // LXI SP,0x0200
+        0x06, 0x03, // MVI B,3
+        0x21, 0x0c, 0x01, // LXI H,0x010c
+        0x7e, // MOV A,M
+        0xee, 0x08, // XRI 0x08, turning INR D into INR E and back
+        0x77, // MOV M,A
+        0x14, // INR D
+        0x05, // DCR B
+        0xc2, 0x05, 0x01, // JNZ 0x0105
+        0x76, // HLT
+    ];
+    let state = loading(State8080::new(), &program);
+    let (state, _) = comparing(BlockCache::new(), state, 100, |state, _| state);
+
+    assert!(state.halted);
+    assert_eq!((state.d, state.e), (1, 2));
+}
+
+#[test]
+fn interrupts_are_taken_between_blocks() {
+    let program = [
+        0x31, 0x00, 0x02, // LXI SP,0x0200
+        0x3e, 0x08, // MVI A,0x08
+        0x30, // SIM, unmasking every interrupt
+        0xfb, // EI
+        0x04, // INR B
+        0x0c, // INR C
+        0xc3, 0x07, 0x01, // JMP 0x0107
+    ];
+    let handler = [
+        0x14, // INR D
+        0xfb, // EI
+        0xc9, // RET
+    ];
+    let state = State8080::new()
+        .setting_variant(CpuVariant::Intel8085)
+        .loading_buffer_into_memory_at(handler.to_vec(), 0x3c);
+    let cache = BlockCache::new().setting_lazy_flags(true);
+    let (state, _) = comparing(
+        cache,
+        loading(state, &program),
+        40,
+        |state, block| match block {
+            10 | 20 => state.setting_interrupt_pin(InterruptPin::Rst75, true),
+            15 => state.setting_interrupt_pin(InterruptPin::Rst75, false),
+            _ => state,
+        },
+    );
+
+    assert_eq!(state.d, 2);
+}
+
+#[test]
+fn invalidated_blocks_are_decoded_again() {
+    let mut cache = BlockCache::new();
+    // MVI A,1; HLT
+    let state = loading(State8080::new(), &[0x3e, 0x01, 0x76]);
+    let state = state.evaluating_block::<DummyIOHandler>(&mut cache, None);
+    assert_eq!(state.a, 1);
+    assert_eq!(cache.len(), 1);
+
+    // MVI B,1
+    let mut state = state.setting_memory_at(0x06, ORIGIN);
+    state.pc = ORIGIN;
+    state.halted = false;
+    cache.invalidate(ORIGIN);
+    assert!(cache.is_empty());
+    let state = state.evaluating_block::<DummyIOHandler>(&mut cache, None);
+    assert_eq!(state.b, 1);
+    assert_eq!(cache.last_instructions(), 2);
+}
+
+#[test]
+fn blocks_crossing_pages_are_dropped_from_either_page() {
+    let mut cache = BlockCache::new();
+    // MVI A,1 on the last two bytes of a page; MVI B,2; HLT on the next
+    let mut state =
+        State8080::new().loading_buffer_into_memory_at(vec![0x3e, 0x01, 0x06, 0x02, 0x76], 0x01fe);
+    for page in [0x0200, 0x0100, 0x0200, 0x0100] {
+        state.pc = 0x01fe;
+        state.halted = false;
+        state = state.evaluating_block::<DummyIOHandler>(&mut cache, None);
+        assert_eq!((state.a, state.b, cache.last_instructions()), (1, 2, 3));
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate(page);
+        assert!(cache.is_empty());
+    }
+}
+
+// Small deterministic generator for sampled operands
+struct Lcg(u32);
+
+impl Lcg {
+    fn next_byte(&mut self) -> u8 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (self.0 >> 16) as u8
+    }
+}
+
+#[test]
+fn lazy_flags_match_eager_flags() {
+    // Arithmetic and logic on registers, then everything else that reads or writes flags
+    let mut op_codes: Vec<u8> = (0x80..=0xbf).collect();
+    op_codes.extend_from_slice(&[
+        0x3c, 0x3d, 0x27, 0x17, 0x1f, 0x07, 0x0f, 0x37, 0x3f, 0x09, 0x03, 0x08,
+    ]);
+    let mut random = Lcg(7);
+
+    for &variant in &[CpuVariant::Intel8080, CpuVariant::Intel8085] {
+        let mut cache = BlockCache::new().setting_lazy_flags(true);
+        let mut blocked = State8080::new().setting_variant(variant);
+        let mut stepped = blocked.clone();
+
+        for &first in &op_codes {
+            for &second in &op_codes {
+                // first; second; PUSH PSW; HLT
+                let program = [first, second, 0xf5, 0x76];
+                for state in [&mut blocked, &mut stepped].iter_mut() {
+                    state.memory[ORIGIN as usize..ORIGIN as usize + 4].copy_from_slice(&program);
+                    state.pc = ORIGIN;
+                    state.sp = 0x8000;
+                    state.halted = false;
+                }
+                cache.invalidate(ORIGIN);
+
+                for _ in 0..4 {
+                    let registers = [random.next_byte(), random.next_byte(), random.next_byte()];
+                    let cc = ConditionCodes::from_bits_truncate(random.next_byte());
+                    for state in [&mut blocked, &mut stepped].iter_mut() {
+                        state.a = registers[0];
+                        state.b = registers[1];
+                        state.c = registers[2];
+                        state.cc = cc;
+                        state.pc = ORIGIN;
+                        state.halted = false;
+                    }
+
+                    blocked = blocked.evaluating_block::<DummyIOHandler>(&mut cache, None);
+                    for _ in 0..4 {
+                        stepped = stepped.evaluating_next::<DummyIOHandler>(None);
+                    }
+                    assert!(blocked.halted);
+                    assert_eq!(
+                        (blocked.a, blocked.cc, blocked.memory[0x7ffe]),
+                        (stepped.a, stepped.cc, stepped.memory[0x7ffe]),
+                        "{:#04x} then {:#04x} on {:?}",
+                        first,
+                        second,
+                        variant
+                    );
+                }
+            }
+        }
+    }
+}
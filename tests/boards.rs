@@ -0,0 +1,237 @@
+use emu_8080::machines::board::{Board, BoardDescription, BoardError, BoardIO, Region, RomFile};
+use emu_8080::machines::invaders::{DipSwitches, Input, Invaders, InvadersIO};
+
+const INVADERS: &str = include_str!("../resources/boards/invaders.toml");
+
+fn invaders_board() -> BoardDescription {
+    BoardDescription::from_toml(INVADERS).unwrap()
+}
+
+#[test]
+fn bundled_descriptions_parse() {
+    for entry in std::fs::read_dir("resources/boards").unwrap() {
+        let path = entry.unwrap().path();
+        let description = BoardDescription::from_file(&path).unwrap();
+
+        assert!(!description.roms.is_empty(), "{}", path.display());
+    }
+
+    let description = invaders_board();
+    assert_eq!(description.name, "Space Invaders");
+    assert_eq!(description.roms.len(), 4);
+    assert_eq!(
+        description.memory[2],
+        Region::Mirror {
+            start: 0x4000,
+            end: 0x5fff,
+            of: 0x2000
+        }
+    );
+}
+
+#[test]
+fn described_ports_match_the_invaders_machine() {
+    let controls = [
+        ("coin", Input::Coin),
+        ("p1_start", Input::P1Start),
+        ("p2_start", Input::P2Start),
+        ("p1_shot", Input::P1Shot),
+        ("p1_left", Input::P1Left),
+        ("p1_right", Input::P1Right),
+        ("p2_shot", Input::P2Shot),
+        ("p2_left", Input::P2Left),
+        ("p2_right", Input::P2Right),
+        ("tilt", Input::Tilt),
+    ];
+
+    for (i, &(name, input)) in controls.iter().enumerate() {
+        let board = BoardIO::new(invaders_board())
+            .setting_input(name, true)
+            .setting_dip("ships", i as u8 % 4)
+            .and_then(|board| board.setting_dip("hide_coin_info", 1))
+            .unwrap();
+        let mut invaders = InvadersIO::default().setting_input(input, true);
+        invaders.dip_switches = DipSwitches {
+            ships: 3 + i as u8 % 4,
+            hide_coin_info: true,
+            ..Default::default()
+        };
+
+        for port in 0..3 {
+            assert_eq!(
+                board.input_port(port),
+                invaders.input_port(port),
+                "{} on port {}",
+                name,
+                port
+            );
+        }
+    }
+
+    let board = BoardIO::new(invaders_board())
+        .writing_port(4, 0xab)
+        .writing_port(4, 0xcd)
+        .writing_port(2, 3)
+        .writing_port(3, 0x01)
+        .writing_port(6, 0xff);
+    assert_eq!(board.input_port(3), 0x6d);
+    assert_eq!(board.latch("sound1"), Some(0x01));
+    assert_eq!(board.latch("sound2"), Some(0x00));
+    assert_eq!(board.latch("watchdog"), None);
+}
+
+#[test]
+fn frames_run_like_the_invaders_machine() {
+    // JMP 0x0040; at 0x0040: LXI SP,0x2400; EI; LXI H,0x2400; loop: INR M; INX H; JMP loop, with
+    // RST 1 and RST 2 handlers that count into 0x2000 and 0x2001
+    let mut rom = vec![0; 0x2000];
+    rom[..3].copy_from_slice(&[0xc3, 0x40, 0x00]);
+    rom[0x40..0x4c].copy_from_slice(&[
+        0x31, 0x00, 0x24, 0xfb, 0x21, 0x00, 0x24, 0x34, 0x23, 0xc3, 0x47, 0x00,
+    ]);
+    for &(vector, counter) in [(0x08, 0x00), (0x10, 0x01)].iter() {
+        // PUSH H; LXI H,counter; INR M; POP H; EI; RET
+        rom[vector..vector + 8]
+            .copy_from_slice(&[0xe5, 0x21, counter, 0x20, 0x34, 0xe1, 0xfb, 0xc9]);
+    }
+
+    let description = invaders_board();
+    let mut board = Board::new(description.clone());
+    for (i, rom_file) in description.roms.iter().enumerate() {
+        board = board
+            .loading_rom(rom_file, rom[i * 0x800..(i + 1) * 0x800].to_vec())
+            .unwrap();
+    }
+    let mut invaders = Invaders::new(rom);
+
+    for _ in 0..3 {
+        board = board.running_frame();
+        invaders = invaders.running_frame();
+    }
+
+    assert_eq!(board.frames(), 3);
+    assert_eq!(board.cpu.pc, invaders.cpu.pc);
+    assert_eq!(&board.cpu.memory[..0x4000], &invaders.cpu.memory[..0x4000]);
+    assert_eq!(board.cpu.memory[0x2000], 3);
+    assert_eq!(board.framebuffer(), invaders.framebuffer());
+    assert_eq!(board.png(), invaders.png());
+}
+
+#[test]
+fn rom_writes_are_ignored_and_mirrors_follow_ram() {
+    // MVI A,0x55; STA 0x0010; STA 0x4100; INR A; STA 0x2200
+    let rom = [
+        0x3e, 0x55, 0x32, 0x10, 0x00, 0x32, 0x00, 0x41, 0x3c, 0x32, 0x00, 0x22,
+    ];
+    let rom_file = RomFile {
+        file: "test.bin".to_string(),
+        address: 0,
+        size: None,
+    };
+    let mut board = Board::new(invaders_board())
+        .loading_rom(&rom_file, rom.to_vec())
+        .unwrap();
+    for _ in 0..5 {
+        board = board.stepping();
+    }
+
+    assert_eq!(board.cpu.memory[0x0010], 0x00);
+    assert_eq!(board.cpu.memory[0x2100], 0x55);
+    assert_eq!(board.cpu.memory[0x4100], 0x55);
+    assert_eq!(board.cpu.memory[0x2200], 0x56);
+    assert_eq!(board.cpu.memory[0x4200], 0x56);
+}
+
+#[test]
+fn active_low_inputs_clear_their_bits() {
+    let description = BoardDescription::from_toml(
+        r#"
+        name = "Active low"
+
+        [[input]]
+        port = 1
+        value = 0xff
+        active_low = true
+        controls = { coin = 0, fire = 7 }
+
+        [[dip]]
+        name = "difficulty"
+        port = 1
+        mask = 0x30
+        value = 2
+        "#,
+    )
+    .unwrap();
+    let io = BoardIO::new(description);
+    assert_eq!(io.input_port(1), 0xff);
+    assert_eq!(io.dip("difficulty"), Some(2));
+
+    let io = io.setting_input("fire", true).setting_input("coin", true);
+    assert_eq!(io.input_port(1), 0x7e);
+
+    let io = io
+        .setting_input("coin", false)
+        .setting_dip("unknown", 1)
+        .unwrap();
+    assert_eq!(io.input_port(1), 0x7f);
+    assert_eq!(io.input_port(0), 0x00);
+    assert!(matches!(
+        io.setting_dip("difficulty", 4),
+        Err(BoardError::DipSetting { value: 4, .. })
+    ));
+}
+
+#[test]
+fn invalid_descriptions_are_rejected() {
+    let invalid = [
+        "name = \"Unknown field\"\nspeed = 1",
+        "name = \"Bad region\"\n[[memory]]\nkind = \"flash\"\nstart = 0\nend = 1",
+        "name = \"Backwards\"\n[[memory]]\nkind = \"ram\"\nstart = 2\nend = 1",
+        "name = \"Chained\"\n[[memory]]\nkind = \"mirror\"\nstart = 0x4000\nend = 0x5fff\nof = 0x4000",
+        "name = \"Partly chained\"\n[[memory]]\nkind = \"mirror\"\nstart = 0x6000\nend = 0x67ff\nof = 0x4000\n[[memory]]\nkind = \"mirror\"\nstart = 0x4400\nend = 0x47ff\nof = 0x2000",
+        "name = \"Bit 8\"\n[[input]]\nport = 0\ncontrols = { coin = 8 }",
+        "name = \"Too big\"\n[[rom]]\nfile = \"a\"\naddress = 0xf000\nsize = 0x2000",
+        "name = \"Overlap\"\n[[memory]]\nkind = \"rom\"\nstart = 0\nend = 0x1fff\n[[memory]]\nkind = \"ram\"\nstart = 0x1000\nend = 0x2fff",
+        "name = \"No mask\"\n[[dip]]\nname = \"ships\"\nport = 2\nmask = 0",
+        "name = \"Wide\"\n[[dip]]\nname = \"ships\"\nport = 2\nmask = 0x0c\nvalue = 4",
+    ];
+
+    for source in invalid.iter() {
+        match BoardDescription::from_toml(source) {
+            Err(BoardError::Parse(_)) | Err(BoardError::Invalid(_)) => {}
+            other => panic!("{:?} for {}", other, source),
+        }
+    }
+
+    let description = invaders_board();
+    let result = Board::new(description.clone()).loading_rom(&description.roms[0], vec![0; 0x400]);
+    assert!(matches!(
+        result.err(),
+        Some(BoardError::RomSize {
+            expected: 0x800,
+            found: 0x400,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn roms_load_from_a_directory() {
+    let description = BoardDescription::from_file("resources/boards/lrescue.toml").unwrap();
+    let directory = std::env::temp_dir().join(format!("board-roms-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    for (i, rom) in description.roms.iter().enumerate() {
+        std::fs::write(directory.join(&rom.file), vec![i as u8 + 1; 0x800]).unwrap();
+    }
+
+    let board = Board::from_directory(description.clone(), &directory).unwrap();
+    std::fs::remove_file(directory.join(&description.roms[5].file)).unwrap();
+    let missing = Board::from_directory(description, &directory);
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(board.description().name, "Lunar Rescue");
+    assert_eq!(board.cpu.memory[0x1fff], 4);
+    assert_eq!(board.cpu.memory[0x4000], 5);
+    assert_eq!(board.cpu.memory[0x4fff], 6);
+    assert!(matches!(missing.err(), Some(BoardError::Io(_))));
+}
@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emu_8080::bus::{Access, BusFault, IoBus, Unmapped};
+use emu_8080::devices::i8253::I8253;
+use emu_8080::devices::i8255::{Pins, Port, I8255};
+use emu_8080::emulator::{IOHandler, State8080};
+
+// Answers reads with the port number and remembers writes
+#[derive(Default)]
+struct Echo {
+    writes: Vec<(u8, u8)>,
+}
+
+impl IOHandler for Echo {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        state.setting_a(port)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        self.writes.push((port, state.a));
+        state
+    }
+}
+
+fn reading(bus: &mut IoBus, port: u8) -> u8 {
+    bus.inp(State8080::new(), port).a
+}
+
+fn writing(bus: &mut IoBus, port: u8, value: u8) {
+    bus.out(State8080::new().setting_a(value), port);
+}
+
+#[test]
+fn dispatches_by_port_and_range() {
+    let echo = Rc::new(RefCell::new(Echo::default()));
+    let mut bus = IoBus::new()
+        .mapping(0x10..=0x17, Box::new(echo.clone()))
+        .mapping_port(0x20, Box::new(Echo::default()));
+
+    assert_eq!(reading(&mut bus, 0x13), 0x13);
+    assert_eq!(reading(&mut bus, 0x20), 0x20);
+    writing(&mut bus, 0x17, 0xaa);
+    writing(&mut bus, 0x20, 0xbb);
+    assert_eq!(echo.borrow().writes, [(0x17, 0xaa)]);
+
+    assert!(bus.is_mapped(0x10));
+    assert!(!bus.is_mapped(0x18));
+}
+
+#[test]
+fn first_mapping_wins_where_they_overlap() {
+    let first = Rc::new(RefCell::new(Echo::default()));
+    let second = Rc::new(RefCell::new(Echo::default()));
+    let mut bus = IoBus::new()
+        .mapping_port(0x04, Box::new(first.clone()))
+        .mapping(0x00..=0xff, Box::new(second.clone()));
+
+    writing(&mut bus, 0x04, 1);
+    writing(&mut bus, 0x05, 2);
+    assert_eq!(first.borrow().writes, [(0x04, 1)]);
+    assert_eq!(second.borrow().writes, [(0x05, 2)]);
+}
+
+#[test]
+fn unmapped_ports_float() {
+    let mut bus = IoBus::new();
+    assert_eq!(reading(&mut bus, 0x42), 0xff);
+    writing(&mut bus, 0x42, 0x00);
+    assert!(bus.faults().is_empty());
+
+    let mut bus = bus.setting_unmapped(Unmapped::Logging);
+    assert_eq!(reading(&mut bus, 0x42), 0xff);
+    assert!(bus.faults().is_empty());
+}
+
+#[test]
+fn unmapped_accesses_can_be_faults() {
+    // MVI A,0x55; OUT 0x99; IN 0x98
+    let program = [0x3e, 0x55, 0xd3, 0x99, 0xdb, 0x98];
+    let mut state = State8080::new().loading_buffer_into_memory_at(program.to_vec(), 0x100);
+    state.pc = 0x100;
+    let mut bus = IoBus::new().setting_unmapped(Unmapped::Error);
+    for _ in 0..3 {
+        state = state.evaluating_next(Some(&mut bus));
+    }
+
+    assert_eq!(state.a, 0xff);
+    assert_eq!(
+        bus.faults(),
+        [
+            BusFault {
+                port: 0x99,
+                access: Access::Out(0x55),
+                pc: 0x102,
+            },
+            BusFault {
+                port: 0x98,
+                access: Access::In,
+                pc: 0x104,
+            },
+        ]
+    );
+    assert_eq!(
+        bus.faults()[0].to_string(),
+        "OUT of 0x55 to unmapped port 0x99 at 0x0102"
+    );
+
+    let bus = bus.clearing_faults();
+    assert!(bus.faults().is_empty());
+}
+
+#[test]
+fn composes_devices_for_the_cpu() {
+    // The timer on 0x40-0x43, the PPI on 0x80-0x83 with LEDs on port B
+    let timer = Rc::new(RefCell::new(I8253::new()));
+    let leds = Rc::new(RefCell::new(Pins::default()));
+    let mut bus = IoBus::new()
+        .mapping(0x40..=0x43, Box::new(timer.clone()))
+        .mapping(
+            0x80..=0x83,
+            Box::new(I8255::new().attaching(Port::B, Box::new(leds.clone()))),
+        );
+
+    // MVI A,0x80; OUT 0x83; MVI A,0x34; OUT 0x43; MVI A,0x0a; OUT 0x40; XRA A; OUT 0x40;
+    // MVI A,0x99; OUT 0x81
+    let program = [
+        0x3e, 0x80, 0xd3, 0x83, 0x3e, 0x34, 0xd3, 0x43, 0x3e, 0x0a, 0xd3, 0x40, 0xaf, 0xd3, 0x40,
+        0x3e, 0x99, 0xd3, 0x81,
+    ];
+    let mut state = State8080::new().loading_buffer_into_memory_at(program.to_vec(), 0);
+    for _ in 0..10 {
+        state = state.evaluating_next(Some(&mut bus));
+    }
+
+    assert_eq!(leds.borrow().outputs, [0x00, 0x99]);
+
+    // The machine still clocks the shared timer, counter 0 counting down from 10 in mode 2
+    let clocked = std::mem::take(&mut *timer.borrow_mut()).ticking(4);
+    *timer.borrow_mut() = clocked;
+    assert_eq!(reading(&mut bus, 0x40), 7);
+}
@@ -6,59 +6,131 @@ use std::io::Read;
 use std::path::Path;
 use std::rc::Rc;
 
-use emu_8080::emulator::{DummyIOHandler, State8080};
+use emu_8080::lockstep::{BusEvent, Lockstep, Reference, Registers};
 use i8080::{Cpu, Linear, Memory};
 use utils::{create_state_with_rom, print_output};
 
 #[test]
 fn compare_on_8080pre() {
-    run_comparison("./resources/cpu_tests/8080PRE.COM", false);
+    run_comparison("./resources/cpu_tests/8080PRE.COM");
 }
 
 #[test]
 fn compare_on_tst8080() {
-    run_comparison("./resources/cpu_tests/TST8080.COM", false);
+    run_comparison("./resources/cpu_tests/TST8080.COM");
 }
 
 #[test]
 fn compare_on_cpudiag() {
-    run_comparison("./resources/cpu_tests/cpudiag.bin", false);
+    run_comparison("./resources/cpu_tests/cpudiag.bin");
 }
 
 #[test]
 fn compare_on_cputest() {
-    run_comparison("./resources/cpu_tests/CPUTEST.COM", false);
+    run_comparison("./resources/cpu_tests/CPUTEST.COM");
 }
 
 // This takes a few minutes to finish (3-4 mins on a high-end Intel MBP)
 #[test]
 fn compare_on_8080exm() {
-    run_comparison("./resources/cpu_tests/8080EXM.COM", false);
+    run_comparison("./resources/cpu_tests/8080EXM.COM");
 }
 
-// This is super valuable for debugging where things are going south, but the current implementation
-// clones the old state to know what failed, which makes it very slow. Takes an hour for me to finish.
-#[test]
-#[ignore]
-fn compare_on_8080exm_print_failed_instruction() {
-    run_comparison("./resources/cpu_tests/8080EXM.COM", true);
+/// Linear memory that remembers what the reference wrote during the last step.
+struct RecordingMemory {
+    inner: Linear,
+    writes: Vec<(u16, u8)>,
 }
 
-fn run_comparison(path: impl AsRef<Path>, keep_old_state: bool) {
-    let mut state = create_state_with_rom(path.as_ref());
+impl Memory for RecordingMemory {
+    fn get(&self, a: u16) -> u8 {
+        self.inner.get(a)
+    }
+
+    fn set(&mut self, a: u16, v: u8) {
+        self.writes.push((a, v));
+        self.inner.set(a, v);
+    }
+}
 
-    let mem = Rc::new(RefCell::new(Linear::new()));
-    load_test(mem.clone(), path.as_ref());
-    let mut cpu = Cpu::power_up(mem.clone());
-    mem.borrow_mut().set(0x0005, 0xc9);
-    // Because tests used the pseudo instruction ORG 0x0100
-    cpu.reg.pc = 0x0100;
+struct I8080 {
+    cpu: Cpu,
+    mem: Rc<RefCell<RecordingMemory>>,
+}
+
+impl I8080 {
+    fn with_rom(path: impl AsRef<Path>) -> Self {
+        let mem = Rc::new(RefCell::new(RecordingMemory {
+            inner: Linear::new(),
+            writes: Vec::new(),
+        }));
+        load_test(&mut mem.borrow_mut().inner, path.as_ref());
+        mem.borrow_mut().inner.set(0x0005, 0xc9);
+
+        let mut cpu = Cpu::power_up(mem.clone());
+        // Because tests used the pseudo instruction ORG 0x0100
+        cpu.reg.pc = 0x0100;
+
+        I8080 { cpu, mem }
+    }
+}
+
+impl Reference for I8080 {
+    fn registers(&self) -> Registers {
+        let reg = &self.cpu.reg;
+
+        Registers {
+            a: reg.a,
+            b: reg.b,
+            c: reg.c,
+            d: reg.d,
+            e: reg.e,
+            h: reg.h,
+            l: reg.l,
+            f: reg.f,
+            sp: reg.sp,
+            pc: reg.pc,
+        }
+    }
+
+    fn read_memory(&self, address: u16) -> u8 {
+        self.mem.borrow().get(address)
+    }
+
+    // i8080 has no port devices, so port traffic is derived from the executed opcode
+    fn step(&mut self, events: &mut Vec<BusEvent>) {
+        let pc = self.cpu.reg.pc;
+        let opcode = self.read_memory(pc);
+        let port = self.read_memory(pc.wrapping_add(1));
+        if opcode == 0xd3 {
+            events.push(BusEvent::PortOut {
+                port,
+                value: self.cpu.reg.a,
+            });
+        }
+
+        self.cpu.next();
+
+        if opcode == 0xdb {
+            events.push(BusEvent::PortIn {
+                port,
+                value: self.cpu.reg.a,
+            });
+        }
+        events.extend(
+            self.mem
+                .borrow_mut()
+                .writes
+                .drain(..)
+                .map(|(address, value)| BusEvent::MemoryWrite { address, value }),
+        );
+    }
+}
 
-    let mut old_state = if keep_old_state {
-        Some(state.clone())
-    } else {
-        None
-    };
+fn run_comparison(path: impl AsRef<Path>) {
+    let state = create_state_with_rom(path.as_ref());
+    let reference = I8080::with_rom(path.as_ref());
+    let mut lockstep = Lockstep::new(state, reference);
 
     let filename = path
         .as_ref()
@@ -67,27 +139,28 @@ fn run_comparison(path: impl AsRef<Path>, keep_old_state: bool) {
         .unwrap_or_default();
     println!("Starting running suite {}...", filename);
 
+    let mismatches = lockstep.compare();
+    assert!(
+        mismatches.is_empty(),
+        "Initial state mismatch: {:?}",
+        mismatches
+    );
+
     loop {
-        if keep_old_state {
-            compare_states(&state, &cpu, old_state);
-            old_state = Some(state.clone());
-        } else {
-            compare_states(&state, &cpu, None);
+        if let Err(divergence) = lockstep.step() {
+            panic!("{}", divergence);
         }
 
-        state = state.evaluating_next::<DummyIOHandler>(None);
-        cpu.next();
-
-        print_output(&state);
-        print_reference_output(&cpu);
+        print_output(&lockstep.subject);
+        print_reference_output(&lockstep.reference.cpu);
 
-        if state.pc == 0 {
+        if lockstep.subject.pc == 0 {
             println!();
             println!("Jumped to 0x0000, halting");
             break;
         }
 
-        if cpu.reg.pc == 0x00 {
+        if lockstep.reference.cpu.reg.pc == 0x00 {
             println!();
             println!("Reference Jumped to 0x0000, halting");
             break;
@@ -95,133 +168,6 @@ fn run_comparison(path: impl AsRef<Path>, keep_old_state: bool) {
     }
 }
 
-fn compare_states(state: &State8080, cpu: &Cpu, old_state: Option<State8080>) {
-    if cpu.reg.a != state.a {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg A mismatch: Should be {}, but is {}",
-            cpu.reg.a, state.a
-        );
-    } else if cpu.reg.b != state.b {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg B mismatch: Should be {}, but is {}",
-            cpu.reg.b, state.b
-        );
-    } else if cpu.reg.c != state.c {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg C mismatch: Should be {}, but is {}",
-            cpu.reg.c, state.c
-        );
-    } else if cpu.reg.d != state.d {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg D mismatch: Should be {}, but is {}",
-            cpu.reg.d, state.d
-        );
-    } else if cpu.reg.e != state.e {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg E mismatch: Should be {}, but is {}",
-            cpu.reg.e, state.e
-        );
-    } else if cpu.reg.h != state.h {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg H mismatch: Should be {}, but is {}",
-            cpu.reg.h, state.h
-        );
-    } else if cpu.reg.l != state.l {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg L mismatch: Should be {}, but is {}",
-            cpu.reg.l, state.l
-        );
-    } else if cpu.reg.sp != state.sp {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg SP mismatch: Should be {}, but is {}",
-            cpu.reg.sp, state.sp
-        );
-    } else if cpu.reg.pc != state.pc {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg PC mismatch: Should be {}, but is {}",
-            cpu.reg.pc, state.pc
-        );
-    } else if cpu.mem.borrow().get(cpu.reg.pc) != state.memory[state.pc as usize] {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Memory data at PC mismatch: Should be {}, but is {}",
-            cpu.mem.borrow().get(cpu.reg.pc),
-            state.memory[state.pc as usize]
-        );
-    } else if cpu.mem.borrow().get(cpu.reg.sp) != state.memory[state.sp as usize] {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Memory data at PC mismatch: Should be {}, but is {}",
-            cpu.mem.borrow().get(cpu.reg.sp),
-            state.memory[state.sp as usize]
-        );
-    } else if cpu.reg.f != state.cc.bits() {
-        old_state.and_then(|s| -> Option<()> {
-            s.log_current_instruction();
-            None
-        });
-
-        panic!(
-            "Reg F mismatch: Should be {:b}, but is {:b}",
-            cpu.reg.f,
-            state.cc.bits()
-        );
-    }
-}
-
 fn print_reference_output(cpu: &Cpu) {
     if cpu.reg.pc == 0x05 {
         if cpu.reg.c == 0x09 {
@@ -242,10 +188,10 @@ fn print_reference_output(cpu: &Cpu) {
     }
 }
 
-fn load_test(mem: Rc<RefCell<Linear>>, path: impl AsRef<Path>) {
+fn load_test(mem: &mut Linear, path: impl AsRef<Path>) {
     let mut file = File::open(path.as_ref()).unwrap();
     let mut buf = Vec::new();
     file.read_to_end(&mut buf).unwrap();
-    mem.borrow_mut().data[0x0100..(buf.len() + 0x0100)].clone_from_slice(&buf[..]);
+    mem.data[0x0100..(buf.len() + 0x0100)].clone_from_slice(&buf[..]);
     println!("Test loaded: {:?}", path.as_ref());
 }
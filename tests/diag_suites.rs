@@ -1,28 +1,156 @@
 mod utils;
 
-use utils::run_suite;
+use emu_8080::emulator::State8080;
+
+use utils::{assert_suite_passed, create_state_with_rom, run_loaded_suite, run_suite};
 
 #[test]
 fn run_8080pre() {
-    run_suite("./resources/cpu_tests/8080PRE.COM");
+    let output = run_suite("./resources/cpu_tests/8080PRE.COM");
+
+    // On failure the suite only prints the address of the failed check
+    assert_eq!(output, "8080 Preliminary tests complete");
 }
 
 #[test]
 fn run_tst8080() {
-    run_suite("./resources/cpu_tests/TST8080.COM");
+    let output = run_suite("./resources/cpu_tests/TST8080.COM");
+
+    assert_suite_passed(&output, "CPU IS OPERATIONAL", &["CPU HAS FAILED", "ERROR"]);
 }
 
 #[test]
 fn run_cpudiag() {
-    run_suite("./resources/cpu_tests/cpudiag.bin");
+    let output = run_suite("./resources/cpu_tests/cpudiag.bin");
+
+    assert_suite_passed(&output, "CPU IS OPERATIONAL", &["CPU HAS FAILED", "ERROR"]);
 }
 
 #[test]
 fn run_cputest() {
-    run_suite("./resources/cpu_tests/CPUTEST.COM");
+    let output = run_suite("./resources/cpu_tests/CPUTEST.COM");
+
+    assert_suite_passed(
+        &output,
+        "CPU TESTS OK",
+        &["CPU FAILED", "ERROR", "GO FIND A Z80"],
+    );
+    assert!(output.contains("CPU IS 8080/8085"));
 }
 
 #[test]
 fn run_8080exm() {
-    run_suite("./resources/cpu_tests/8080EXM.COM");
+    let output = run_suite("./resources/cpu_tests/8080EXM.COM");
+
+    assert_suite_passed(&output, "Tests complete", &["ERROR"]);
+    assert_eq!(output.matches("PASS!").count(), 25);
+}
+
+// CRCs reported by 8080EXER on real silicon, from resources/cpu_tests/8080EXER.PNG
+const SILICON_CRCS: [(&str, u32); 25] = [
+    ("dad <b,d,h,sp>", 0x14474ba6),
+    ("aluop nn", 0x9e922f9e),
+    ("aluop <b,c,d,e,h,l,m,a>", 0xcf762c86),
+    ("<daa,cma,stc,cmc>", 0xbb3f030c),
+    ("<inr,dcr> a", 0xadb6460e),
+    ("<inr,dcr> b", 0x83ed1345),
+    ("<inx,dcx> b", 0xf79287cd),
+    ("<inr,dcr> c", 0xe5f6721b),
+    ("<inr,dcr> d", 0x15b5579a),
+    ("<inx,dcx> d", 0x7f4e2501),
+    ("<inr,dcr> e", 0xcf2ab396),
+    ("<inr,dcr> h", 0x12b2952c),
+    ("<inx,dcx> h", 0x9f2b23c0),
+    ("<inr,dcr> l", 0xff57d356),
+    ("<inr,dcr> m", 0x92e963bd),
+    ("<inx,dcx> sp", 0xd5702fab),
+    ("lhld nnnn", 0xa9c3d5cb),
+    ("shld nnnn", 0xe8864f26),
+    ("lxi <b,d,h,sp>,nnnn", 0xfcf46e12),
+    ("ldax <b,d>", 0x2b821d5f),
+    ("mvi <b,c,d,e,h,l,m,a>,nn", 0xeaa72044),
+    ("mov <bcdehla>,<bcdehla>", 0x10b58cee),
+    ("sta nnnn / lda nnnn", 0xed57af72),
+    ("<rlc,rrc,ral,rar>", 0xe0d89235),
+    ("stax <b,d>", 0x2b0471e9),
+];
+
+// Address of the zero-terminated list of test descriptors in 8080EXER.COM, see 8080EXER.PRN
+const EXERCISER_TESTS: usize = 0x013a;
+
+// Each test descriptor is a flag mask, three 20 byte machine states and the expected CRC. Zeroing
+// the expected CRCs makes the exerciser print the CRC it computed for every group, as
+// "<name>.....  ERROR **** crc expected:00000000 found:<crc>"
+fn clearing_expected_crcs(state: State8080) -> State8080 {
+    let mut state = state;
+    let mut entry = EXERCISER_TESTS;
+
+    loop {
+        let test = u16::from_le_bytes([state.memory[entry], state.memory[entry + 1]]) as usize;
+        if test == 0 {
+            break;
+        }
+
+        let crc = test + 1 + 3 * 20;
+        state.memory[crc..crc + 4].copy_from_slice(&[0; 4]);
+        entry += 2;
+    }
+
+    state
+}
+
+fn parse_exerciser_crcs(output: &str) -> Vec<(String, u32)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let found = line.find("found:")?;
+            // The exerciser ends its lines with LF CR, so names start with the CR
+            let name = line[..line.find("..")?].trim().to_string();
+            let crc = u32::from_str_radix(line[found + 6..].trim(), 16).ok()?;
+
+            Some((name, crc))
+        })
+        .collect()
+}
+
+#[test]
+fn run_8080exer() {
+    println!("Starting running suite 8080EXER.COM...");
+    let state = create_state_with_rom("./resources/cpu_tests/8080EXER.COM");
+    let output = run_loaded_suite(clearing_expected_crcs(state));
+    let reported = parse_exerciser_crcs(&output);
+
+    println!();
+    println!("{:<28}{:<12}{:<12}", "group", "silicon", "emulated");
+    let mut failed = Vec::new();
+    for (name, silicon) in SILICON_CRCS.iter() {
+        let found = reported
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, crc)| *crc);
+        let result = match found {
+            Some(crc) if crc == *silicon => "PASS",
+            Some(_) => "FAIL",
+            None => "MISSING",
+        };
+        println!(
+            "{:<28}{:08x}    {:<12}{}",
+            name,
+            silicon,
+            found.map(|crc| format!("{:08x}", crc)).unwrap_or_default(),
+            result
+        );
+
+        if result != "PASS" {
+            failed.push(*name);
+        }
+    }
+
+    assert!(output.contains("Tests complete"));
+    assert_eq!(reported.len(), SILICON_CRCS.len());
+    assert!(
+        failed.is_empty(),
+        "Groups differing from silicon: {:?}",
+        failed
+    );
 }
@@ -0,0 +1,242 @@
+//! Tests the C API, which wasm builds of the JavaScript bindings leave out.
+#![cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::ptr::null_mut;
+
+use emu_8080::emulator::State8080;
+use emu_8080::ffi::*;
+
+#[test]
+fn registers_and_flags() {
+    unsafe {
+        let state = state8080_new();
+        state8080_set_register(state, Register8080::B, 0x1234);
+        state8080_set_register(state, Register8080::Sp, 0x1234);
+        assert_eq!(state8080_register(state, Register8080::B), 0x34);
+        assert_eq!(state8080_register(state, Register8080::Sp), 0x1234);
+        assert_eq!(state8080_register(state, Register8080::C), 0);
+
+        state8080_set_flag(state, Flag8080::Z, true);
+        state8080_set_flag(state, Flag8080::Cy, true);
+        assert!(state8080_flag(state, Flag8080::Cy));
+        // Bit 1 is always set on the 8080
+        assert_eq!(state8080_register(state, Register8080::Flags), 0x43);
+        state8080_set_register(state, Register8080::Flags, 0x80);
+        assert!(state8080_flag(state, Flag8080::S));
+        assert!(!state8080_flag(state, Flag8080::Z));
+
+        state8080_set_interrupt_enabled(state, true);
+        assert!(state8080_interrupt_enabled(state));
+
+        state8080_free(state);
+    }
+}
+
+#[test]
+fn memory_ranges() {
+    unsafe {
+        let state = state8080_new();
+        let program = [1, 2, 3, 4];
+        let state =
+            state8080_loading_buffer_into_memory_at(state, program.as_ptr(), program.len(), 0xfffe);
+
+        let mut read = [0xaa; 4];
+        let copied = state8080_read_memory(state, 0xfffd, read.as_mut_ptr(), read.len());
+        assert_eq!(copied, 3);
+        assert_eq!(read, [0, 1, 2, 0xaa]);
+
+        let copied = state8080_write_memory(state, 0x100, program.as_ptr(), program.len());
+        assert_eq!(copied, 4);
+        state8080_read_memory(state, 0x100, read.as_mut_ptr(), read.len());
+        assert_eq!(read, program);
+
+        state8080_free(state);
+    }
+}
+
+#[test]
+fn running_until_halted_and_interrupting() {
+    unsafe {
+        // LXI SP,0x1000; EI; MVI A,0x42; HLT
+        let program = [0x31, 0x00, 0x10, 0xfb, 0x3e, 0x42, 0x76];
+        let state = state8080_loading_buffer_into_memory_at(
+            state8080_new(),
+            program.as_ptr(),
+            program.len(),
+            0,
+        );
+
+        let run = state8080_running(state, 1000);
+        assert_eq!(run, 10 + 4 + 7 + 7);
+        assert!(state8080_halted(state));
+        assert_eq!(state8080_register(state, Register8080::A), 0x42);
+        assert_eq!(state8080_last_cycles(state), 7);
+
+        // Halted, nothing runs until an interrupt
+        assert_eq!(state8080_running(state, 1000), 0);
+        let state = state8080_evaluating_next(state);
+        assert_eq!(state8080_register(state, Register8080::Pc), 0x07);
+        assert_eq!(state8080_last_cycles(state), 4);
+
+        state8080_generating_interrupt(state, 1);
+        assert!(!state8080_halted(state));
+        assert_eq!(state8080_register(state, Register8080::Pc), 0x08);
+        assert_eq!(state8080_register(state, Register8080::Sp), 0x0ffe);
+        assert!(state8080_running(state, 1) >= 1);
+
+        state8080_free(state);
+    }
+}
+
+// What the C side of the callbacks below sees
+#[derive(Default)]
+struct Host {
+    outputs: Vec<(u8, u8)>,
+    memory_writes: Vec<(u16, u8)>,
+}
+
+extern "C" fn input(_user_data: *mut c_void, port: u8) -> u8 {
+    port.wrapping_add(1)
+}
+
+extern "C" fn output(user_data: *mut c_void, port: u8, value: u8) {
+    let host = unsafe { &mut *(user_data as *mut Host) };
+    host.outputs.push((port, value));
+}
+
+extern "C" fn memory_write(user_data: *mut c_void, address: u16, value: u8) {
+    let host = unsafe { &mut *(user_data as *mut Host) };
+    host.memory_writes.push((address, value));
+}
+
+#[test]
+fn io_through_c_callbacks() {
+    let mut host = Host::default();
+    let mut io = IoHandler8080 {
+        user_data: &mut host as *mut Host as *mut c_void,
+        input: Some(input),
+        output: Some(output),
+        memory_write: Some(memory_write),
+    };
+
+    unsafe {
+        // IN 0x10; OUT 0x20; LXI SP,0x1000; PUSH PSW; HLT
+        let program = [0xdb, 0x10, 0xd3, 0x20, 0x31, 0x00, 0x10, 0xf5, 0x76];
+        let state = state8080_loading_buffer_into_memory_at(
+            state8080_new(),
+            program.as_ptr(),
+            program.len(),
+            0,
+        );
+
+        let state = state8080_evaluating_next_with_io(state, &mut io);
+        assert_eq!(state8080_register(state, Register8080::A), 0x11);
+
+        let run = state8080_running_with_io(state, &mut io, 1000);
+        assert_eq!(run, 10 + 10 + 11 + 7);
+        state8080_free(state);
+    }
+
+    assert_eq!(host.outputs, [(0x20, 0x11)]);
+    assert_eq!(host.memory_writes, [(0x0fff, 0x11), (0x0ffe, 0x02)]);
+
+    // Without callbacks IN reads an open bus
+    let mut io = IoHandler8080 {
+        user_data: std::ptr::null_mut(),
+        input: None,
+        output: None,
+        memory_write: None,
+    };
+    unsafe {
+        let program = [0xdb, 0x10, 0xd3, 0x20];
+        let state = state8080_loading_buffer_into_memory_at(
+            state8080_new(),
+            program.as_ptr(),
+            program.len(),
+            0,
+        );
+        assert_eq!(state8080_running_with_io(state, &mut io, 20), 20);
+        assert_eq!(state8080_register(state, Register8080::A), 0xff);
+        state8080_free(state);
+    }
+}
+
+#[test]
+fn stepping_and_loading_in_place() {
+    unsafe {
+        let state = state8080_new();
+        // MVI A,0x42; OUT 0x20
+        let program = [0x3e, 0x42, 0xd3, 0x20];
+        assert_eq!(
+            state8080_load_buffer(state, program.as_ptr(), program.len(), 0x100),
+            Status8080::Ok
+        );
+        assert_eq!(
+            state8080_load_buffer(state, program.as_ptr(), program.len(), 0xfffd),
+            Status8080::OutOfRange
+        );
+        state8080_set_register(state, Register8080::Pc, 0x100);
+
+        // The same pointer stays valid across steps
+        assert_eq!(state8080_step(state, std::ptr::null_mut()), Status8080::Ok);
+        assert_eq!(state8080_register(state, Register8080::A), 0x42);
+
+        let mut host = Host::default();
+        let mut io = IoHandler8080 {
+            user_data: &mut host as *mut Host as *mut c_void,
+            input: None,
+            output: Some(output),
+            memory_write: None,
+        };
+        assert_eq!(state8080_step(state, &mut io), Status8080::Ok);
+        assert_eq!(host.outputs, [(0x20, 0x42)]);
+
+        let path = CString::new("resources/does-not-exist.bin").unwrap();
+        assert_eq!(
+            state8080_load_file(state, path.as_ptr(), 0),
+            Status8080::ReadFailed
+        );
+        let invalid = CString::new(vec![0xff, 0xfe]).unwrap();
+        assert_eq!(
+            state8080_load_file(state, invalid.as_ptr(), 0),
+            Status8080::InvalidPath
+        );
+        assert!(state8080_loading_file_into_memory_at(state, path.as_ptr(), 0).is_null());
+
+        state8080_free(state);
+    }
+}
+
+#[test]
+fn null_pointers_are_reported() {
+    let null: *mut State8080 = null_mut();
+    unsafe {
+        assert_eq!(state8080_step(null, null_mut()), Status8080::NullPointer);
+        assert_eq!(
+            state8080_load_buffer(null, [0u8].as_ptr(), 1, 0),
+            Status8080::NullPointer
+        );
+        assert!(state8080_evaluating_next(null).is_null());
+        assert_eq!(state8080_register(null, Register8080::Pc), 0);
+        assert_eq!(state8080_running(null, 100), 0);
+        state8080_set_flag(null, Flag8080::Z, true);
+
+        let state = state8080_new();
+        assert_eq!(
+            state8080_load_file(state, std::ptr::null(), 0),
+            Status8080::NullPointer
+        );
+        assert_eq!(
+            state8080_load_buffer(state, std::ptr::null(), 1, 0),
+            Status8080::NullPointer
+        );
+        assert_eq!(
+            state8080_load_buffer(state, std::ptr::null(), 0, 0),
+            Status8080::Ok
+        );
+        assert_eq!(state8080_read_memory(state, 0, std::ptr::null_mut(), 4), 0);
+        state8080_free(state);
+    }
+}
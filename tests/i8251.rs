@@ -0,0 +1,187 @@
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use emu_8080::devices::i8251::{
+    COMMAND_DTR, COMMAND_ERROR_RESET, COMMAND_INTERNAL_RESET, COMMAND_RTS, COMMAND_RX_ENABLE,
+    COMMAND_TX_ENABLE, I8251, STATUS_DSR, STATUS_OVERRUN_ERROR, STATUS_RX_READY, STATUS_TX_EMPTY,
+    STATUS_TX_READY,
+};
+use emu_8080::emulator::State8080;
+use emu_8080::serial::{Buffer, Loopback, Pipe, SerialHost};
+
+const DATA: u8 = 0x00;
+const CONTROL: u8 = 0x01;
+
+// Asynchronous, 16x clock, 8 data bits, no parity and 1 stop bit
+const MODE: u8 = 0x4e;
+const COMMAND: u8 = COMMAND_TX_ENABLE | COMMAND_DTR | COMMAND_RX_ENABLE | COMMAND_RTS;
+
+// 16 clocks of 13 cycles for 10 bits
+const CHARACTER_CYCLES: u32 = 13 * 16 * 10;
+
+fn programmed(usart: I8251) -> I8251 {
+    usart
+        .writing_port(CONTROL, MODE)
+        .writing_port(CONTROL, COMMAND)
+}
+
+fn reading_status(usart: I8251) -> (I8251, u8) {
+    usart.reading_port(CONTROL)
+}
+
+#[test]
+fn loopback_without_clock() {
+    let usart = programmed(I8251::new().attaching(Box::new(Loopback::default())));
+    let (usart, status) = reading_status(usart);
+    assert_eq!(status, STATUS_TX_READY | STATUS_TX_EMPTY);
+    assert!(usart.dtr() && usart.rts());
+
+    let usart = usart.writing_port(DATA, b'Z');
+    let (usart, status) = reading_status(usart);
+    assert_eq!(status, STATUS_TX_READY | STATUS_RX_READY | STATUS_TX_EMPTY);
+    assert!(usart.rx_ready());
+
+    let (usart, byte) = usart.reading_port(DATA);
+    assert_eq!(byte, b'Z');
+    assert!(!usart.rx_ready());
+}
+
+#[test]
+fn transmission_takes_a_character_time() {
+    let host = Rc::new(RefCell::new(Buffer::default()));
+    let usart = programmed(
+        I8251::new()
+            .attaching(Box::new(host.clone()))
+            .setting_clock_period(13),
+    )
+    .writing_port(DATA, 0x41)
+    .writing_port(DATA, 0x42);
+
+    // The first character went straight to the shift register, the second waits in the buffer
+    let (usart, status) = reading_status(usart);
+    assert_eq!(status, 0);
+    assert!(!usart.tx_ready());
+
+    let usart = usart.ticking(CHARACTER_CYCLES - 1);
+    assert!(host.borrow().output.is_empty());
+    let usart = usart.ticking(1);
+    assert_eq!(host.borrow().output, [0x41]);
+    let (usart, status) = reading_status(usart);
+    assert_eq!(status, STATUS_TX_READY);
+
+    let usart = usart.ticking(CHARACTER_CYCLES);
+    let (_, status) = reading_status(usart);
+    assert_eq!(status, STATUS_TX_READY | STATUS_TX_EMPTY);
+    assert_eq!(host.borrow().output, [0x41, 0x42]);
+}
+
+#[test]
+fn transmitter_waits_for_tx_enable() {
+    let host = Rc::new(RefCell::new(Buffer::default()));
+    let usart = I8251::new()
+        .attaching(Box::new(host.clone()))
+        .writing_port(CONTROL, MODE)
+        .writing_port(CONTROL, COMMAND_RX_ENABLE)
+        .writing_port(DATA, 0x41);
+    assert!(host.borrow().output.is_empty());
+    assert!(!usart.tx_ready());
+
+    usart.writing_port(CONTROL, COMMAND_TX_ENABLE);
+    assert_eq!(host.borrow().output, [0x41]);
+}
+
+#[test]
+fn slow_reads_overrun_until_error_reset() {
+    let host = Buffer::with_input(&[0x01, 0x02]);
+    let usart = programmed(
+        I8251::new()
+            .attaching(Box::new(host))
+            .setting_clock_period(13),
+    );
+
+    let usart = usart.ticking(CHARACTER_CYCLES);
+    let (usart, status) = reading_status(usart);
+    assert_eq!(status & STATUS_RX_READY, STATUS_RX_READY);
+    assert_eq!(status & STATUS_OVERRUN_ERROR, 0);
+
+    let usart = usart.ticking(CHARACTER_CYCLES);
+    let (usart, status) = reading_status(usart);
+    assert_eq!(status & STATUS_OVERRUN_ERROR, STATUS_OVERRUN_ERROR);
+    let (usart, byte) = usart.reading_port(DATA);
+    assert_eq!(byte, 0x02);
+
+    let usart = usart.writing_port(CONTROL, COMMAND | COMMAND_ERROR_RESET);
+    let (_, status) = reading_status(usart);
+    assert_eq!(status, STATUS_TX_READY | STATUS_TX_EMPTY);
+}
+
+#[test]
+fn character_length_and_timing_follow_the_mode() {
+    // 7 data bits, even parity, 2 stop bits and a 64x clock: 11 bits of 64 clocks
+    let host = Rc::new(RefCell::new(Buffer::default()));
+    let usart = I8251::new()
+        .attaching(Box::new(host.clone()))
+        .setting_clock_period(2)
+        .writing_port(CONTROL, 0xfb)
+        .writing_port(CONTROL, COMMAND)
+        .writing_port(DATA, 0xff)
+        .ticking(2 * 64 * 11 - 1);
+    assert!(host.borrow().output.is_empty());
+
+    usart.ticking(1);
+    assert_eq!(host.borrow().output, [0x7f]);
+}
+
+#[test]
+fn internal_reset_expects_a_new_mode() {
+    let host = Rc::new(RefCell::new(Buffer::default()));
+    let usart = programmed(I8251::new().attaching(Box::new(host.clone())))
+        .setting_dsr(true)
+        .writing_port(CONTROL, COMMAND_INTERNAL_RESET);
+    let (usart, status) = reading_status(usart);
+    assert_eq!(status, STATUS_TX_READY | STATUS_TX_EMPTY | STATUS_DSR);
+    assert!(!usart.dtr());
+
+    // Synchronous mode with two sync characters before the command
+    let usart = usart
+        .writing_port(CONTROL, 0x0c)
+        .writing_port(CONTROL, COMMAND_TX_ENABLE)
+        .writing_port(CONTROL, COMMAND_TX_ENABLE);
+    assert!(!usart.tx_ready());
+    usart
+        .writing_port(CONTROL, COMMAND_TX_ENABLE)
+        .writing_port(DATA, 0x16);
+    assert_eq!(host.borrow().output, [0x16]);
+}
+
+#[test]
+fn plugs_into_the_cpu() {
+    // MVI A,MODE; OUT 0x41; MVI A,COMMAND; OUT 0x41; MVI A,'!'; OUT 0x40; IN 0x41; MOV B,A;
+    // IN 0x40
+    let program = [
+        0x3e, MODE, 0xd3, 0x41, 0x3e, COMMAND, 0xd3, 0x41, 0x3e, b'!', 0xd3, 0x40, 0xdb, 0x41,
+        0x47, 0xdb, 0x40,
+    ];
+    let mut state = State8080::new().loading_buffer_into_memory_at(program.to_vec(), 0);
+    let mut usart = I8251::new().attaching(Box::new(Loopback::default()));
+    for _ in 0..9 {
+        state = state.evaluating_next(Some(&mut usart));
+    }
+
+    assert_eq!(state.b, STATUS_TX_READY | STATUS_RX_READY | STATUS_TX_EMPTY);
+    assert_eq!(state.a, b'!');
+}
+
+#[test]
+fn pipes_are_read_in_the_background() {
+    let mut pipe = Pipe::new(Cursor::new(b"hi".to_vec()), std::io::sink());
+    let mut received = vec![];
+    while received.len() < 2 {
+        received.extend(pipe.receive());
+    }
+    pipe.transmit(b'!');
+
+    assert_eq!(received, b"hi");
+    assert_eq!(pipe.receive(), None);
+}
@@ -0,0 +1,227 @@
+use emu_8080::devices::i8253::I8253;
+use emu_8080::emulator::State8080;
+
+const CONTROL: u8 = 0x03;
+
+// Control word for `counter`, reading and writing both bytes, in binary
+fn programmed(timer: I8253, counter: u8, mode: u8, count: u16) -> I8253 {
+    timer
+        .writing_port(CONTROL, counter << 6 | 0x30 | mode << 1)
+        .writing_port(counter, count as u8)
+        .writing_port(counter, (count >> 8) as u8)
+}
+
+// Output of `counter` after each of `clocks` clocks
+fn clocking_outputs(timer: I8253, counter: usize, clocks: usize) -> (I8253, Vec<bool>) {
+    let mut timer = timer;
+    let mut outputs = vec![];
+    for _ in 0..clocks {
+        timer = timer.ticking(1);
+        outputs.push(timer.output(counter));
+    }
+
+    (timer, outputs)
+}
+
+fn latched_count(timer: I8253, counter: u8) -> (I8253, u16) {
+    let timer = timer.writing_port(CONTROL, counter << 6);
+    let (timer, low) = timer.reading_port(counter);
+    let (timer, high) = timer.reading_port(counter);
+
+    (timer, (high as u16) << 8 | low as u16)
+}
+
+#[test]
+fn mode_0_interrupts_on_terminal_count() {
+    let timer = programmed(I8253::new(), 0, 0, 5);
+    assert!(!timer.output(0));
+
+    // One clock loads the count, five more count it down
+    let (timer, outputs) = clocking_outputs(timer, 0, 6);
+    assert_eq!(outputs, [false, false, false, false, false, true]);
+
+    // The output stays high while the counter wraps around
+    let (timer, count) = latched_count(timer.ticking(2), 0);
+    assert_eq!(count, 0xfffe);
+    assert!(timer.output(0));
+
+    // A low gate stops counting
+    let timer = programmed(timer, 0, 0, 3).setting_gate(0, false);
+    let (timer, outputs) = clocking_outputs(timer, 0, 10);
+    assert!(outputs.iter().all(|&output| !output));
+    let (_, outputs) = clocking_outputs(timer.setting_gate(0, true), 0, 3);
+    assert_eq!(outputs, [false, false, true]);
+}
+
+#[test]
+fn mode_1_is_a_retriggerable_one_shot() {
+    let timer = programmed(I8253::new(), 1, 1, 3).setting_gate(1, false);
+    let (timer, outputs) = clocking_outputs(timer, 1, 4);
+    assert!(outputs.iter().all(|&output| output));
+
+    let timer = timer.setting_gate(1, true);
+    let (timer, outputs) = clocking_outputs(timer, 1, 5);
+    assert_eq!(outputs, [false, false, false, true, true]);
+
+    // Retriggering in the middle restarts the full count
+    let timer = timer.setting_gate(1, false).setting_gate(1, true);
+    let (timer, outputs) = clocking_outputs(timer, 1, 2);
+    assert_eq!(outputs, [false, false]);
+    let timer = timer.setting_gate(1, false).setting_gate(1, true);
+    let (_, outputs) = clocking_outputs(timer, 1, 5);
+    assert_eq!(outputs, [false, false, false, true, true]);
+}
+
+#[test]
+fn mode_2_divides_the_clock() {
+    let timer = programmed(I8253::new(), 2, 2, 4);
+    assert!(timer.output(2));
+
+    let (timer, outputs) = clocking_outputs(timer, 2, 9);
+    assert_eq!(
+        outputs,
+        [true, true, true, false, true, true, true, false, true]
+    );
+
+    // A new count waits for the current period to end
+    let timer = timer.writing_port(2, 2).writing_port(2, 0);
+    let (_, outputs) = clocking_outputs(timer, 2, 7);
+    assert_eq!(outputs, [true, true, false, true, false, true, false]);
+}
+
+#[test]
+fn mode_3_generates_square_waves() {
+    let timer = programmed(I8253::new(), 0, 3, 4);
+    let (timer, outputs) = clocking_outputs(timer, 0, 9);
+    assert_eq!(
+        outputs,
+        [true, true, false, false, true, true, false, false, true]
+    );
+
+    // Odd counts are high one clock longer than they are low
+    let timer = programmed(timer, 1, 3, 5);
+    let (timer, outputs) = clocking_outputs(timer, 1, 11);
+    assert_eq!(
+        outputs,
+        [true, true, true, false, false, true, true, true, false, false, true]
+    );
+
+    // Mode 7 is mode 3, and a low gate holds the output high
+    let timer = timer.writing_port(CONTROL, 0x1e).writing_port(0, 2);
+    let (timer, outputs) = clocking_outputs(timer, 0, 3);
+    assert_eq!(outputs, [true, false, true]);
+    let timer = timer.ticking(1).setting_gate(0, false);
+    assert!(timer.output(0));
+}
+
+#[test]
+fn modes_4_and_5_strobe_once() {
+    let timer = programmed(I8253::new(), 0, 4, 3);
+    let (timer, outputs) = clocking_outputs(timer, 0, 7);
+    assert_eq!(outputs, [true, true, true, false, true, true, true]);
+
+    // Mode 5 waits for the gate
+    let timer = programmed(timer, 1, 5, 2);
+    let (timer, outputs) = clocking_outputs(timer, 1, 3);
+    assert!(outputs.iter().all(|&output| output));
+    let timer = timer.setting_gate(1, false).setting_gate(1, true);
+    let (_, outputs) = clocking_outputs(timer, 1, 5);
+    assert_eq!(outputs, [true, true, false, true, true]);
+}
+
+#[test]
+fn counts_in_bcd() {
+    // Counter 0, LSB and MSB, mode 0, BCD
+    let timer = I8253::new()
+        .writing_port(CONTROL, 0x31)
+        .writing_port(0, 0x00)
+        .writing_port(0, 0x10)
+        .ticking(2);
+    let (timer, count) = latched_count(timer, 0);
+    assert_eq!(count, 0x0999);
+
+    let (_, count) = latched_count(timer.ticking(1000), 0);
+    assert_eq!(count, 0x9999);
+}
+
+#[test]
+fn byte_access_and_latching() {
+    // Counter 1 LSB only, then MSB only, mode 2
+    let timer = I8253::new()
+        .writing_port(CONTROL, 0x54)
+        .writing_port(1, 0x80)
+        .ticking(0x11);
+    let (timer, lsb) = timer.reading_port(1);
+    assert_eq!(lsb, 0x70);
+
+    let timer = timer
+        .writing_port(CONTROL, 0x64)
+        .writing_port(1, 0x01)
+        .ticking(1);
+    let (timer, msb) = timer.reading_port(1);
+    assert_eq!(msb, 0x01);
+
+    // A latched count survives counting until it is read
+    let timer = programmed(timer, 0, 2, 0x1234).ticking(1);
+    let timer = timer.writing_port(CONTROL, 0x00).ticking(0x30);
+    let (timer, low) = timer.reading_port(0);
+    let timer = timer.ticking(0x30);
+    let (timer, high) = timer.reading_port(0);
+    assert_eq!((high, low), (0x12, 0x34));
+    let (_, low) = timer.reading_port(0);
+    assert_eq!(low, 0xd4);
+
+    // Port 3 cannot be read
+    assert_eq!(I8253::new().reading_port(CONTROL).1, 0xff);
+}
+
+#[test]
+fn clock_period_divides_cpu_cycles() {
+    let timer = programmed(I8253::new().setting_clock_period(0, 10), 0, 0, 2);
+    let timer = timer.ticking(29);
+    assert!(!timer.output(0));
+
+    assert!(timer.ticking(1).output(0));
+}
+
+#[test]
+fn rising_outputs_raise_interrupts() {
+    // Counter 0 in mode 2 with a count of 100 clocks, running EI instructions of 4 cycles each
+    let mut timer = programmed(I8253::new(), 0, 2, 100).wiring_interrupt(0, 7);
+    let mut state = State8080::new();
+    state.sp = 0x1000;
+    for byte in state.memory[..0x100].iter_mut() {
+        *byte = 0xfb;
+    }
+
+    let mut interrupted_at = None;
+    for step in 0..40 {
+        state = state.evaluating_next(Some(&mut timer));
+        let (next_timer, next_state) = timer.ticking_after(state);
+        timer = next_timer;
+        state = next_state;
+        if state.pc == 0x38 {
+            interrupted_at = Some(step);
+            break;
+        }
+    }
+
+    // The output goes back high on the 101st clock, during the 26th instruction
+    assert_eq!(interrupted_at, Some(25));
+    assert_eq!(state.memory[0x0ffe], 26);
+    assert!(!state.interrupt_enabled);
+
+    // The next rising edge waits for interrupts to be enabled
+    let (timer, state) = timer.ticking(100).ticking_after(state);
+    assert_eq!(state.pc, 0x38);
+    let mut state = state;
+    state.interrupt_enabled = true;
+    let (_, state) = timer.ticking_after(state);
+    assert_eq!(state.memory[0x0ffc], 0x38);
+}
+
+#[test]
+#[should_panic(expected = "no counter 3")]
+fn wiring_a_missing_counter_panics() {
+    I8253::new().wiring_interrupt(3, 1);
+}
@@ -0,0 +1,165 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emu_8080::devices::i8255::{Pins, Port, PortHost, I8255};
+use emu_8080::emulator::State8080;
+
+const CONTROL: u8 = 0x03;
+
+fn attached(ppi: I8255, port: Port, pins: &Rc<RefCell<Pins>>) -> I8255 {
+    ppi.attaching(port, Box::new(pins.clone()))
+}
+
+#[test]
+fn ports_are_inputs_after_reset() {
+    let pins = Rc::new(RefCell::new(Pins::with_input(0x5a)));
+    let ppi = attached(I8255::new(), Port::B, &pins);
+
+    let (ppi, value) = ppi.reading_port(1);
+    assert_eq!(value, 0x5a);
+    // Nothing is attached to port A
+    let (ppi, value) = ppi.reading_port(0);
+    assert_eq!(value, 0xff);
+
+    // Writing an input port drives nothing
+    let ppi = ppi.writing_port(1, 0x12);
+    assert!(pins.borrow().outputs.is_empty());
+    assert_eq!(ppi.output(Port::B), 0);
+}
+
+#[test]
+fn mode_0_outputs_and_split_port_c() {
+    let a = Rc::new(RefCell::new(Pins::default()));
+    let c = Rc::new(RefCell::new(Pins::with_input(0xa5)));
+    // A output, B input, C upper input and lower output
+    let ppi =
+        attached(attached(I8255::new(), Port::A, &a), Port::C, &c).writing_port(CONTROL, 0x8a);
+    assert_eq!(a.borrow().outputs, [0x00]);
+
+    let ppi = ppi.writing_port(0, 0x42);
+    assert_eq!(a.borrow().last_output(), Some(0x42));
+    let (ppi, value) = ppi.reading_port(0);
+    assert_eq!(value, 0x42);
+
+    let ppi = ppi.writing_port(2, 0xff);
+    assert_eq!(c.borrow().outputs, [0x0f]);
+    let (_, value) = ppi.reading_port(2);
+    assert_eq!(value, 0xaf);
+}
+
+#[test]
+fn port_c_bit_set_and_reset() {
+    let c = Rc::new(RefCell::new(Pins::default()));
+    let ppi = attached(I8255::new(), Port::C, &c).writing_port(CONTROL, 0x80);
+
+    let ppi = ppi
+        .writing_port(CONTROL, 0x0f)
+        .writing_port(CONTROL, 0x01)
+        .writing_port(CONTROL, 0x0e);
+    assert_eq!(c.borrow().outputs, [0x80, 0x81, 0x01]);
+    assert_eq!(ppi.output(Port::C), 0x01);
+
+    // Setting the mode clears the outputs
+    let ppi = ppi.writing_port(CONTROL, 0x80);
+    assert_eq!(ppi.output(Port::C), 0);
+}
+
+#[test]
+fn mode_1_strobed_input() {
+    // A input in mode 1, the rest outputs in mode 0
+    let ppi = I8255::new().writing_port(CONTROL, 0xb0);
+    let (ppi, status) = ppi.reading_port(2);
+    assert_eq!(status, 0x00);
+
+    // IBF goes high with STB, INTR needs INTE A from PC4
+    let ppi = ppi.strobing(Port::A, 0x33);
+    assert_eq!(ppi.output(Port::C), 0x20);
+    assert!(!ppi.interrupt_requested(Port::A));
+    let ppi = ppi.writing_port(CONTROL, 0x09);
+    assert!(ppi.interrupt_requested(Port::A));
+    let (ppi, status) = ppi.reading_port(2);
+    assert_eq!(status, 0x38);
+
+    // Reading the latch clears IBF and INTR
+    let (ppi, value) = ppi.reading_port(0);
+    assert_eq!(value, 0x33);
+    assert!(!ppi.interrupt_requested(Port::A));
+    assert_eq!(ppi.output(Port::C), 0x00);
+}
+
+#[test]
+fn mode_1_acknowledged_output() {
+    let b = Rc::new(RefCell::new(Pins::default()));
+    // B output in mode 1 with INTE B
+    let ppi = attached(I8255::new(), Port::B, &b)
+        .writing_port(CONTROL, 0x84)
+        .writing_port(CONTROL, 0x05);
+    assert_eq!(ppi.output(Port::C) & 0x03, 0x02);
+    assert!(!ppi.interrupt_requested(Port::B));
+
+    // OBF goes low with the write and back high with ACK, which interrupts
+    let ppi = ppi.writing_port(1, 0x99);
+    assert_eq!(b.borrow().last_output(), Some(0x99));
+    assert_eq!(ppi.output(Port::C) & 0x03, 0x00);
+    let ppi = ppi.acknowledging(Port::B);
+    assert_eq!(ppi.output(Port::C) & 0x03, 0x03);
+    assert!(ppi.interrupt_requested(Port::B));
+
+    let ppi = ppi.writing_port(1, 0x98);
+    assert!(!ppi.interrupt_requested(Port::B));
+}
+
+#[test]
+fn mode_2_is_bidirectional() {
+    let a = Rc::new(RefCell::new(Pins::default()));
+    let ppi = attached(I8255::new(), Port::A, &a).writing_port(CONTROL, 0xc0);
+    assert!(a.borrow().outputs.is_empty());
+
+    // Output waits on the bus until ACK
+    let ppi = ppi.writing_port(0, 0x11);
+    assert!(a.borrow().outputs.is_empty());
+    let (ppi, status) = ppi.reading_port(2);
+    assert_eq!(status, 0x00);
+    let ppi = ppi.acknowledging(Port::A);
+    assert_eq!(a.borrow().outputs, [0x11]);
+
+    let ppi = ppi.strobing(Port::A, 0x22);
+    let (ppi, status) = ppi.reading_port(2);
+    assert_eq!(status, 0xa0);
+    let (_, value) = ppi.reading_port(0);
+    assert_eq!(value, 0x22);
+}
+
+struct Keyboard {
+    keys: Vec<u8>,
+}
+
+impl PortHost for Keyboard {
+    fn reading(&mut self) -> u8 {
+        self.keys.pop().unwrap_or(0)
+    }
+}
+
+#[test]
+fn plugs_into_the_cpu() {
+    // MVI A,0x90; OUT 0x03; IN 0x00; MOV B,A; IN 0x00; OUT 0x01
+    let program = [
+        0x3e, 0x90, 0xd3, 0x03, 0xdb, 0x00, 0x47, 0xdb, 0x00, 0xd3, 0x01,
+    ];
+    let mut state = State8080::new().loading_buffer_into_memory_at(program.to_vec(), 0);
+    let leds = Rc::new(RefCell::new(Pins::default()));
+    let mut ppi = I8255::new()
+        .attaching(
+            Port::A,
+            Box::new(Keyboard {
+                keys: vec![b'b', b'a'],
+            }),
+        )
+        .attaching(Port::B, Box::new(leds.clone()));
+    for _ in 0..6 {
+        state = state.evaluating_next(Some(&mut ppi));
+    }
+
+    assert_eq!((state.b, state.a), (b'a', b'b'));
+    assert_eq!(leds.borrow().outputs, [0x00, b'b']);
+}
@@ -0,0 +1,249 @@
+use emu_8080::devices::i8259::I8259;
+use emu_8080::emulator::{InterruptController, State8080};
+
+const COMMAND: u8 = 0x20;
+const DATA: u8 = 0x21;
+
+// Single, edge triggered, with CALLs to 0x4000 + 4 * line
+fn initialized() -> I8259 {
+    I8259::new()
+        .writing_port(COMMAND, 0x16)
+        .writing_port(DATA, 0x40)
+}
+
+fn requesting(controller: I8259, lines: &[u8]) -> I8259 {
+    lines.iter().fold(controller, |controller, &line| {
+        controller.setting_request(line, true)
+    })
+}
+
+fn acknowledged_line(controller: I8259) -> (I8259, u8) {
+    let (controller, instruction) = controller.acknowledging();
+    assert_eq!(instruction[0], 0xcd);
+    assert_eq!(instruction[2], 0x40);
+
+    (controller, instruction[1] >> 2)
+}
+
+// Reads a register without polling
+fn register(controller: &I8259, port: u8) -> u8 {
+    controller.clone().reading_port(port).1
+}
+
+#[test]
+fn initialization_sets_the_call_vectors() {
+    // Requests are ignored until the controller is initialized
+    let controller = I8259::new().setting_request(2, true);
+    assert!(!controller.interrupt_requested());
+
+    let controller = requesting(initialized(), &[5]);
+    let (_, instruction) = controller.acknowledging();
+    assert_eq!(instruction, [0xcd, 0x14, 0x40]);
+
+    // Interval of 8 with A7-A6 from ICW1, cascaded with ICW3 and an ICW4
+    let controller = I8259::new()
+        .writing_port(COMMAND, 0xd1)
+        .writing_port(DATA, 0x12)
+        .writing_port(DATA, 0x00)
+        .writing_port(DATA, 0x00)
+        .setting_request(5, true);
+    let (_, instruction) = controller.acknowledging();
+    assert_eq!(instruction, [0xcd, 0xe8, 0x12]);
+}
+
+#[test]
+fn in_service_levels_block_lower_priorities() {
+    let controller = requesting(initialized(), &[5, 3]);
+    let (controller, line) = acknowledged_line(controller);
+    assert_eq!(line, 3);
+    assert_eq!(controller.in_service_register(), 0x08);
+
+    // IR5 waits for the end of IR3, IR1 nests
+    assert!(!controller.interrupt_requested());
+    let controller = controller.setting_request(1, true);
+    let (controller, line) = acknowledged_line(controller);
+    assert_eq!(line, 1);
+    assert_eq!(controller.in_service_register(), 0x0a);
+
+    // Non-specific EOIs end the highest level in service first
+    let controller = controller.writing_port(COMMAND, 0x20);
+    assert_eq!(controller.in_service_register(), 0x08);
+    assert!(!controller.interrupt_requested());
+    let controller = controller.writing_port(COMMAND, 0x20);
+    let (controller, line) = acknowledged_line(controller);
+    assert_eq!(line, 5);
+
+    // Specific EOI
+    let controller = controller.writing_port(COMMAND, 0x65);
+    assert_eq!(controller.in_service_register(), 0);
+}
+
+#[test]
+fn masked_lines_wait() {
+    let controller = requesting(initialized().writing_port(DATA, 0x04), &[2]);
+    assert!(!controller.interrupt_requested());
+    assert_eq!(register(&controller, DATA), 0x04);
+
+    let controller = controller.writing_port(DATA, 0x00);
+    assert!(controller.interrupt_requested());
+}
+
+#[test]
+fn edge_and_level_triggering() {
+    // A held edge triggered line only requests once
+    let controller = requesting(initialized(), &[4]);
+    let (controller, _) = acknowledged_line(controller);
+    let controller = controller
+        .writing_port(COMMAND, 0x20)
+        .setting_request(4, true);
+    assert!(!controller.interrupt_requested());
+
+    // A request that goes away is lost, and acknowledging anyway gets IR7 without setting it in
+    // service
+    let controller = controller
+        .setting_request(4, false)
+        .setting_request(4, true)
+        .setting_request(4, false);
+    assert!(!controller.interrupt_requested());
+    let (controller, line) = acknowledged_line(controller);
+    assert_eq!(line, 7);
+    assert_eq!(controller.in_service_register(), 0);
+
+    // A level triggered line requests again after the EOI while it is held
+    let controller = I8259::new()
+        .writing_port(COMMAND, 0x1e)
+        .writing_port(DATA, 0x40)
+        .setting_request(4, true);
+    let (controller, _) = acknowledged_line(controller);
+    assert!(!controller.interrupt_requested());
+    let controller = controller.writing_port(COMMAND, 0x20);
+    assert!(controller.interrupt_requested());
+}
+
+#[test]
+fn rotation_and_priority_setting() {
+    // Rotate on non-specific EOI: the serviced line becomes the lowest priority
+    let controller = requesting(initialized(), &[2]);
+    let (controller, _) = acknowledged_line(controller);
+    let controller = requesting(controller.writing_port(COMMAND, 0xa0), &[2, 6]);
+    let (controller, line) = acknowledged_line(controller);
+    assert_eq!(line, 6);
+
+    // Set priority makes IR4 the lowest, so IR5 comes first
+    let controller = controller
+        .writing_port(COMMAND, 0x20)
+        .writing_port(COMMAND, 0xc4);
+    let controller = requesting(controller, &[5]);
+    let (_, line) = acknowledged_line(controller);
+    assert_eq!(line, 5);
+}
+
+#[test]
+fn automatic_eoi() {
+    let controller = I8259::new()
+        .writing_port(COMMAND, 0x17)
+        .writing_port(DATA, 0x40)
+        .writing_port(DATA, 0x02);
+    let controller = requesting(controller, &[3]);
+    let (controller, _) = acknowledged_line(controller);
+    assert_eq!(controller.in_service_register(), 0);
+
+    // With rotation in automatic EOI mode, IR3 goes to the lowest priority
+    let controller = controller
+        .setting_request(3, false)
+        .writing_port(COMMAND, 0x80);
+    let controller = requesting(controller, &[3, 4]);
+    let (controller, line) = acknowledged_line(controller);
+    assert_eq!(line, 3);
+    let controller = requesting(controller.setting_request(3, false), &[3]);
+    let (_, line) = acknowledged_line(controller);
+    assert_eq!(line, 4);
+}
+
+#[test]
+fn reading_registers_and_polling() {
+    let controller = requesting(initialized(), &[1, 6]);
+    assert_eq!(register(&controller, COMMAND), 0x42);
+
+    let (controller, _) = acknowledged_line(controller);
+    let controller = controller.writing_port(COMMAND, 0x0b);
+    assert_eq!(register(&controller, COMMAND), 0x02);
+    let controller = controller.writing_port(COMMAND, 0x0a);
+    assert_eq!(register(&controller, COMMAND), 0x40);
+
+    // Polling acknowledges the request without the CPU
+    let controller = controller
+        .writing_port(COMMAND, 0x20)
+        .writing_port(COMMAND, 0x0c);
+    let (controller, word) = controller.reading_port(COMMAND);
+    assert_eq!(word, 0x86);
+    assert_eq!(controller.in_service_register(), 0x40);
+    let (_, word) = controller.writing_port(COMMAND, 0x0c).reading_port(COMMAND);
+    assert_eq!(word, 0x00);
+}
+
+#[test]
+fn special_mask_mode_lets_lower_levels_in() {
+    let controller = requesting(initialized(), &[1]);
+    let (controller, _) = acknowledged_line(controller);
+
+    // Mask the level in service and enter special mask mode
+    let controller = controller
+        .writing_port(DATA, 0x02)
+        .writing_port(COMMAND, 0x68);
+    let controller = requesting(controller, &[6]);
+    let (controller, line) = acknowledged_line(controller);
+    assert_eq!(line, 6);
+
+    // Back to the normal mode, IR1 blocks IR7 again
+    let controller = requesting(controller.writing_port(COMMAND, 0x48), &[7]);
+    assert!(!controller.interrupt_requested());
+}
+
+#[test]
+fn the_cpu_takes_the_call_during_acknowledge() {
+    let mut controller = requesting(initialized(), &[2]);
+    let mut state = State8080::new();
+    state.pc = 0x1234;
+    state.sp = 0x2000;
+
+    // Nothing happens while interrupts are disabled
+    state = state.acknowledging_interrupt(&mut controller);
+    assert_eq!(state.pc, 0x1234);
+
+    state.interrupt_enabled = true;
+    state = state.acknowledging_interrupt(&mut controller);
+    assert_eq!(state.pc, 0x4008);
+    assert_eq!(state.sp, 0x1ffe);
+    assert_eq!(&state.memory[0x1ffe..0x2000], &[0x34, 0x12]);
+    assert_eq!(state.last_cycles(), 17);
+    assert_eq!(state.last_memory_writes(), &[0x1fff, 0x1ffe]);
+    assert!(!state.interrupt_enabled);
+    assert_eq!(controller.in_service_register(), 0x04);
+}
+
+struct Rst(u8);
+
+impl InterruptController for Rst {
+    fn interrupt_requested(&self) -> bool {
+        true
+    }
+
+    fn acknowledging(&mut self) -> [u8; 3] {
+        [0xc7 | self.0 << 3, 0, 0]
+    }
+}
+
+#[test]
+fn controllers_can_also_put_rst_on_the_bus() {
+    let mut state = State8080::new();
+    state.pc = 0x1234;
+    state.sp = 0x2000;
+    state.interrupt_enabled = true;
+
+    let state = state.acknowledging_interrupt(&mut Rst(5));
+
+    assert_eq!(state.pc, 0x28);
+    assert_eq!(state.last_cycles(), 11);
+    assert_eq!(&state.memory[0x1ffe..0x2000], &[0x34, 0x12]);
+}
@@ -0,0 +1,981 @@
+use std::convert::TryFrom;
+
+use emu_8080::disassembler::{self, Instruction, DECODE};
+use emu_8080::emulator::{ConditionCodes, CpuVariant, DummyIOHandler, IOHandler, State8080};
+
+const S: u8 = 0x80;
+const Z: u8 = 0x40;
+const AC: u8 = 0x10;
+const P: u8 = 0x04;
+const PAD: u8 = 0x02;
+const CY: u8 = 0x01;
+
+// Register operand encoding used by the opcodes
+const B: u8 = 0;
+const M: u8 = 6;
+const A: u8 = 7;
+
+const ORIGIN: u16 = 0x0100;
+const MEMORY_OPERAND: u16 = 0x4000;
+const STACK: u16 = 0x8000;
+
+// Size and cycle count of every opcode as listed in the Intel 8080 programmer's manual. Conditional
+// calls and returns use the cycle count of the taken branch. Undefined opcodes are `None`.
+#[rustfmt::skip]
+const DOCUMENTED: [Option<(u8, u8)>; 256] = [
+    // 0x00
+    Some((1, 4)), Some((3, 10)), Some((1, 7)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((2, 7)), Some((1, 4)),
+    Some((1, 4)), Some((1, 10)), Some((1, 7)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((2, 7)), Some((1, 4)),
+    // 0x10
+    Some((1, 4)), Some((3, 10)), Some((1, 7)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((2, 7)), Some((1, 4)),
+    Some((1, 4)), Some((1, 10)), Some((1, 7)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((2, 7)), Some((1, 4)),
+    // 0x20
+    Some((1, 4)), Some((3, 10)), Some((3, 16)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((2, 7)), Some((1, 4)),
+    Some((1, 4)), Some((1, 10)), Some((3, 16)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((2, 7)), Some((1, 4)),
+    // 0x30
+    Some((1, 4)), Some((3, 10)), Some((3, 13)), Some((1, 5)), Some((1, 10)), Some((1, 10)), Some((2, 10)), Some((1, 4)),
+    Some((1, 4)), Some((1, 10)), Some((3, 13)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((2, 7)), Some((1, 4)),
+    // 0x40
+    Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 7)), Some((1, 5)),
+    Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 7)), Some((1, 5)),
+    // 0x50
+    Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 7)), Some((1, 5)),
+    Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 7)), Some((1, 5)),
+    // 0x60
+    Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 7)), Some((1, 5)),
+    Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 7)), Some((1, 5)),
+    // 0x70
+    Some((1, 7)), Some((1, 7)), Some((1, 7)), Some((1, 7)), Some((1, 7)), Some((1, 7)), Some((1, 7)), Some((1, 7)),
+    Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 5)), Some((1, 7)), Some((1, 5)),
+    // 0x80
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    // 0x90
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    // 0xA0
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    // 0xB0
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 4)), Some((1, 7)), Some((1, 4)),
+    // 0xC0
+    Some((1, 11)), Some((1, 10)), Some((3, 10)), Some((3, 10)), Some((3, 17)), Some((1, 11)), Some((2, 7)), Some((1, 11)),
+    Some((1, 11)), Some((1, 10)), Some((3, 10)), None, Some((3, 17)), Some((3, 17)), Some((2, 7)), Some((1, 11)),
+    // 0xD0
+    Some((1, 11)), Some((1, 10)), Some((3, 10)), Some((2, 10)), Some((3, 17)), Some((1, 11)), Some((2, 7)), Some((1, 11)),
+    Some((1, 11)), None, Some((3, 10)), Some((2, 10)), Some((3, 17)), None, Some((2, 7)), Some((1, 11)),
+    // 0xE0
+    Some((1, 11)), Some((1, 10)), Some((3, 10)), Some((1, 18)), Some((3, 17)), Some((1, 11)), Some((2, 7)), Some((1, 11)),
+    Some((1, 11)), Some((1, 5)), Some((3, 10)), Some((1, 4)), Some((3, 17)), None, Some((2, 7)), Some((1, 11)),
+    // 0xF0
+    Some((1, 11)), Some((1, 10)), Some((3, 10)), Some((1, 4)), Some((3, 17)), Some((1, 11)), Some((2, 7)), Some((1, 11)),
+    Some((1, 11)), Some((1, 5)), Some((3, 10)), Some((1, 4)), Some((3, 17)), None, Some((2, 7)), Some((1, 11)),
+];
+
+fn new_state() -> State8080 {
+    let mut state = State8080::new();
+    state.sp = STACK;
+    state.h = (MEMORY_OPERAND >> 8) as u8;
+    state.l = MEMORY_OPERAND as u8;
+
+    state
+}
+
+fn executing(state: State8080, bytes: &[u8]) -> State8080 {
+    let mut state = state;
+    state.pc = ORIGIN;
+    state.memory[ORIGIN as usize..ORIGIN as usize + bytes.len()].copy_from_slice(bytes);
+
+    state.evaluating_next::<DummyIOHandler>(None)
+}
+
+fn register(state: &State8080, index: u8) -> u8 {
+    match index {
+        0 => state.b,
+        1 => state.c,
+        2 => state.d,
+        3 => state.e,
+        4 => state.h,
+        5 => state.l,
+        6 => state.memory[u16::from(state.hl()) as usize],
+        _ => state.a,
+    }
+}
+
+fn setting_register(state: State8080, index: u8, value: u8) -> State8080 {
+    let mut state = state;
+    match index {
+        0 => state.b = value,
+        1 => state.c = value,
+        2 => state.d = value,
+        3 => state.e = value,
+        4 => state.h = value,
+        5 => state.l = value,
+        6 => {
+            let address = u16::from(state.hl());
+            state.memory[address as usize] = value;
+        }
+        _ => state.a = value,
+    }
+
+    state
+}
+
+fn setting_flags(state: State8080, bits: u8) -> State8080 {
+    let mut state = state;
+    state.cc = ConditionCodes::from_bits_truncate(bits | PAD);
+
+    state
+}
+
+fn flag(bit: u8, set: bool) -> u8 {
+    if set {
+        bit
+    } else {
+        0
+    }
+}
+
+fn zsp(value: u8) -> u8 {
+    flag(S, value & 0x80 != 0) | flag(Z, value == 0) | flag(P, value.count_ones() & 1 == 0) | PAD
+}
+
+// Result of ADD, ADC, SUB, SBB, ANA, XRA, ORA and CMP (in opcode order) as documented for the 8080:
+// returns the new accumulator and the complete flags byte.
+fn expected_alu(group: u8, a: u8, b: u8, cy: bool) -> (u8, u8) {
+    match group {
+        0 | 1 => {
+            let carry = (group == 1 && cy) as u8;
+            let sum = a as u16 + b as u16 + carry as u16;
+            let res = sum as u8;
+            let ac = (a & 0x0f) + (b & 0x0f) + carry > 0x0f;
+
+            (res, zsp(res) | flag(AC, ac) | flag(CY, sum > 0xff))
+        }
+        2 | 3 | 7 => {
+            let borrow = (group == 3 && cy) as u8;
+            let difference = a as i16 - b as i16 - borrow as i16;
+            let res = difference as u8;
+            // The 8080 subtracts by adding the two's complement, AC is the carry out of bit 3
+            let ac = (a & 0x0f) >= (b & 0x0f) + borrow;
+            let flags = zsp(res) | flag(AC, ac) | flag(CY, difference < 0);
+
+            (if group == 7 { a } else { res }, flags)
+        }
+        4 => {
+            let res = a & b;
+
+            (res, zsp(res) | flag(AC, (a | b) & 0x08 != 0))
+        }
+        5 => {
+            let res = a ^ b;
+
+            (res, zsp(res))
+        }
+        _ => {
+            let res = a | b;
+
+            (res, zsp(res))
+        }
+    }
+}
+
+// DAA as described step by step in the 8080 manual, carried out on a wider value so a carry from
+// the low correction is seen by the high nibble check
+fn expected_daa(a: u8, ac: bool, cy: bool) -> (u8, u8) {
+    let mut value = a as u16;
+    let mut half_carry = false;
+    if a & 0x0f > 9 || ac {
+        half_carry = (a & 0x0f) + 6 > 0x0f;
+        value += 0x06;
+    }
+
+    let mut carry = cy;
+    if value >> 4 > 9 || cy {
+        value += 0x60;
+        carry = true;
+    }
+    let res = value as u8;
+
+    (res, zsp(res) | flag(AC, half_carry) | flag(CY, carry))
+}
+
+// Flags left behind by an earlier instruction, derived from the operands so every combination
+// gets exercised
+fn stale_flags(a: u8, b: u8, cy: bool) -> u8 {
+    ((a ^ b.rotate_left(3)) & (S | Z | AC | P)) | flag(CY, cy)
+}
+
+// Small deterministic generator for sampled operands
+struct Lcg(u32);
+
+impl Lcg {
+    fn next_byte(&mut self) -> u8 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (self.0 >> 16) as u8
+    }
+}
+
+#[test]
+fn every_opcode_has_documented_size_and_cycles() {
+    for opcode in 0..=255u8 {
+        let documented = DOCUMENTED[opcode as usize];
+        let decoded = Instruction::try_from(opcode).ok();
+        let actual = decoded.map(|i| (i.size(), i.cycles()));
+
+        assert_eq!(actual, documented, "opcode {:#04x}", opcode);
+    }
+}
+
+#[test]
+fn decode_table_matches_the_instructions() {
+    for opcode in 0..=255u8 {
+        let decoded = DECODE[opcode as usize];
+        let documented = decoded.map(|decoded| (decoded.size, decoded.cycles));
+
+        assert_eq!(
+            documented, DOCUMENTED[opcode as usize],
+            "opcode {:#04x}",
+            opcode
+        );
+        assert_eq!(
+            decoded.map(|decoded| decoded.instruction),
+            Instruction::try_from(opcode).ok()
+        );
+    }
+}
+
+#[test]
+fn instructions_only_write_the_flags_they_are_decoded_with() {
+    let mut random = Lcg(3);
+    for opcode in 0..=255u8 {
+        let decoded = match DECODE[opcode as usize] {
+            Some(decoded) => decoded,
+            None => continue,
+        };
+
+        for _ in 0..64 {
+            let mut before = setting_flags(new_state(), random.next_byte());
+            before.a = random.next_byte();
+            before.b = random.next_byte();
+            before.memory[MEMORY_OPERAND as usize] = random.next_byte();
+            before.memory[STACK as usize] = random.next_byte();
+            let operands = [opcode, random.next_byte(), random.next_byte()];
+            let after = executing(before.clone(), &operands);
+
+            let changed = after.cc.bits() ^ before.cc.bits();
+            assert_eq!(changed & !decoded.flags.bits(), 0, "opcode {:#04x}", opcode);
+        }
+    }
+}
+
+#[test]
+fn non_branching_instructions_advance_pc_by_size_and_report_cycles() {
+    let branching = [
+        0x76, // HLT
+        0xc0, 0xc2, 0xc3, 0xc4, 0xc7, 0xc8, 0xc9, 0xca, 0xcc, 0xcd, 0xcf, // C row
+        0xd0, 0xd2, 0xd4, 0xd7, 0xd8, 0xda, 0xdc, 0xdf, // D row
+        0xe0, 0xe2, 0xe4, 0xe7, 0xe8, 0xe9, 0xea, 0xec, 0xef, // E row
+        0xf0, 0xf2, 0xf4, 0xf7, 0xf8, 0xfa, 0xfc, 0xff, // F row
+    ];
+
+    for opcode in 0..=255u8 {
+        let instruction = match Instruction::try_from(opcode) {
+            Ok(instruction) => instruction,
+            Err(_) => continue,
+        };
+        if branching.contains(&opcode) {
+            continue;
+        }
+
+        let state = executing(new_state(), &[opcode, 0x12, 0x34]);
+
+        assert_eq!(
+            state.pc,
+            ORIGIN + instruction.size() as u16,
+            "opcode {:#04x}",
+            opcode
+        );
+        assert_eq!(
+            state.last_cycles(),
+            instruction.cycles(),
+            "opcode {:#04x}",
+            opcode
+        );
+    }
+}
+
+#[test]
+fn undefined_opcodes_do_not_decode() {
+    for opcode in [0xcb, 0xd9, 0xdd, 0xed, 0xfd].iter() {
+        assert!(Instruction::try_from(*opcode).is_err());
+    }
+}
+
+#[test]
+fn disassembling_shows_operands() {
+    // LXI H,0x1234; MVI A,0x42; NOP, or RIM on the 8085; undefined; JMP
+    let memory = [0x21, 0x34, 0x12, 0x3e, 0x42, 0x20, 0xcb, 0xc3];
+    let disassembling =
+        |address| disassembler::disassembling(&memory, address, CpuVariant::Intel8080);
+
+    assert_eq!(disassembling(0), ("LxiH    $1234".to_string(), 3));
+    assert_eq!(disassembling(3), ("MviA    #$42".to_string(), 2));
+    assert_eq!(disassembling(5), ("Nop4".to_string(), 1));
+    assert_eq!(disassembling(6), ("Db    #$cb".to_string(), 1));
+    // The address of the JMP is past the end
+    assert_eq!(disassembling(7), ("Jmp".to_string(), 3));
+    assert_eq!(
+        disassembler::disassembling(&memory, 5, CpuVariant::Intel8085),
+        ("Rim".to_string(), 1)
+    );
+}
+
+#[test]
+fn alternate_nops_do_nothing() {
+    for opcode in [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38].iter() {
+        let before = setting_flags(new_state(), S | AC | CY);
+        let after = executing(before.clone(), &[*opcode]);
+
+        assert_eq!(after.pc, ORIGIN + 1);
+        assert_eq!(after.cc, before.cc);
+        assert_eq!(
+            (after.a, after.b, after.sp),
+            (before.a, before.b, before.sp)
+        );
+    }
+}
+
+#[test]
+fn alu_register_ops_match_documented_flags_exhaustively() {
+    let mut state = new_state();
+
+    for group in 0..8u8 {
+        let opcode = 0x80 | (group << 3) | B;
+
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                for &cy in [false, true].iter() {
+                    state = setting_flags(state, stale_flags(a, b, cy));
+                    state.a = a;
+                    state.b = b;
+                    state = executing(state, &[opcode]);
+
+                    let (res, flags) = expected_alu(group, a, b, cy);
+                    assert_eq!(
+                        (state.a, state.cc.bits()),
+                        (res, flags),
+                        "opcode {:#04x} a={:#04x} b={:#04x} cy={}",
+                        opcode,
+                        a,
+                        b,
+                        cy
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn alu_immediate_ops_match_documented_flags_exhaustively() {
+    let mut state = new_state();
+
+    for group in 0..8u8 {
+        let opcode = 0xc6 | (group << 3);
+
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                for &cy in [false, true].iter() {
+                    state = setting_flags(state, stale_flags(a, b, cy));
+                    state.a = a;
+                    state = executing(state, &[opcode, b]);
+
+                    let (res, flags) = expected_alu(group, a, b, cy);
+                    assert_eq!(
+                        (state.a, state.cc.bits()),
+                        (res, flags),
+                        "opcode {:#04x} a={:#04x} b={:#04x} cy={}",
+                        opcode,
+                        a,
+                        b,
+                        cy
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn alu_ops_read_every_source_operand() {
+    let mut rng = Lcg(0x8080);
+
+    for opcode in 0x80..=0xbfu8 {
+        let group = (opcode >> 3) & 7;
+        let source = opcode & 7;
+
+        for _ in 0..256 {
+            let a = rng.next_byte();
+            let operand = rng.next_byte();
+            let cy = rng.next_byte() & 1 == 1;
+
+            let mut state = setting_flags(new_state(), flag(CY, cy));
+            state = setting_register(state, source, operand);
+            state.a = a;
+            let b = register(&state, source);
+            let state = executing(state, &[opcode]);
+
+            let (res, flags) = expected_alu(group, a, b, cy);
+            assert_eq!(
+                (state.a, state.cc.bits()),
+                (res, flags),
+                "opcode {:#04x} a={:#04x} b={:#04x} cy={}",
+                opcode,
+                a,
+                b,
+                cy
+            );
+        }
+    }
+}
+
+#[test]
+fn inr_and_dcr_match_documented_flags_exhaustively() {
+    for index in 0..8u8 {
+        let inr = 0x04 | (index << 3);
+        let dcr = 0x05 | (index << 3);
+
+        for value in 0..=255u8 {
+            for &cy in [false, true].iter() {
+                let before = setting_flags(new_state(), stale_flags(value, index, cy));
+                let before = setting_register(before, index, value);
+
+                let after = executing(before.clone(), &[inr]);
+                let res = value.wrapping_add(1);
+                let flags = zsp(res) | flag(AC, value & 0x0f == 0x0f) | flag(CY, cy);
+                assert_eq!(
+                    (register(&after, index), after.cc.bits()),
+                    (res, flags),
+                    "INR {:#04x} value={:#04x} cy={}",
+                    inr,
+                    value,
+                    cy
+                );
+
+                let after = executing(before, &[dcr]);
+                let res = value.wrapping_sub(1);
+                let flags = zsp(res) | flag(AC, value & 0x0f != 0) | flag(CY, cy);
+                assert_eq!(
+                    (register(&after, index), after.cc.bits()),
+                    (res, flags),
+                    "DCR {:#04x} value={:#04x} cy={}",
+                    dcr,
+                    value,
+                    cy
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn rotates_only_affect_carry() {
+    for a in 0..=255u8 {
+        for &cy in [false, true].iter() {
+            let stale = stale_flags(a, 0x5a, cy);
+            let before = setting_flags(new_state(), stale).setting_a(a);
+            let kept = stale & !CY | PAD;
+
+            let expected = [
+                // RLC
+                (0x07, a.rotate_left(1), a & 0x80 != 0),
+                // RRC
+                (0x0f, a.rotate_right(1), a & 0x01 != 0),
+                // RAL
+                (0x17, (a << 1) | cy as u8, a & 0x80 != 0),
+                // RAR
+                (0x1f, (a >> 1) | ((cy as u8) << 7), a & 0x01 != 0),
+            ];
+
+            for &(opcode, res, carry) in expected.iter() {
+                let after = executing(before.clone(), &[opcode]);
+
+                assert_eq!(
+                    (after.a, after.cc.bits()),
+                    (res, kept | flag(CY, carry)),
+                    "opcode {:#04x} a={:#04x} cy={}",
+                    opcode,
+                    a,
+                    cy
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn cma_cmc_and_stc_only_touch_their_target() {
+    for a in 0..=255u8 {
+        let stale = stale_flags(a, 0xa5, a & 1 == 1);
+        let before = setting_flags(new_state(), stale).setting_a(a);
+
+        let after = executing(before.clone(), &[0x2f]);
+        assert_eq!((after.a, after.cc.bits()), (!a, stale | PAD));
+
+        let after = executing(before.clone(), &[0x3f]);
+        assert_eq!((after.a, after.cc.bits()), (a, (stale ^ CY) | PAD));
+
+        let after = executing(before, &[0x37]);
+        assert_eq!((after.a, after.cc.bits()), (a, stale | CY | PAD));
+    }
+}
+
+#[test]
+fn daa_matches_intel_manual_example() {
+    // From the Intel 8080 programmer's manual: A = 0x9B becomes 0x01 with both carries set
+    let state = executing(new_state().setting_a(0x9b), &[0x27]);
+
+    assert_eq!(state.a, 0x01);
+    assert!(state.cy());
+    assert!(state.ac());
+
+    // 0x19 + 0x28 = 0x41, adjusted to the BCD result 0x47
+    let state = setting_register(new_state().setting_a(0x19), B, 0x28);
+    let state = executing(state, &[0x80]);
+    let state = executing(state, &[0x27]);
+
+    assert_eq!(state.a, 0x47);
+    assert!(!state.cy());
+}
+
+#[test]
+fn daa_matches_documented_behaviour_exhaustively() {
+    for a in 0..=0xff {
+        for &carries in &[0, AC, CY, AC | CY] {
+            let state = setting_flags(new_state().setting_a(a), carries);
+            let after = executing(state, &[0x27]);
+            let (res, flags) = expected_daa(a, carries & AC != 0, carries & CY != 0);
+
+            assert_eq!(
+                (after.a, after.cc.bits()),
+                (res, flags),
+                "DAA with A = {:#04x}, flags {:#04x}",
+                a,
+                carries
+            );
+        }
+    }
+}
+
+#[test]
+fn daa_after_addition_yields_bcd_sum() {
+    let bcd = |n: u16| (((n / 10) << 4) | (n % 10)) as u8;
+
+    for x in 0..100 {
+        for y in 0..100 {
+            for &carry in &[false, true] {
+                let state = setting_register(new_state().setting_a(bcd(x)), B, bcd(y));
+                let state = setting_flags(state, flag(CY, carry));
+                // ADC B, DAA
+                let state = executing(executing(state, &[0x88]), &[0x27]);
+                let sum = x + y + carry as u16;
+
+                assert_eq!(state.a, bcd(sum % 100), "{} + {} + {}", x, y, carry as u8);
+                assert_eq!(state.cy(), sum >= 100, "{} + {} + {}", x, y, carry as u8);
+            }
+        }
+    }
+}
+
+#[test]
+fn daa_has_no_subtraction_adjust() {
+    // 0x15 - 0x06 leaves 0x0f, which the 8080 adjusts as if it followed an addition. A Z80 would
+    // produce 0x09 here.
+    let state = setting_register(new_state().setting_a(0x15), B, 0x06);
+    let state = executing(executing(state, &[0x90]), &[0x27]);
+
+    assert_eq!(state.a, 0x15);
+    assert!(state.ac());
+    assert!(!state.cy());
+}
+
+#[test]
+fn mov_copies_every_register_pair() {
+    let mut rng = Lcg(0x4040);
+
+    for opcode in 0x40..=0x7fu8 {
+        if opcode == 0x76 {
+            continue;
+        }
+        let destination = (opcode >> 3) & 7;
+        let source = opcode & 7;
+
+        let mut before = setting_flags(new_state(), S | Z | AC | P | CY);
+        for index in [0, 1, 2, 3, A].iter() {
+            before = setting_register(before, *index, rng.next_byte());
+        }
+        before.memory[MEMORY_OPERAND as usize] = rng.next_byte();
+        // H and L keep pointing at the memory operand unless they are the destination
+        let value = register(&before, source);
+        let after = executing(before.clone(), &[opcode]);
+
+        if destination == M {
+            assert_eq!(
+                after.memory[MEMORY_OPERAND as usize], value,
+                "opcode {:#04x}",
+                opcode
+            );
+        } else {
+            assert_eq!(
+                register(&after, destination),
+                value,
+                "opcode {:#04x}",
+                opcode
+            );
+        }
+        for index in (0..8).filter(|i| *i != destination && *i != M) {
+            assert_eq!(
+                register(&after, index),
+                register(&before, index),
+                "opcode {:#04x}",
+                opcode
+            );
+        }
+        assert_eq!(after.cc, before.cc);
+    }
+}
+
+#[test]
+fn mvi_loads_every_register() {
+    for index in 0..8u8 {
+        let opcode = 0x06 | (index << 3);
+        let after = executing(new_state(), &[opcode, 0xa7]);
+
+        assert_eq!(register(&after, index), 0xa7, "opcode {:#04x}", opcode);
+        assert_eq!(after.cc.bits(), PAD);
+    }
+}
+
+#[test]
+fn lxi_inx_dcx_and_dad_use_every_register_pair() {
+    let pair = |state: &State8080, index: u8| -> u16 {
+        match index {
+            0 => state.bc().into(),
+            1 => state.de().into(),
+            2 => state.hl().into(),
+            _ => state.sp,
+        }
+    };
+
+    for index in 0..4u8 {
+        let lxi = 0x01 | (index << 4);
+        let inx = 0x03 | (index << 4);
+        let dad = 0x09 | (index << 4);
+        let dcx = 0x0b | (index << 4);
+
+        let loaded = executing(new_state(), &[lxi, 0x34, 0x12]);
+        assert_eq!(pair(&loaded, index), 0x1234, "LXI {:#04x}", lxi);
+
+        for &(value, flags) in [(0xffffu16, S | Z | AC | P | CY), (0x00ff, 0), (0x0000, CY)].iter()
+        {
+            let before = executing(
+                setting_flags(new_state(), flags),
+                &[lxi, value as u8, (value >> 8) as u8],
+            );
+
+            let after = executing(before.clone(), &[inx]);
+            assert_eq!(
+                pair(&after, index),
+                value.wrapping_add(1),
+                "INX {:#04x}",
+                inx
+            );
+            assert_eq!(after.cc, before.cc, "INX {:#04x}", inx);
+
+            let after = executing(before.clone(), &[dcx]);
+            assert_eq!(
+                pair(&after, index),
+                value.wrapping_sub(1),
+                "DCX {:#04x}",
+                dcx
+            );
+            assert_eq!(after.cc, before.cc, "DCX {:#04x}", dcx);
+        }
+
+        let mut rng = Lcg(u32::from(dad));
+        for _ in 0..256 {
+            let hl = u16::from_le_bytes([rng.next_byte(), rng.next_byte()]);
+            let operand = u16::from_le_bytes([rng.next_byte(), rng.next_byte()]);
+            let stale = stale_flags(hl as u8, operand as u8, false);
+
+            let mut before = setting_flags(new_state(), stale);
+            before = executing(before, &[lxi, operand as u8, (operand >> 8) as u8]);
+            before = executing(before, &[0x21, hl as u8, (hl >> 8) as u8]);
+            let operand = pair(&before, index);
+            let after = executing(before, &[dad]);
+
+            let sum = u32::from(hl) + u32::from(operand);
+            assert_eq!(u16::from(after.hl()), sum as u16, "DAD {:#04x}", dad);
+            assert_eq!(
+                after.cc.bits(),
+                stale | PAD | flag(CY, sum > 0xffff),
+                "DAD {:#04x}",
+                dad
+            );
+        }
+    }
+}
+
+#[test]
+fn direct_and_indirect_loads_and_stores() {
+    let mut state = new_state();
+    state.b = 0x20;
+    state.c = 0x10;
+    state.d = 0x30;
+    state.e = 0x20;
+    state.memory[0x2010] = 0x11;
+    state.memory[0x3020] = 0x22;
+    state.memory[0x5000] = 0x33;
+    state.memory[0x5001] = 0x44;
+
+    // LDAX B, LDAX D
+    assert_eq!(executing(state.clone(), &[0x0a]).a, 0x11);
+    assert_eq!(executing(state.clone(), &[0x1a]).a, 0x22);
+    // LDA
+    assert_eq!(executing(state.clone(), &[0x3a, 0x00, 0x50]).a, 0x33);
+    // LHLD
+    let after = executing(state.clone(), &[0x2a, 0x00, 0x50]);
+    assert_eq!((after.h, after.l), (0x44, 0x33));
+
+    let state = state.setting_a(0x99);
+    // STAX B, STAX D
+    assert_eq!(executing(state.clone(), &[0x02]).memory[0x2010], 0x99);
+    assert_eq!(executing(state.clone(), &[0x12]).memory[0x3020], 0x99);
+    // STA
+    assert_eq!(
+        executing(state.clone(), &[0x32, 0x00, 0x60]).memory[0x6000],
+        0x99
+    );
+    // SHLD
+    let after = executing(state, &[0x22, 0x00, 0x60]);
+    assert_eq!(after.memory[0x6000..0x6002], [0x00, 0x40]);
+}
+
+#[test]
+fn exchanges_and_stack_pointer_transfers() {
+    let mut state = new_state();
+    state.d = 0x12;
+    state.e = 0x34;
+    state.memory[STACK as usize] = 0x78;
+    state.memory[STACK as usize + 1] = 0x56;
+
+    // XCHG
+    let after = executing(state.clone(), &[0xeb]);
+    assert_eq!(
+        (after.d, after.e, after.h, after.l),
+        (0x40, 0x00, 0x12, 0x34)
+    );
+
+    // XTHL
+    let after = executing(state.clone(), &[0xe3]);
+    assert_eq!((after.h, after.l), (0x56, 0x78));
+    assert_eq!(
+        after.memory[STACK as usize..STACK as usize + 2],
+        [0x00, 0x40]
+    );
+    assert_eq!(after.sp, STACK);
+
+    // SPHL
+    assert_eq!(executing(state.clone(), &[0xf9]).sp, MEMORY_OPERAND);
+
+    // PCHL
+    assert_eq!(executing(state, &[0xe9]).pc, MEMORY_OPERAND);
+}
+
+type RegisterPair = fn(&State8080) -> (u8, u8);
+
+#[test]
+fn push_and_pop_every_register_pair() {
+    let mut state = new_state();
+    state.b = 0x01;
+    state.c = 0x02;
+    state.d = 0x03;
+    state.e = 0x04;
+    state.a = 0x07;
+    let state = setting_flags(state, S | P | CY);
+
+    let pushes = [
+        (0xc5, [0x02, 0x01]),
+        (0xd5, [0x04, 0x03]),
+        (0xe5, [0x00, 0x40]),
+        (0xf5, [S | P | CY | PAD, 0x07]),
+    ];
+    for &(opcode, bytes) in pushes.iter() {
+        let after = executing(state.clone(), &[opcode]);
+
+        assert_eq!(after.sp, STACK - 2, "PUSH {:#04x}", opcode);
+        assert_eq!(
+            after.memory[STACK as usize - 2..STACK as usize],
+            bytes,
+            "PUSH {:#04x}",
+            opcode
+        );
+    }
+
+    let mut state = new_state();
+    state.memory[STACK as usize] = 0xff;
+    state.memory[STACK as usize + 1] = 0xee;
+    let pops: [(u8, RegisterPair); 3] = [
+        (0xc1, |s| (s.b, s.c)),
+        (0xd1, |s| (s.d, s.e)),
+        (0xe1, |s| (s.h, s.l)),
+    ];
+    for &(opcode, pair) in pops.iter() {
+        let after = executing(state.clone(), &[opcode]);
+
+        assert_eq!(pair(&after), (0xee, 0xff), "POP {:#04x}", opcode);
+        assert_eq!(after.sp, STACK + 2, "POP {:#04x}", opcode);
+    }
+
+    // POP PSW forces the fixed bits of the flags byte
+    let after = executing(state, &[0xf1]);
+    assert_eq!(
+        (after.a, after.cc.bits()),
+        (0xee, S | Z | AC | P | PAD | CY)
+    );
+}
+
+// Condition encoding shared by Jcc, Ccc and Rcc: NZ, Z, NC, C, PO, PE, P, M
+const CONDITIONS: [(u8, bool); 8] = [
+    (Z, false),
+    (Z, true),
+    (CY, false),
+    (CY, true),
+    (P, false),
+    (P, true),
+    (S, false),
+    (S, true),
+];
+
+#[test]
+fn conditional_jumps_calls_and_returns() {
+    for (index, &(bit, when_set)) in CONDITIONS.iter().enumerate() {
+        let index = index as u8;
+        let jcc = 0xc2 | (index << 3);
+        let ccc = 0xc4 | (index << 3);
+        let rcc = 0xc0 | (index << 3);
+
+        for &set in [false, true].iter() {
+            let taken = set == when_set;
+            let mut state = setting_flags(new_state(), flag(bit, set));
+            state.memory[STACK as usize] = 0x00;
+            state.memory[STACK as usize + 1] = 0x30;
+
+            let after = executing(state.clone(), &[jcc, 0x00, 0x20]);
+            assert_eq!(
+                after.pc,
+                if taken { 0x2000 } else { ORIGIN + 3 },
+                "J {:#04x}",
+                jcc
+            );
+
+            let after = executing(state.clone(), &[ccc, 0x00, 0x20]);
+            if taken {
+                assert_eq!(after.pc, 0x2000, "C {:#04x}", ccc);
+                assert_eq!(after.sp, STACK - 2, "C {:#04x}", ccc);
+                assert_eq!(
+                    after.memory[STACK as usize - 2..STACK as usize],
+                    [0x03, 0x01]
+                );
+            } else {
+                assert_eq!((after.pc, after.sp), (ORIGIN + 3, STACK), "C {:#04x}", ccc);
+            }
+
+            let after = executing(state, &[rcc]);
+            if taken {
+                assert_eq!((after.pc, after.sp), (0x3000, STACK + 2), "R {:#04x}", rcc);
+            } else {
+                assert_eq!((after.pc, after.sp), (ORIGIN + 1, STACK), "R {:#04x}", rcc);
+            }
+        }
+    }
+}
+
+#[test]
+fn unconditional_jump_call_return_and_restarts() {
+    let mut state = new_state();
+    state.memory[STACK as usize] = 0x00;
+    state.memory[STACK as usize + 1] = 0x30;
+
+    // JMP
+    assert_eq!(executing(state.clone(), &[0xc3, 0x00, 0x20]).pc, 0x2000);
+    // CALL
+    let after = executing(state.clone(), &[0xcd, 0x00, 0x20]);
+    assert_eq!((after.pc, after.sp), (0x2000, STACK - 2));
+    assert_eq!(
+        after.memory[STACK as usize - 2..STACK as usize],
+        [0x03, 0x01]
+    );
+    // RET
+    let after = executing(state.clone(), &[0xc9]);
+    assert_eq!((after.pc, after.sp), (0x3000, STACK + 2));
+
+    for n in 0..8u8 {
+        let opcode = 0xc7 | (n << 3);
+        let after = executing(state.clone(), &[opcode]);
+
+        assert_eq!(after.pc, u16::from(n) * 8, "RST {:#04x}", opcode);
+        assert_eq!(after.sp, STACK - 2, "RST {:#04x}", opcode);
+        assert_eq!(
+            after.memory[STACK as usize - 2..STACK as usize],
+            [0x01, 0x01]
+        );
+    }
+}
+
+#[test]
+fn ei_and_di_toggle_interrupts() {
+    let state = executing(new_state(), &[0xfb]);
+    assert!(state.interrupt_enabled);
+
+    let state = executing(state, &[0xf3]);
+    assert!(!state.interrupt_enabled);
+}
+
+#[derive(Default)]
+struct Ports {
+    written: Vec<(u8, u8)>,
+    read: Vec<u8>,
+}
+
+impl IOHandler for Ports {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        self.read.push(port);
+
+        state.setting_a(port ^ 0xff)
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        self.written.push((port, state.a));
+
+        state
+    }
+}
+
+#[test]
+fn in_and_out_go_through_the_io_handler() {
+    let mut ports = Ports::default();
+    let mut state = new_state().setting_a(0x5a);
+    state.memory[ORIGIN as usize..ORIGIN as usize + 4].copy_from_slice(&[0xd3, 0x10, 0xdb, 0x20]);
+    state.pc = ORIGIN;
+
+    let state = state.evaluating_next(Some(&mut ports));
+    let state = state.evaluating_next(Some(&mut ports));
+
+    assert_eq!(ports.written, vec![(0x10, 0x5a)]);
+    assert_eq!(ports.read, vec![0x20]);
+    assert_eq!(state.a, 0xdf);
+    assert_eq!(state.cc.bits(), PAD);
+}
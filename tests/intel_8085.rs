@@ -0,0 +1,397 @@
+use std::convert::TryFrom;
+
+use emu_8080::disassembler::{Instruction, Instruction8085};
+use emu_8080::emulator::{ConditionCodes, CpuVariant, DummyIOHandler, InterruptPin, State8080};
+
+const ORIGIN: u16 = 0x0100;
+const STACK: u16 = 0x8000;
+
+const K: u8 = 0x20;
+const V: u8 = 0x02;
+
+fn new_state() -> State8080 {
+    let mut state = State8080::new().setting_variant(CpuVariant::Intel8085);
+    state.sp = STACK;
+
+    state
+}
+
+fn loading(state: State8080, bytes: &[u8]) -> State8080 {
+    let mut state = state;
+    state.pc = ORIGIN;
+    state.memory[ORIGIN as usize..ORIGIN as usize + bytes.len()].copy_from_slice(bytes);
+
+    state
+}
+
+fn executing(state: State8080, bytes: &[u8]) -> State8080 {
+    loading(state, bytes).evaluating_next::<DummyIOHandler>(None)
+}
+
+fn stepping(state: State8080) -> State8080 {
+    state.evaluating_next::<DummyIOHandler>(None)
+}
+
+fn popped_return_address(state: &State8080) -> u16 {
+    state.memory[state.sp as usize] as u16 | (state.memory[state.sp as usize + 1] as u16) << 8
+}
+
+#[test]
+fn variant_defaults_to_8080_and_keeps_alternate_nops() {
+    let state = State8080::new();
+    assert_eq!(state.variant(), CpuVariant::Intel8080);
+
+    // RIM is a NOP on the 8080
+    let mut state = state;
+    state.a = 0x55;
+    let state = executing(state, &[0x20]);
+
+    assert_eq!(state.a, 0x55);
+    assert_eq!(state.pc, ORIGIN + 1);
+}
+
+#[test]
+fn repurposed_opcodes_decode_with_size_and_cycles() {
+    let expected: [(u8, u8, u8); 12] = [
+        (0x08, 1, 10),
+        (0x10, 1, 7),
+        (0x18, 1, 10),
+        (0x20, 1, 4),
+        (0x28, 2, 10),
+        (0x30, 1, 4),
+        (0x38, 2, 10),
+        (0xcb, 1, 12),
+        (0xd9, 1, 10),
+        (0xdd, 3, 10),
+        (0xed, 1, 10),
+        (0xfd, 3, 10),
+    ];
+
+    for &(op_code, size, cycles) in expected.iter() {
+        let instruction = Instruction8085::try_from(op_code).unwrap();
+
+        assert_eq!(instruction as u8, op_code);
+        assert_eq!((instruction.size(), instruction.cycles()), (size, cycles));
+    }
+}
+
+#[test]
+fn cycle_counts_follow_the_8085() {
+    // MOV B,C, INX B, PUSH B, CALL, RST 0, XTHL, SPHL and an unchanged ADD B
+    let expected: [(&[u8], u8); 8] = [
+        (&[0x41], 4),
+        (&[0x03], 6),
+        (&[0xc5], 12),
+        (&[0xcd, 0x00, 0x20], 18),
+        (&[0xc7], 12),
+        (&[0xe3], 16),
+        (&[0xf9], 6),
+        (&[0x80], 4),
+    ];
+
+    for &(bytes, cycles) in expected.iter() {
+        let state = executing(new_state(), bytes);
+
+        assert_eq!(state.last_cycles(), cycles, "{:#04x}", bytes[0]);
+        let instruction = Instruction::try_from(bytes[0]).unwrap();
+        assert_eq!(instruction.cycles_8085(), cycles);
+    }
+}
+
+#[test]
+fn sim_sets_masks_and_serial_output_and_rim_reads_them_back() {
+    let mut state = new_state();
+
+    // Masks are all set after reset
+    state = executing(state, &[0x20]);
+    assert_eq!(state.a, 0x07);
+
+    // MSE with M6.5 only, SOE with SOD high
+    state.a = 0x80 | 0x40 | 0x08 | 0x02;
+    state = executing(state, &[0x30]);
+    assert!(state.sod());
+
+    state.interrupt_enabled = true;
+    state = executing(state.setting_sid(true), &[0x20]);
+    assert_eq!(state.a, 0x80 | 0x08 | 0x02);
+
+    // Without SOE the serial output is left alone
+    state.a = 0x00;
+    state = executing(state, &[0x30]);
+    assert!(state.sod());
+}
+
+#[test]
+fn rst_7_5_is_latched_on_the_rising_edge_until_serviced() {
+    let mut state = new_state();
+    state.interrupt_enabled = true;
+    state.a = 0x08;
+    let state = executing(state, &[0x30, 0x00, 0x00]);
+
+    let state = state
+        .setting_interrupt_pin(InterruptPin::Rst75, true)
+        .setting_interrupt_pin(InterruptPin::Rst75, false);
+    let state = stepping(state);
+
+    assert_eq!(state.pc, 0x3c);
+    assert_eq!(popped_return_address(&state), ORIGIN + 1);
+    assert_eq!(state.last_cycles(), 12);
+    assert!(!state.interrupt_enabled);
+
+    // The latch is cleared by servicing it
+    let mut state = state;
+    state.interrupt_enabled = true;
+    let state = executing(state, &[0x20]);
+    assert_eq!(state.a & 0x40, 0);
+}
+
+#[test]
+fn masked_or_disabled_interrupts_wait() {
+    let mut state = new_state()
+        .setting_interrupt_pin(InterruptPin::Rst55, true)
+        .setting_interrupt_pin(InterruptPin::Rst75, true);
+    state.interrupt_enabled = true;
+
+    // All masked after reset, RIM shows both pending
+    let state = executing(state, &[0x20, 0x00]);
+    assert_eq!(state.a & 0x70, 0x50);
+    let state = stepping(state);
+    assert_eq!(state.pc, ORIGIN + 2);
+
+    // Unmasking RST 5.5 alone while interrupts are disabled still waits
+    let mut state = state;
+    state.interrupt_enabled = false;
+    state.a = 0x08 | 0x06;
+    let state = executing(state, &[0x30, 0x00]);
+    let state = stepping(state);
+    assert_eq!(state.pc, ORIGIN + 2);
+
+    let mut state = state;
+    state.interrupt_enabled = true;
+    let state = stepping(state);
+    assert_eq!(state.pc, 0x2c);
+}
+
+#[test]
+fn interrupts_are_serviced_by_priority() {
+    let mut state = new_state();
+    state.interrupt_enabled = true;
+    state.a = 0x08;
+    let state = executing(state, &[0x30]);
+
+    let state = state
+        .setting_interrupt_pin(InterruptPin::Rst55, true)
+        .setting_interrupt_pin(InterruptPin::Rst65, true);
+    let state = stepping(state);
+    assert_eq!(state.pc, 0x34);
+
+    let mut state = state.setting_interrupt_pin(InterruptPin::Rst65, false);
+    state.interrupt_enabled = true;
+    let state = stepping(state);
+    assert_eq!(state.pc, 0x2c);
+}
+
+#[test]
+fn interrupts_wait_for_the_instruction_after_ei() {
+    // Unmask everything, then EI; NOP; NOP with RST 5.5 held high
+    let mut state = new_state();
+    state.a = 0x08;
+    let state = executing(state, &[0x30]);
+    let state =
+        loading(state, &[0xfb, 0x00, 0x00]).setting_interrupt_pin(InterruptPin::Rst55, true);
+
+    let state = stepping(state);
+    assert!(state.interrupt_enabled);
+    // Saved in between, the delay carries over
+    let state = State8080::from_save_state(&state.save_state()).unwrap();
+    let state = stepping(state);
+    assert_eq!(state.pc, ORIGIN + 2);
+    let state = stepping(state);
+    assert_eq!(state.pc, 0x2c);
+    assert_eq!(popped_return_address(&state), ORIGIN + 2);
+
+    // A handler ending in EI; RET returns before the interrupt comes back
+    let mut state = state;
+    state.memory[0x2c..0x2e].copy_from_slice(&[0xfb, 0xc9]);
+    let state = stepping(stepping(state));
+    assert_eq!(state.pc, ORIGIN + 2);
+    assert_eq!(state.sp, STACK);
+    let state = stepping(state);
+    assert_eq!(state.pc, 0x2c);
+}
+
+#[test]
+fn trap_ignores_interrupt_enable_and_rim_reports_the_previous_state() {
+    let mut state = new_state();
+    state.interrupt_enabled = true;
+    let state = loading(state, &[0x20]).setting_interrupt_pin(InterruptPin::Trap, true);
+    let state = stepping(state);
+
+    assert_eq!(state.pc, 0x24);
+    assert!(!state.interrupt_enabled);
+
+    // Holding the pin high does not retrigger it
+    let state = loading(state, &[0x20, 0x20]);
+    let state = stepping(state);
+    assert_eq!(state.a & 0x08, 0x08);
+    let state = stepping(state);
+    assert_eq!(state.a & 0x08, 0);
+    assert_eq!(state.pc, ORIGIN + 2);
+}
+
+#[test]
+fn overflow_and_k_flags() {
+    // 0x7f + 1 overflows into a negative result, K is S xor V
+    let mut state = new_state();
+    state.a = 0x7f;
+    state.b = 0x01;
+    let state = executing(state, &[0x80]);
+    assert!(state.v());
+    assert_eq!(state.cc.bits() & (K | V), V);
+
+    // 0x80 - 1 overflows into a positive result
+    let mut state = state;
+    state.a = 0x80;
+    let state = executing(state, &[0x90]);
+    assert!(state.v());
+    assert_eq!(state.cc.bits() & K, K);
+
+    let mut state = state;
+    state.d = 0x80;
+    let state = executing(state, &[0x15]);
+    assert!(state.v());
+
+    let state = executing(state, &[0xa0]);
+    assert!(!state.v());
+
+    // INX wrapping to zero sets K, anything else clears it
+    let mut state = state;
+    state.b = 0xff;
+    state.c = 0xff;
+    let state = executing(state, &[0x03]);
+    assert!(state.k());
+    let state = executing(state, &[0x03]);
+    assert!(!state.k());
+    let state = executing(state, &[0x1b]);
+    assert!(!state.k());
+    let mut state = state;
+    state.sp = 0;
+    let state = executing(state, &[0x3b]);
+    assert!(state.k());
+}
+
+#[test]
+fn pop_psw_keeps_v_and_k_only_on_the_8085() {
+    let mut state = new_state();
+    state.memory[STACK as usize] = 0xff;
+    let state = executing(state, &[0xf1]);
+    assert_eq!(state.cc.bits(), 0xf7);
+
+    let mut state = State8080::new();
+    state.sp = STACK;
+    state.memory[STACK as usize] = 0xff;
+    let state = executing(state, &[0xf1]);
+    assert_eq!(state.cc.bits(), 0xd7);
+}
+
+#[test]
+fn sixteen_bit_undocumented_instructions() {
+    // DSUB
+    let mut state = new_state();
+    state.h = 0x12;
+    state.l = 0x34;
+    state.b = 0x02;
+    state.c = 0x35;
+    let state = executing(state, &[0x08]);
+    assert_eq!((state.h, state.l), (0x0f, 0xff));
+    assert!(!state.cy());
+    assert!(!state.z());
+
+    let mut state = state;
+    state.b = 0x0f;
+    state.c = 0xff;
+    let state = executing(state, &[0x08]);
+    assert_eq!((state.h, state.l), (0x00, 0x00));
+    assert!(state.z());
+
+    // ARHL keeps the sign and can't overflow
+    let mut state = state;
+    state.h = 0x80;
+    state.l = 0x03;
+    state.cc.insert(ConditionCodes::V);
+    let state = executing(state, &[0x10]);
+    assert_eq!((state.h, state.l), (0xc0, 0x01));
+    assert!(state.cy());
+    assert!(!state.v());
+
+    // RDEL rotates through carry, overflowing when the sign changes
+    let mut state = state;
+    state.d = 0x80;
+    state.e = 0x01;
+    let state = executing(state, &[0x18]);
+    assert_eq!((state.d, state.e), (0x00, 0x03));
+    assert!(state.cy());
+    assert!(state.v());
+
+    let mut state = state;
+    state.d = 0xc0;
+    state.e = 0x00;
+    let state = executing(state, &[0x18]);
+    assert_eq!((state.d, state.e), (0x80, 0x01));
+    assert!(state.cy());
+    assert!(!state.v());
+
+    let mut state = state;
+    state.d = 0x40;
+    let state = executing(state, &[0x18]);
+    assert_eq!((state.d, state.e), (0x80, 0x03));
+    assert!(!state.cy());
+    assert!(state.v());
+
+    // LDHI and LDSI
+    let state = executing(state, &[0x28, 0x10]);
+    assert_eq!((state.d, state.e), (0xc0, 0x11));
+    let state = executing(state, &[0x38, 0x02]);
+    assert_eq!((state.d, state.e), (0x80, 0x02));
+}
+
+#[test]
+fn shlx_and_lhlx_go_through_de() {
+    let mut state = new_state();
+    state.d = 0x40;
+    state.e = 0x00;
+    state.h = 0xbe;
+    state.l = 0xef;
+    let state = executing(state, &[0xd9]);
+    assert_eq!(&state.memory[0x4000..0x4002], &[0xef, 0xbe]);
+    assert_eq!(state.last_memory_writes(), &[0x4000, 0x4001]);
+
+    let mut state = state;
+    state.h = 0;
+    state.l = 0;
+    let state = executing(state, &[0xed]);
+    assert_eq!((state.h, state.l), (0xbe, 0xef));
+}
+
+#[test]
+fn branches_on_v_and_k() {
+    let mut state = new_state();
+    state.b = 0xff;
+    state.c = 0xff;
+    let state = executing(state, &[0x03]);
+
+    let taken = executing(state, &[0xfd, 0x00, 0x20]);
+    assert_eq!(taken.pc, 0x2000);
+    let not_taken = executing(taken, &[0xdd, 0x00, 0x20]);
+    assert_eq!(not_taken.pc, ORIGIN + 3);
+
+    let not_taken = executing(not_taken, &[0xcb]);
+    assert_eq!(not_taken.pc, ORIGIN + 1);
+
+    let mut state = not_taken;
+    state.a = 0x7f;
+    let state = executing(state, &[0x3c]);
+    let state = executing(state, &[0xcb]);
+    assert_eq!(state.pc, 0x40);
+    assert_eq!(popped_return_address(&state), ORIGIN + 1);
+}
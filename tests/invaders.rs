@@ -0,0 +1,171 @@
+use emu_8080::machines::invaders::{
+    DipSwitches, Input, Invaders, InvadersIO, ShiftRegister, HEIGHT, ROM_FILES, VIDEO_RAM_START,
+    WIDTH,
+};
+
+// Stand-in for the game ROM: counts the RST 1 and RST 2 interrupts at 0x2000 and 0x2001, and
+// stores the RST 1 count seen by every RST 2 at 0x2002.
+#[rustfmt::skip]
+fn interrupt_counting_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x2000];
+    // JMP 0x0040
+    rom[0x00..0x03].copy_from_slice(&[0xc3, 0x40, 0x00]);
+    // RST 1: JMP 0x0080
+    rom[0x08..0x0b].copy_from_slice(&[0xc3, 0x80, 0x00]);
+    // RST 2: JMP 0x00a0
+    rom[0x10..0x13].copy_from_slice(&[0xc3, 0xa0, 0x00]);
+    // LXI SP,0x2400; EI; JMP 0x0044
+    rom[0x40..0x47].copy_from_slice(&[0x31, 0x00, 0x24, 0xfb, 0xc3, 0x44, 0x00]);
+    // PUSH PSW; LDA 0x2000; INR A; STA 0x2000; POP PSW; EI; RET
+    rom[0x80..0x8b].copy_from_slice(&[
+        0xf5, 0x3a, 0x00, 0x20, 0x3c, 0x32, 0x00, 0x20, 0xf1, 0xfb, 0xc9,
+    ]);
+    // PUSH PSW; LDA 0x2001; INR A; STA 0x2001; LDA 0x2000; STA 0x2002; POP PSW; EI; RET
+    rom[0xa0..0xb1].copy_from_slice(&[
+        0xf5, 0x3a, 0x01, 0x20, 0x3c, 0x32, 0x01, 0x20, 0x3a, 0x00, 0x20, 0x32, 0x02, 0x20, 0xf1,
+        0xfb, 0xc9,
+    ]);
+
+    rom
+}
+
+#[test]
+fn frames_raise_mid_screen_and_vblank_interrupts() {
+    let mut machine = Invaders::new(interrupt_counting_rom());
+
+    // RST 2 comes at the very end of the frame, its handler runs at the start of the next one
+    machine = machine.running_frame();
+    assert_eq!(&machine.cpu.memory[0x2000..0x2003], &[1, 0, 0]);
+    assert_eq!(machine.cpu.pc, 0x10);
+
+    for _ in 0..2 {
+        machine = machine.running_frame();
+    }
+    assert_eq!(&machine.cpu.memory[0x2000..0x2003], &[3, 2, 2]);
+    assert_eq!(machine.frames(), 3);
+}
+
+#[test]
+fn default_machine_runs_without_a_rom() {
+    let machine = Invaders::default().running_frame();
+
+    assert_eq!(machine.frames(), 1);
+    assert_eq!(machine.cpu.memory.len(), 0x10000);
+}
+
+#[test]
+fn interrupts_wait_for_ei() {
+    let mut rom = interrupt_counting_rom();
+    // DI in place of EI
+    rom[0x43] = 0xf3;
+    let machine = Invaders::new(rom).running_frame();
+
+    assert_eq!(&machine.cpu.memory[0x2000..0x2003], &[0, 0, 0]);
+}
+
+#[test]
+fn shift_register_through_ports() {
+    // MVI A,0xab; OUT 4; MVI A,0xcd; OUT 4; MVI A,3; OUT 2; IN 3
+    let mut rom = vec![0; 0x2000];
+    rom[..14].copy_from_slice(&[
+        0x3e, 0xab, 0xd3, 0x04, 0x3e, 0xcd, 0xd3, 0x04, 0x3e, 0x03, 0xd3, 0x02, 0xdb, 0x03,
+    ]);
+    let mut machine = Invaders::new(rom);
+    for _ in 0..7 {
+        machine = machine.stepping();
+    }
+
+    assert_eq!(machine.cpu.a, 0x6d);
+
+    let register = ShiftRegister::default().shifting_in(0xff).shifting_in(0x00);
+    assert_eq!(register.result(), 0x00);
+    assert_eq!(register.setting_offset(7).result(), 0x7f);
+}
+
+#[test]
+fn inputs_and_dip_switches() {
+    let io = InvadersIO::default();
+    assert_eq!(io.input_port(0), 0x0e);
+    assert_eq!(io.input_port(1), 0x08);
+    assert_eq!(io.input_port(2), 0x00);
+
+    let io = io
+        .setting_input(Input::Coin, true)
+        .setting_input(Input::P1Left, true)
+        .setting_input(Input::P2Shot, true);
+    assert_eq!(io.input_port(0), 0x2e);
+    assert_eq!(io.input_port(1), 0x29);
+    assert_eq!(io.input_port(2), 0x10);
+
+    let io = io.setting_input(Input::Coin, false);
+    assert_eq!(io.input_port(1), 0x28);
+
+    let mut io = InvadersIO::default();
+    io.dip_switches = DipSwitches {
+        ships: 5,
+        extra_ship_at_1000: true,
+        hide_coin_info: true,
+    };
+    assert_eq!(io.input_port(2), 0x8a);
+}
+
+#[test]
+fn sound_ports_are_latched() {
+    let io = InvadersIO::default()
+        .writing_port(3, 0x01)
+        .writing_port(5, 0x10)
+        .writing_port(6, 0xff);
+
+    assert_eq!(io.sound, [0x01, 0x10]);
+}
+
+#[test]
+fn framebuffer_and_png_capture() {
+    let mut machine = Invaders::new(vec![0; 0x2000]);
+    machine.cpu.memory[VIDEO_RAM_START as usize] = 0x01;
+    machine.cpu.memory[VIDEO_RAM_START as usize + 32 * 223 + 31] = 0x80;
+
+    assert_eq!(machine.framebuffer().len(), WIDTH * HEIGHT / 8);
+    assert!(machine.pixel(0, 0));
+    assert!(!machine.pixel(1, 0));
+    assert!(machine.pixel(255, 223));
+
+    let png = machine.png();
+    assert_eq!(
+        &png[..8],
+        &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']
+    );
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..20], &(WIDTH as u32).to_be_bytes());
+    assert_eq!(&png[20..24], &(HEIGHT as u32).to_be_bytes());
+    assert_eq!(png[24], 1);
+    assert_eq!(&png[37..41], b"IDAT");
+
+    // Stored deflate data, so the first row starts right after the headers with the leftmost
+    // pixel in the most significant bit
+    assert_eq!(&png[48..50], &[0x00, 0x80]);
+
+    // IEND always has the same CRC
+    assert_eq!(
+        &png[png.len() - 8..],
+        &[b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+    );
+}
+
+#[test]
+fn roms_load_from_a_directory() {
+    let directory = std::env::temp_dir().join(format!("invaders-roms-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    for (i, name) in ROM_FILES.iter().enumerate() {
+        std::fs::write(directory.join(name), vec![i as u8 + 1; 0x800]).unwrap();
+    }
+
+    let machine = Invaders::from_directory(&directory).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(machine.cpu.memory[0x0000], 1);
+    assert_eq!(machine.cpu.memory[0x0800], 2);
+    assert_eq!(machine.cpu.memory[0x1000], 3);
+    assert_eq!(machine.cpu.memory[0x1fff], 4);
+    assert!(Invaders::from_directory(std::env::temp_dir().join("no-such-roms")).is_err());
+}
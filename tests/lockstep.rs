@@ -0,0 +1,167 @@
+use emu_8080::emulator::{IOHandler, State8080};
+use emu_8080::lockstep::{BusEvent, Lockstep, Mismatch, Reference, Registers, StateWithIo};
+
+// MVI A,0x42; STA 0x2000; OUT 0x10; INR A; PUSH PSW
+const PROGRAM: [u8; 9] = [0x3e, 0x42, 0x32, 0x00, 0x20, 0xd3, 0x10, 0x3c, 0xf5];
+
+fn state_with_program() -> State8080 {
+    let mut state = State8080::new().loading_buffer_into_memory_at(PROGRAM.to_vec(), 0);
+    state.sp = 0x3000;
+
+    state
+}
+
+/// Wraps a correct implementation but gets INR wrong.
+struct BrokenInr(State8080);
+
+impl Reference for BrokenInr {
+    fn registers(&self) -> Registers {
+        self.0.registers()
+    }
+
+    fn read_memory(&self, address: u16) -> u8 {
+        self.0.read_memory(address)
+    }
+
+    fn step(&mut self, events: &mut Vec<BusEvent>) {
+        let is_inr = self.0.memory[self.0.pc as usize] == 0x3c;
+        self.0.step(events);
+        if is_inr {
+            self.0.a = self.0.a.wrapping_add(1);
+            self.0.cc.toggle(emu_8080::emulator::ConditionCodes::P);
+        }
+    }
+}
+
+#[test]
+fn identical_implementations_do_not_diverge() {
+    let mut lockstep = Lockstep::new(state_with_program(), state_with_program());
+
+    assert!(lockstep.compare().is_empty());
+    for _ in 0..5 {
+        lockstep.step().unwrap();
+    }
+    assert_eq!(lockstep.steps(), 5);
+    assert_eq!(lockstep.subject.memory[0x2000], 0x42);
+}
+
+#[test]
+fn state_reports_memory_writes_and_port_traffic() {
+    let mut state = state_with_program();
+    let mut events = Vec::new();
+
+    state.step(&mut events);
+    assert!(events.is_empty());
+
+    state.step(&mut events);
+    assert_eq!(
+        events,
+        vec![BusEvent::MemoryWrite {
+            address: 0x2000,
+            value: 0x42
+        }]
+    );
+
+    events.clear();
+    state.step(&mut events);
+    assert_eq!(
+        events,
+        vec![BusEvent::PortOut {
+            port: 0x10,
+            value: 0x42
+        }]
+    );
+}
+
+#[test]
+fn divergence_lists_every_mismatch() {
+    let mut lockstep = Lockstep::new(state_with_program(), BrokenInr(state_with_program()));
+
+    for _ in 0..3 {
+        lockstep.step().unwrap();
+    }
+    let divergence = lockstep.step().unwrap_err();
+
+    assert_eq!(divergence.step, 4);
+    assert_eq!(divergence.pc, 0x0007);
+    assert_eq!(divergence.opcode, 0x3c);
+    assert_eq!(
+        divergence.mismatches,
+        vec![
+            Mismatch::Register {
+                name: "A",
+                expected: 0x44,
+                actual: 0x43
+            },
+            Mismatch::Flag {
+                name: "P",
+                expected: true,
+                actual: false
+            },
+        ]
+    );
+
+    // The PUSH PSW that follows writes the diverged accumulator
+    let divergence = lockstep.step().unwrap_err();
+    assert!(divergence
+        .mismatches
+        .iter()
+        .any(|mismatch| matches!(mismatch, Mismatch::Bus { .. })));
+}
+
+/// Answers IN with the port plus one.
+struct Echo;
+
+impl IOHandler for Echo {
+    fn inp(&mut self, mut state: State8080, port: u8) -> State8080 {
+        state.a = port.wrapping_add(1);
+
+        state
+    }
+
+    fn out(&mut self, state: State8080, _port: u8) -> State8080 {
+        state
+    }
+}
+
+#[test]
+fn states_with_io_handlers_serve_in() {
+    // IN 0x10; OUT 0x20
+    let state = State8080::new().loading_buffer_into_memory_at(vec![0xdb, 0x10, 0xd3, 0x20], 0);
+    let with_io = || StateWithIo {
+        state: state.clone(),
+        io_handler: Echo,
+    };
+    let mut events = Vec::new();
+
+    let mut lockstep = Lockstep::new(with_io(), with_io());
+    lockstep.step().unwrap();
+    lockstep.step().unwrap();
+    assert_eq!(lockstep.subject.state.a, 0x11);
+
+    let mut reference = with_io();
+    reference.step(&mut events);
+    reference.step(&mut events);
+    assert_eq!(
+        events,
+        vec![
+            BusEvent::PortIn {
+                port: 0x10,
+                value: 0x11
+            },
+            BusEvent::PortOut {
+                port: 0x20,
+                value: 0x11
+            }
+        ]
+    );
+
+    // Without an IO handler IN leaves A alone
+    let mut lockstep = Lockstep::new(state.clone(), with_io());
+    let divergence = lockstep.step().unwrap_err();
+    assert!(divergence.mismatches.contains(&Mismatch::Register {
+        name: "A",
+        expected: 0x11,
+        actual: 0x00
+    }));
+}
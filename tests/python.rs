@@ -0,0 +1,149 @@
+//! Runs the Python bindings in an embedded interpreter, with `--features python`.
+#![cfg(feature = "python")]
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use emu_8080::python::PyState8080;
+
+// Runs `code` with the `State8080` class in scope, failing with the Python exception
+fn running(code: &str) {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let globals = PyDict::new_bound(py);
+        globals
+            .set_item("State8080", py.get_type_bound::<PyState8080>())
+            .unwrap();
+        if let Err(error) = py.run_bound(code, Some(&globals), None) {
+            panic!("{}", error);
+        }
+    });
+}
+
+#[test]
+fn steps_and_runs_with_python_io() {
+    running(
+        r#"
+class Io:
+    def __init__(self):
+        self.outputs = []
+        self.writes = []
+
+    def input(self, port):
+        return port + 1
+
+    def output(self, port, value):
+        self.outputs.append((port, value))
+
+    def memory_write(self, address, value):
+        self.writes.append((address, value))
+
+cpu = State8080()
+# IN 0x10; OUT 0x20; LXI SP,0x1000; PUSH PSW; HLT
+cpu.load(bytes([0xdb, 0x10, 0xd3, 0x20, 0x31, 0x00, 0x10, 0xf5, 0x76]))
+io = Io()
+assert cpu.step(io) == 10
+assert cpu.a == 0x11
+assert cpu.run(1000, io) == 10 + 10 + 11 + 7
+assert cpu.halted
+assert io.outputs == [(0x20, 0x11)]
+assert io.writes == [(0x0fff, 0x11), (0x0ffe, 0x02)]
+assert cpu.read_memory(0x0ffe, 2) == bytes([0x02, 0x11])
+
+# Without an IO object nothing drives the bus
+cpu.pc = 0
+cpu.halted = False
+cpu.step()
+assert cpu.a == 0xff
+"#,
+    );
+}
+
+#[test]
+fn io_exceptions_stop_the_cpu() {
+    running(
+        r#"
+class Raising:
+    def input(self, port):
+        raise RuntimeError("boom")
+
+cpu = State8080()
+# IN 0x10; NOP; NOP
+cpu.load(bytes([0xdb, 0x10, 0x00, 0x00]))
+try:
+    cpu.run(100, Raising())
+    raise AssertionError("run went on")
+except RuntimeError as error:
+    assert str(error) == "boom"
+assert cpu.pc == 2
+
+class Returning:
+    def __init__(self, value):
+        self.value = value
+
+    def input(self, port):
+        return self.value
+
+for value, exception in [(0x100, OverflowError), (-1, OverflowError), ("1", TypeError)]:
+    cpu.pc = 0
+    try:
+        cpu.step(Returning(value))
+        raise AssertionError(f"{value!r} was accepted")
+    except exception:
+        pass
+"#,
+    );
+}
+
+#[test]
+fn memory_and_save_states_are_checked() {
+    running(
+        r#"
+cpu = State8080("8085")
+assert cpu.variant == "8085"
+for data, address in [(bytes(2), 0xffff), (bytes(0x10001), 0)]:
+    try:
+        cpu.load(data, address)
+        raise AssertionError("loaded past the end of memory")
+    except ValueError:
+        pass
+try:
+    cpu.load_file("/nonexistent/rom.bin")
+    raise AssertionError("loaded a missing file")
+except OSError:
+    pass
+assert cpu.read_memory(0xfffe, 4) == bytes(2)
+
+import os, tempfile
+with tempfile.NamedTemporaryFile(suffix=".bin", delete=False) as rom:
+    rom.write(bytes([0x12, 0x34]))
+try:
+    cpu.load_file(rom.name, 0xfff0)
+    assert cpu.read_memory(0xfff0, 2) == bytes([0x12, 0x34])
+    try:
+        cpu.load_file(rom.name, 0xffff)
+        raise AssertionError("loaded a file past the end of memory")
+    except ValueError:
+        pass
+finally:
+    os.remove(rom.name)
+
+# JMP 0x1234; RIM
+cpu.load(bytes([0xc3, 0x34, 0x12, 0x20]), 0x100)
+cpu.pc = 0x100
+assert cpu.disassemble() == ("Jmp    $1234", 3)
+assert cpu.disassemble(0x103) == ("Rim", 1)
+
+saved = cpu.save_state()
+restored = State8080()
+restored.load_state(saved)
+assert (restored.variant, restored.pc) == ("8085", 0x100)
+for data in [saved[:-1], b"8086"]:
+    try:
+        restored.load_state(data)
+        raise AssertionError("loaded an invalid save state")
+    except ValueError:
+        pass
+"#,
+    );
+}
@@ -0,0 +1,58 @@
+use emu_8080::emulator::{CpuVariant, DummyIOHandler, InterruptPin, SaveStateError, State8080};
+
+fn running(state: State8080, steps: usize) -> State8080 {
+    (0..steps).fold(state, |state, _| {
+        state.evaluating_next::<DummyIOHandler>(None)
+    })
+}
+
+#[test]
+fn restored_states_run_the_same() {
+    // LXI SP,0x1000; MVI A,0x08; SIM; EI; HLT
+    let program = [0x31, 0x00, 0x10, 0x3e, 0x08, 0x30, 0xfb, 0x76];
+    let state = State8080::new()
+        .setting_variant(CpuVariant::Intel8085)
+        .loading_buffer_into_memory_at(program.to_vec(), 0);
+    // RST 7.5 is latched while masked, and taken once SIM unmasks it
+    let state = running(state, 2).setting_interrupt_pin(InterruptPin::Rst75, true);
+
+    let saved = state.save_state();
+    let restored = State8080::from_save_state(&saved).unwrap();
+    assert_eq!(restored.variant(), CpuVariant::Intel8085);
+    assert_eq!(restored.save_state(), saved);
+
+    // SIM, EI, HLT, which runs before interrupts are taken, and the interrupt
+    let state = running(state, 4);
+    let restored = running(restored, 4);
+    assert_eq!(restored.pc, 0x3c);
+    assert!(restored.save_state() == state.save_state());
+}
+
+#[test]
+fn invalid_save_states_are_rejected() {
+    let saved = State8080::new().save_state();
+
+    assert_eq!(
+        State8080::from_save_state(b"8086").err(),
+        Some(SaveStateError::NotASaveState)
+    );
+    let mut future = saved.clone();
+    future[4] = 2;
+    assert_eq!(
+        State8080::from_save_state(&future).err(),
+        Some(SaveStateError::UnsupportedVersion(2))
+    );
+    assert_eq!(
+        State8080::from_save_state(&saved[..saved.len() - 1]).err(),
+        Some(SaveStateError::Truncated)
+    );
+    // A header claiming 10 bytes of memory, followed by them
+    let mut small = saved[..saved.len() - 0x10000].to_vec();
+    let len = small.len();
+    small[len - 4..].copy_from_slice(&10u32.to_le_bytes());
+    small.extend_from_slice(&[0; 10]);
+    assert_eq!(
+        State8080::from_save_state(&small).err(),
+        Some(SaveStateError::MemorySize(10))
+    );
+}
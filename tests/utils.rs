@@ -2,10 +2,10 @@ use std::path::Path;
 
 use emu_8080::emulator::{DummyIOHandler, State8080};
 
+/// Runs a CP/M test binary until it jumps back to 0x0000 and returns everything it wrote to the
+/// console. The output is also echoed to stdout as it is produced.
 #[allow(dead_code)]
-pub fn run_suite(path: impl AsRef<Path>) {
-    let mut state = create_state_with_rom(path.as_ref());
-
+pub fn run_suite(path: impl AsRef<Path>) -> String {
     let filename = path
         .as_ref()
         .file_name()
@@ -13,17 +13,64 @@ pub fn run_suite(path: impl AsRef<Path>) {
         .unwrap_or_default();
     println!("Starting running suite {}...", filename);
 
-    loop {
+    run_loaded_suite(create_state_with_rom(path.as_ref()))
+}
+
+/// Upper bound on the instructions a suite may run, about three times what 8080EXM, the longest, needs.
+const MAX_STEPS: u64 = 10_000_000_000;
+
+/// Same as `run_suite`, for a test binary that is already loaded, possibly patched, into `state`.
+/// Panics with the console output so far if the binary halts or runs for more than `MAX_STEPS`
+/// instructions.
+#[allow(dead_code)]
+pub fn run_loaded_suite(state: State8080) -> String {
+    let mut state = state;
+    let mut console = String::new();
+
+    for _ in 0..MAX_STEPS {
         state = state.evaluating_next::<DummyIOHandler>(None);
 
-        print_output(&state);
+        if let Some(output) = console_output(&state) {
+            print!("{}", output);
+            console.push_str(&output);
+        }
 
         if state.pc == 0 {
             println!();
             println!("Jumped to 0x0000, halting");
-            break;
+            return console;
         }
+
+        assert!(
+            !state.halted,
+            "Suite halted at {:#06x}, output was:\n{}",
+            state.pc, console
+        );
     }
+
+    panic!(
+        "Suite did not finish within {} instructions, output was:\n{}",
+        MAX_STEPS, console
+    );
+}
+
+/// Fails unless `output` contains `success` and none of the `errors` markers.
+#[allow(dead_code)]
+pub fn assert_suite_passed(output: &str, success: &str, errors: &[&str]) {
+    for error in errors {
+        assert!(
+            !output.contains(error),
+            "Suite reported {:?}, output was:\n{}",
+            error,
+            output
+        );
+    }
+    assert!(
+        output.contains(success),
+        "Suite did not report {:?}, output was:\n{}",
+        success,
+        output
+    );
 }
 
 pub fn create_state_with_rom(path: impl AsRef<Path>) -> State8080 {
@@ -35,19 +82,32 @@ pub fn create_state_with_rom(path: impl AsRef<Path>) -> State8080 {
     state
 }
 
-pub fn print_output(state: &State8080) {
-    let addr = state.pc;
-    if addr == 5 {
-        if state.c == 9 {
+/// Console output of the CP/M BDOS call about to be made, if `state` is at the BDOS entry point.
+/// Only functions 2 (console output) and 9 (print string) are supported.
+pub fn console_output(state: &State8080) -> Option<String> {
+    if state.pc != 5 {
+        return None;
+    }
+
+    match state.c {
+        9 => {
             let offset: u16 = state.de().into();
-            state.memory[(offset as usize)..]
+            let output = state.memory[(offset as usize)..]
                 .iter()
                 .take_while(|c| **c != b'$')
-                .map(|c| *c)
-                .for_each(|c| print!("{}", c as char));
-            println!();
-        } else if state.c == 2 {
-            print!("{}", state.e as char);
+                .map(|c| *c as char)
+                .collect();
+
+            Some(output)
         }
+        2 => Some((state.e as char).to_string()),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn print_output(state: &State8080) {
+    if let Some(output) = console_output(state) {
+        print!("{}", output);
     }
 }
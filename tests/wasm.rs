@@ -0,0 +1,106 @@
+//! Run with `wasm-pack test --node -- --features wasm`.
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use js_sys::{Array, Function, Object, Reflect};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use emu_8080::wasm::WasmState8080;
+
+// An IO object answering IN with the port plus one and recording OUT and memory writes
+fn recording_io() -> Object {
+    let io = Object::new();
+    let set = |name: &str, value: &JsValue| Reflect::set(&io, &name.into(), value).unwrap();
+    set("outputs", &Array::new());
+    set("writes", &Array::new());
+    set("input", &Function::new_with_args("port", "return port + 1"));
+    set(
+        "output",
+        &Function::new_with_args("port, value", "this.outputs.push([port, value])"),
+    );
+    set(
+        "memoryWrite",
+        &Function::new_with_args("address, value", "this.writes.push([address, value])"),
+    );
+
+    io
+}
+
+fn recorded(io: &Object, name: &str) -> Vec<(u32, u32)> {
+    let array: Array = Reflect::get(io, &name.into()).unwrap().into();
+    array
+        .iter()
+        .map(|pair| {
+            let pair: Array = pair.into();
+            let at = |index| pair.get(index).as_f64().unwrap() as u32;
+            (at(0), at(1))
+        })
+        .collect()
+}
+
+#[wasm_bindgen_test]
+fn steps_and_runs_with_js_io() {
+    // IN 0x10; OUT 0x20; LXI SP,0x1000; PUSH PSW; HLT
+    let program = [0xdb, 0x10, 0xd3, 0x20, 0x31, 0x00, 0x10, 0xf5, 0x76];
+    let mut cpu = WasmState8080::new(None).unwrap();
+    cpu.load(&program, 0).unwrap();
+    let io: JsValue = recording_io().into();
+
+    assert_eq!(cpu.step(Some(io.clone())).unwrap(), 10);
+    assert_eq!(cpu.a(), 0x11);
+    assert_eq!(cpu.run(1000, Some(io.clone())).unwrap(), 10 + 10 + 11 + 7);
+    assert!(cpu.halted());
+
+    let io: Object = io.into();
+    assert_eq!(recorded(&io, "outputs"), [(0x20, 0x11)]);
+    assert_eq!(recorded(&io, "writes"), [(0x0fff, 0x11), (0x0ffe, 0x02)]);
+    assert_eq!(cpu.memory_view().get_index(0x0fff), 0x11);
+    assert_eq!(cpu.memory().length(), 0x10000);
+}
+
+#[wasm_bindgen_test]
+fn exceptions_stop_the_cpu() {
+    let mut cpu = WasmState8080::new(None).unwrap();
+    // IN 0x10; NOP
+    cpu.load(&[0xdb, 0x10, 0x00], 0).unwrap();
+    let io = Object::new();
+    Reflect::set(
+        &io,
+        &"input".into(),
+        &Function::new_with_args("port", "throw new Error('boom')"),
+    )
+    .unwrap();
+
+    assert!(cpu.run(100, Some(io.into())).is_err());
+    assert_eq!(cpu.pc(), 2);
+    assert!(cpu.load(&[0; 2], 0xffff).is_err());
+}
+
+#[wasm_bindgen_test]
+fn input_has_to_return_a_byte() {
+    let mut cpu = WasmState8080::new(None).unwrap();
+    // IN 0x10
+    cpu.load(&[0xdb, 0x10], 0).unwrap();
+
+    for body in ["return 256", "return -1", "return 1.5", "return '1'"].iter() {
+        let io = Object::new();
+        Reflect::set(&io, &"input".into(), &Function::new_with_args("port", body)).unwrap();
+        cpu.set_pc(0);
+        assert!(cpu.step(Some(io.into())).is_err(), "{}", body);
+    }
+}
+
+#[wasm_bindgen_test]
+fn save_states_restore() {
+    let mut cpu = WasmState8080::new(Some("8085".to_string())).unwrap();
+    cpu.set_a(0x42);
+    cpu.set_pc(0x100);
+    let saved = cpu.save_state();
+
+    let mut restored = WasmState8080::new(None).unwrap();
+    restored.load_state(&saved).unwrap();
+    assert_eq!(restored.variant(), "8085");
+    assert_eq!((restored.a(), restored.pc()), (0x42, 0x100));
+    assert!(restored.load_state(&saved[..10]).is_err());
+    assert_eq!(restored.disassemble(Some(0)), "Nop");
+}
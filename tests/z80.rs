@@ -0,0 +1,433 @@
+mod utils;
+
+use emu_8080::emulator::{DummyIOHandler, IOHandler, State8080};
+use emu_8080::z80::{InterruptMode, StateZ80};
+
+const S: u8 = 0x80;
+const Z: u8 = 0x40;
+const H: u8 = 0x10;
+const PV: u8 = 0x04;
+const N: u8 = 0x02;
+const C: u8 = 0x01;
+
+const ORIGIN: u16 = 0x0100;
+const STACK: u16 = 0x8000;
+
+fn new_state() -> StateZ80 {
+    let mut state = StateZ80::new();
+    state.core.sp = STACK;
+
+    state
+}
+
+fn loading(state: StateZ80, bytes: &[u8]) -> StateZ80 {
+    let mut state = state;
+    state.core.pc = ORIGIN;
+    state.core.memory[ORIGIN as usize..ORIGIN as usize + bytes.len()].copy_from_slice(bytes);
+
+    state
+}
+
+fn stepping(state: StateZ80) -> StateZ80 {
+    state.evaluating_next::<DummyIOHandler>(None)
+}
+
+fn executing(state: StateZ80, bytes: &[u8]) -> StateZ80 {
+    stepping(loading(state, bytes))
+}
+
+fn hl(state: &StateZ80) -> u16 {
+    state.core.hl().into()
+}
+
+#[test]
+fn run_cputest() {
+    let mut state: StateZ80 =
+        utils::create_state_with_rom("resources/cpu_tests/CPUTEST.COM").into();
+    let mut console = String::new();
+
+    while state.core.pc != 0 {
+        state = stepping(state);
+
+        if let Some(output) = utils::console_output(&state.core) {
+            print!("{}", output);
+            console.push_str(&output);
+        }
+    }
+
+    utils::assert_suite_passed(&console, "CPU TESTS OK", &["CPU FAILED", "ERROR"]);
+    assert!(console.contains("CPU IS Z80"), "Not detected as a Z80");
+}
+
+#[test]
+fn arithmetic_sets_overflow_and_subtract_flags() {
+    // ADD A,B: 0x7f + 1 overflows
+    let mut state = new_state();
+    state.core.a = 0x7f;
+    state.core.b = 0x01;
+    let state = executing(state, &[0x80]);
+    assert_eq!(state.core.a, 0x80);
+    assert_eq!(state.f() & (S | Z | H | PV | N | C), S | H | PV);
+
+    // NEG of 0x80 stays 0x80 and overflows
+    let state = executing(state, &[0xed, 0x44]);
+    assert_eq!(state.core.a, 0x80);
+    assert_eq!(state.f() & (S | Z | PV | N | C), S | PV | N | C);
+    assert_eq!(state.last_cycles(), 8);
+
+    // INC sets P/V on overflow instead of parity, DEC sets N
+    let mut state = state;
+    state.core.b = 0x7f;
+    let state = executing(state, &[0x04]);
+    assert_eq!(state.f() & (PV | N), PV);
+    let state = executing(state, &[0x05]);
+    assert_eq!(state.f() & (PV | N | H), PV | N | H);
+}
+
+#[test]
+fn daa_adjusts_after_subtraction() {
+    // 0x15 - 0x06 = 0x0f, adjusted to the BCD result 0x09
+    let mut state = new_state();
+    state.core.a = 0x15;
+    state.core.b = 0x06;
+    let state = executing(state, &[0x90]);
+    let state = executing(state, &[0x27]);
+
+    assert_eq!(state.core.a, 0x09);
+    assert_eq!(state.f() & (N | C), N);
+
+    // And after an addition like the 8080
+    let mut state = state;
+    state.core.a = 0x19;
+    state.core.b = 0x28;
+    let state = executing(state, &[0x80]);
+    let state = executing(state, &[0x27]);
+    assert_eq!(state.core.a, 0x47);
+}
+
+#[test]
+fn sixteen_bit_arithmetic_with_carry() {
+    // ADC HL,DE
+    let mut state = new_state();
+    state.core.h = 0x7f;
+    state.core.l = 0xff;
+    state.core.e = 0x01;
+    let state = executing(state, &[0xed, 0x5a]);
+    assert_eq!(hl(&state), 0x8000);
+    assert_eq!(state.f() & (S | Z | H | PV | N | C), S | H | PV);
+    assert_eq!(state.last_cycles(), 15);
+
+    // SBC HL,HL with carry clear is zero
+    let state = executing(state, &[0xed, 0x62]);
+    assert_eq!(hl(&state), 0);
+    assert_eq!(state.f() & (Z | N | C), Z | N);
+
+    // ADD HL,BC leaves S, Z and P/V alone
+    let mut state = state;
+    state.core.b = 0xff;
+    state.core.c = 0xff;
+    state.core.l = 0x01;
+    let state = executing(state, &[0x09]);
+    assert_eq!(hl(&state), 0);
+    assert_eq!(state.f() & (Z | H | N | C), Z | H | C);
+}
+
+#[test]
+fn exchanges_swap_alternate_registers() {
+    let mut state = new_state();
+    state.core.a = 0x12;
+    state.core.b = 0x34;
+    state.alternate.a = 0x56;
+    state.alternate.f = 0xff;
+    state.alternate.b = 0x78;
+
+    let state = executing(state, &[0x08]);
+    assert_eq!((state.core.a, state.f()), (0x56, 0xff));
+    assert_eq!(state.alternate.a, 0x12);
+
+    let state = executing(state, &[0xd9]);
+    assert_eq!((state.core.b, state.alternate.b), (0x78, 0x34));
+}
+
+#[test]
+fn index_registers_and_displacements() {
+    let mut state = new_state();
+    state.ix = 0x4010;
+    state.iy = 0x5000;
+    state.core.memory[0x400e] = 0x99;
+
+    // LD A,(IX-2)
+    let state = executing(state, &[0xdd, 0x7e, 0xfe]);
+    assert_eq!(state.core.a, 0x99);
+    assert_eq!(state.core.pc, ORIGIN + 3);
+    assert_eq!(state.last_cycles(), 19);
+
+    // LD (IY+5),0x42
+    let state = executing(state, &[0xfd, 0x36, 0x05, 0x42]);
+    assert_eq!(state.core.memory[0x5005], 0x42);
+    assert_eq!(state.last_cycles(), 19);
+
+    // LD H,(IX+0) loads the real H, LD IXH,0x20 the high byte of IX
+    let state = executing(state, &[0xdd, 0x66, 0x00]);
+    assert_eq!(state.ix, 0x4010);
+    let state = executing(state, &[0xdd, 0x26, 0x20]);
+    assert_eq!(state.ix, 0x2010);
+    assert_eq!(state.last_cycles(), 11);
+
+    // ADD A,IXL
+    let mut state = state;
+    state.core.a = 0x01;
+    let state = executing(state, &[0xdd, 0x85]);
+    assert_eq!(state.core.a, 0x11);
+
+    // LD IX,nn, PUSH IX and POP IY
+    let state = executing(state, &[0xdd, 0x21, 0x34, 0x12]);
+    assert_eq!(state.ix, 0x1234);
+    let state = executing(state, &[0xdd, 0xe5]);
+    assert_eq!(state.last_cycles(), 15);
+    let state = executing(state, &[0xfd, 0xe1]);
+    assert_eq!(state.iy, 0x1234);
+
+    // EX DE,HL ignores the prefix
+    let mut state = state;
+    state.core.d = 0xab;
+    let state = executing(state, &[0xdd, 0xeb]);
+    assert_eq!(state.core.h, 0xab);
+    assert_eq!(state.ix, 0x1234);
+}
+
+#[test]
+fn bit_operations() {
+    let mut state = new_state();
+    state.core.b = 0x80;
+
+    // BIT 7,B
+    let state = executing(state, &[0xcb, 0x78]);
+    assert_eq!(state.f() & (S | Z | H | N), S | H);
+    assert_eq!(state.last_cycles(), 8);
+
+    // BIT 0,B
+    let state = executing(state, &[0xcb, 0x40]);
+    assert_eq!(state.f() & (S | Z | PV | H), Z | PV | H);
+
+    // SRA B keeps the sign, SLL shifts in a one
+    let state = executing(state, &[0xcb, 0x28]);
+    assert_eq!(state.core.b, 0xc0);
+    let state = executing(state, &[0xcb, 0x30]);
+    assert_eq!(state.core.b, 0x81);
+    assert_eq!(state.f() & C, C);
+
+    // SET 3,(IX+1) also copies the result into C
+    let mut state = state;
+    state.ix = 0x4000;
+    let state = executing(state, &[0xdd, 0xcb, 0x01, 0xd9]);
+    assert_eq!(state.core.memory[0x4001], 0x08);
+    assert_eq!(state.core.c, 0x08);
+    assert_eq!(state.last_cycles(), 23);
+
+    // BIT 3,(IX+1)
+    let state = executing(state, &[0xdd, 0xcb, 0x01, 0x5e]);
+    assert_eq!(state.f() & Z, 0);
+    assert_eq!(state.last_cycles(), 20);
+
+    // RLD and RRD
+    let mut state = state;
+    state.core.h = 0x40;
+    state.core.l = 0x00;
+    state.core.a = 0x12;
+    state.core.memory[0x4000] = 0x34;
+    let state = executing(state, &[0xed, 0x6f]);
+    assert_eq!((state.core.a, state.core.memory[0x4000]), (0x13, 0x42));
+    let state = executing(state, &[0xed, 0x67]);
+    assert_eq!((state.core.a, state.core.memory[0x4000]), (0x12, 0x34));
+}
+
+#[test]
+fn relative_jumps_and_cycles() {
+    // JR -2 loops on itself
+    let state = executing(new_state(), &[0x18, 0xfe]);
+    assert_eq!(state.core.pc, ORIGIN);
+    assert_eq!(state.last_cycles(), 12);
+
+    // JR NZ not taken
+    let mut state = new_state();
+    state = state.setting_f(Z);
+    let state = executing(state, &[0x20, 0x10]);
+    assert_eq!(state.core.pc, ORIGIN + 2);
+    assert_eq!(state.last_cycles(), 7);
+
+    // DJNZ counts B down
+    let mut state = loading(new_state(), &[0x10, 0xfe]);
+    state.core.b = 3;
+    let state = stepping(state);
+    assert_eq!((state.core.pc, state.last_cycles()), (ORIGIN, 13));
+    let state = stepping(stepping(state));
+    assert_eq!((state.core.b, state.core.pc), (0, ORIGIN + 2));
+    assert_eq!(state.last_cycles(), 8);
+
+    // CALL PO taken, RET PE not taken
+    let state = executing(new_state(), &[0xe4, 0x00, 0x20]);
+    assert_eq!((state.core.pc, state.last_cycles()), (0x2000, 17));
+    let state = executing(state, &[0xe8]);
+    assert_eq!((state.core.pc, state.last_cycles()), (ORIGIN + 1, 5));
+}
+
+#[test]
+fn block_transfer_and_search() {
+    let mut state = new_state();
+    state.core.memory[0x4000..0x4003].copy_from_slice(&[1, 2, 3]);
+    state.core.h = 0x40;
+    state.core.d = 0x50;
+    state.core.c = 3;
+
+    // LDIR repeats by rewinding PC
+    let mut state = loading(state, &[0xed, 0xb0]);
+    for _ in 0..2 {
+        state = stepping(state);
+        assert_eq!((state.core.pc, state.last_cycles()), (ORIGIN, 21));
+    }
+    let state = stepping(state);
+    assert_eq!((state.core.pc, state.last_cycles()), (ORIGIN + 2, 16));
+    assert_eq!(&state.core.memory[0x5000..0x5003], &[1, 2, 3]);
+    assert_eq!((hl(&state), state.core.c), (0x4003, 0));
+    assert_eq!(state.f() & PV, 0);
+
+    // CPIR stops on the match with BC left over
+    let mut state = state;
+    state.core.h = 0x40;
+    state.core.l = 0x00;
+    state.core.c = 3;
+    state.core.a = 2;
+    let mut state = loading(state, &[0xed, 0xb1]);
+    state = stepping(stepping(state));
+    assert_eq!(state.core.pc, ORIGIN + 2);
+    assert_eq!((hl(&state), state.core.c), (0x4002, 1));
+    assert_eq!(state.f() & (Z | PV | N), Z | PV | N);
+}
+
+struct Ports {
+    input: u8,
+    written: Vec<(u8, u8)>,
+}
+
+impl IOHandler for Ports {
+    fn inp(&mut self, state: State8080, port: u8) -> State8080 {
+        state.setting_a(self.input.wrapping_add(port))
+    }
+
+    fn out(&mut self, state: State8080, port: u8) -> State8080 {
+        self.written.push((port, state.a));
+
+        state
+    }
+}
+
+#[test]
+fn port_io_through_the_io_handler() {
+    let mut ports = Ports {
+        input: 0x10,
+        written: Vec::new(),
+    };
+    let mut state = new_state();
+    state.core.a = 0x55;
+    state.core.c = 0x02;
+    state.core.d = 0x66;
+
+    // IN D,(C) leaves A alone and sets the flags
+    let state = loading(state, &[0xed, 0x50]).evaluating_next(Some(&mut ports));
+    assert_eq!((state.core.d, state.core.a), (0x12, 0x55));
+    assert_eq!(state.f() & (S | Z | PV), PV);
+
+    // OUT (C),D and OUT (n),A
+    let state = loading(state, &[0xed, 0x51]).evaluating_next(Some(&mut ports));
+    let state = loading(state, &[0xd3, 0x07]).evaluating_next(Some(&mut ports));
+    assert_eq!(ports.written, vec![(0x02, 0x12), (0x07, 0x55)]);
+    assert_eq!(state.core.a, 0x55);
+}
+
+#[test]
+fn interrupt_modes() {
+    // Mode 1 always goes to 0x38
+    let mut state = loading(new_state(), &[0x00]);
+    state.interrupt_mode = InterruptMode::Mode1;
+    state.core.interrupt_enabled = true;
+    let state = state.generating_interrupt(0xff);
+    assert_eq!((state.core.pc, state.last_cycles()), (0x38, 13));
+    assert!(!state.core.interrupt_enabled);
+
+    // Ignored while disabled
+    let state = loading(state, &[0x00]).generating_interrupt(0xff);
+    assert_eq!(state.core.pc, ORIGIN);
+
+    // IM 2 goes through the vector table at I * 256 + data
+    let mut state = executing(state, &[0xed, 0x5e]);
+    assert_eq!(state.interrupt_mode, InterruptMode::Mode2);
+    state.i = 0x40;
+    state.core.memory[0x4010] = 0x34;
+    state.core.memory[0x4011] = 0x12;
+    let state = executing(state, &[0xfb, 0x00]);
+
+    // EI holds off interrupts for one more instruction
+    let state = state.generating_interrupt(0x10);
+    assert_eq!(state.core.pc, ORIGIN + 1);
+    let state = stepping(state).generating_interrupt(0x10);
+    assert_eq!((state.core.pc, state.last_cycles()), (0x1234, 19));
+    assert_eq!(state.core.memory[state.core.sp as usize], 0x02);
+
+    // Mode 0 executes the RST on the bus
+    let mut state = executing(state, &[0xed, 0x46]);
+    state.core.interrupt_enabled = true;
+    let state = state.generating_interrupt(0xd7);
+    assert_eq!(state.core.pc, 0x10);
+}
+
+#[test]
+fn nmi_and_retn_restore_iff1() {
+    let mut state = loading(new_state(), &[0x00]);
+    state.core.interrupt_enabled = true;
+    state.iff2 = true;
+
+    let state = state.generating_nmi();
+    assert_eq!((state.core.pc, state.last_cycles()), (0x66, 11));
+    assert!(!state.core.interrupt_enabled);
+    assert!(state.iff2);
+
+    // LD A,I reports IFF2 in P/V
+    let state = executing(state, &[0xed, 0x57]);
+    assert_eq!(state.f() & PV, PV);
+
+    let state = executing(state, &[0xed, 0x45]);
+    assert_eq!(state.core.pc, ORIGIN);
+    assert!(state.core.interrupt_enabled);
+}
+
+#[test]
+fn halt_waits_for_an_interrupt() {
+    let mut state = loading(new_state(), &[0x76, 0x00]);
+    state.core.interrupt_enabled = true;
+    state.interrupt_mode = InterruptMode::Mode1;
+
+    let state = stepping(stepping(stepping(state)));
+    assert!(state.halted);
+    assert_eq!((state.core.pc, state.last_cycles()), (ORIGIN + 1, 4));
+
+    let state = state.generating_interrupt(0);
+    assert!(!state.halted);
+    assert_eq!(state.core.pc, 0x38);
+    assert_eq!(state.core.memory[STACK as usize - 2], 0x01);
+}
+
+#[test]
+fn refresh_register_counts_op_code_fetches() {
+    let mut state = new_state();
+    state.r = 0xff;
+
+    let state = executing(state, &[0x00]);
+    assert_eq!(state.r, 0x80);
+
+    // Prefixed instructions fetch two op codes
+    let state = executing(state, &[0xdd, 0x23]);
+    assert_eq!(state.r, 0x82);
+    let state = executing(state, &[0xed, 0x5f]);
+    assert_eq!(state.core.a, 0x84);
+}